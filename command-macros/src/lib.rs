@@ -1,6 +1,6 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, ItemFn};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, ItemFn, LitStr};
 
 #[proc_macro_attribute]
 pub fn command_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -24,3 +24,47 @@ pub fn command_handler(_attr: TokenStream, item: TokenStream) -> TokenStream {
     
     TokenStream::from(expanded)
 }
+
+#[proc_macro_attribute]
+pub fn autocomplete_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    keyed_handler(attr, item, "autocomplete", "register_autocomplete")
+}
+
+#[proc_macro_attribute]
+pub fn component_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    keyed_handler(attr, item, "component", "register_component")
+}
+
+#[proc_macro_attribute]
+pub fn modal_handler(attr: TokenStream, item: TokenStream) -> TokenStream {
+    keyed_handler(attr, item, "modal", "register_modal")
+}
+
+/// Registers a handler under the key given in the attribute, e.g. a command name for
+/// autocomplete or a custom ID prefix for components and modals.
+fn keyed_handler(
+    attr: TokenStream,
+    item: TokenStream,
+    kind: &str,
+    register_fn: &str,
+) -> TokenStream {
+    let key = parse_macro_input!(attr as LitStr);
+    let input_fn = parse_macro_input!(item as ItemFn);
+    let fn_name = &input_fn.sig.ident;
+    let ctor_name = format_ident!("__register_{}_{}", kind, fn_name);
+    let register_fn = format_ident!("{}", register_fn);
+
+    let expanded = quote! {
+        #input_fn
+
+        #[ctor::ctor]
+        fn #ctor_name() {
+            crate::controller::discord::interaction::#register_fn(
+                #key,
+                |data, app_state| Box::pin(#fn_name(data, app_state))
+            );
+        }
+    };
+
+    TokenStream::from(expanded)
+}
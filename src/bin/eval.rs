@@ -0,0 +1,627 @@
+//! Replays stored plan records against a candidate configuration, and writes a side-by-side
+//! report of the original and the new responses, optionally scored by an LLM judge.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_openai::types::{
+    ChatChoice, ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    CreateChatCompletionRequestArgs, FinishReason, ResponseFormat, ResponseFormatJsonSchema, Role,
+};
+use clap::{Parser, ValueEnum};
+use dashmap::DashMap;
+use serde::Deserialize;
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+use travel_agency::shared::structs::LLMClients;
+use travel_agency::shared::structs::agent::itinerary::Itinerary;
+use travel_agency::shared::structs::agent::record::{
+    Content, CostSummary, GenerationDump, GenerationStage, PlanRecord, PlanStatus,
+};
+use travel_agency::shared::structs::agent::{
+    Agent, Executor, OrchestrationPlan, Provider, TaskOutcome, Taskable,
+};
+use travel_agency::shared::structs::budget::PlanBudget;
+use travel_agency::shared::structs::config::{
+    AgentOutput, Configuration,
+    settings::{ApiKeys, SettingsLayer},
+};
+use travel_agency::shared::utility::build_one_shot_messages;
+use travel_agency::shared::utility::context_window::PromptFitter;
+use travel_agency::shared::utility::estimate::cost_estimate_format;
+use travel_agency::shared::utility::itinerary::render_itinerary;
+use travel_agency::shared::utility::llm::{GenerationLog, create_chat_completion};
+use travel_agency::shared::utility::synthesis::{
+    build_synthesis_request, fit_synthesis_results, render_synthesis_prompt,
+};
+use travel_agency::shared::utility::tools::{build_tool_turn_message, create_tool};
+use travel_agency::shared::{GEMINI_25_PRO, MAX_TOOL_RETRY_COUNT, TEMPERATURE_LOW};
+use uuid::Uuid;
+
+const JUDGE_SYSTEM_PROMPT: &str = "You are an impartial judge of travel plans. You will be given a user's request and two responses to it. Score each response from 1 to 10 for how well it fulfils the request, considering accuracy, completeness, practicality and how well it is organized. Judge the content, not the length.";
+
+#[derive(Parser, Debug)]
+#[command(about = "Replays stored plans against a candidate config and compares the results.")]
+struct Args {
+    /// Exported plan records, either as a JSON array or as one record per line.
+    records: PathBuf,
+    /// The candidate configuration whose prompts and prices are used.
+    #[arg(long)]
+    config: PathBuf,
+    #[arg(long, value_enum, default_value_t = Target::Synthesis)]
+    target: Target,
+    /// The agent to re-run when the target is `agent`, e.g. `food`. The tools it calls are
+    /// answered with the outputs recorded in the plan.
+    #[arg(long, value_parser = parse_agent)]
+    agent: Option<Agent>,
+    /// The model that synthesizes the plan. Agents use the panel and aggregation model of the
+    /// candidate config.
+    #[arg(long, default_value = GEMINI_25_PRO)]
+    model: String,
+    /// Scores the original and the candidate response with this model if present.
+    #[arg(long)]
+    judge_model: Option<String>,
+    /// Where the markdown report is written. Defaults to stdout.
+    #[arg(long)]
+    output: Option<PathBuf>,
+    /// Evaluates at most this many records.
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Keys not given here are read from the `[settings]` table of the candidate config.
+    #[command(flatten)]
+    api_keys: ApiKeys,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Synthesis,
+    Agent,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct Judgement {
+    original_score: f64,
+    candidate_score: f64,
+    reasoning: String,
+}
+
+#[derive(Debug)]
+struct Evaluation {
+    record_id: Uuid,
+    label: String,
+    original: String,
+    candidate: String,
+    judgement: Option<Judgement>,
+}
+
+struct Evaluator {
+    config: Configuration,
+    llm_clients: Arc<LLMClients>,
+    generation_log: GenerationLog,
+    agent_dumps: Vec<GenerationDump>,
+    model: String,
+    judge_model: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let subscriber = tracing_subscriber::FmtSubscriber::builder()
+        .with_max_level(tracing::Level::INFO)
+        .with_writer(std::io::stderr)
+        .finish();
+
+    if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
+        eprintln!("Initialization of tracing subscriber failed with error: {e}");
+    }
+
+    let args = Args::parse();
+
+    let config = Configuration::load_from_path(&args.config)?;
+
+    let agent = match (args.target, args.agent.clone()) {
+        (Target::Agent, None) => {
+            return Err(anyhow::anyhow!("The agent target requires --agent."));
+        }
+        (_, agent) => agent,
+    };
+    let api_keys = args
+        .api_keys
+        .clone()
+        .merge(SettingsLayer::from_config_file(&args.config)?.api_keys);
+    let mut records = load_records(&args.records)?;
+    if let Some(limit) = args.limit {
+        records.truncate(limit);
+    }
+
+    tracing::info!("Evaluating {} records.", records.len());
+
+    let mut evaluator = Evaluator {
+        llm_clients: Arc::new(LLMClients::new(
+            &config,
+            &api_keys,
+            args.config.parent().unwrap_or(Path::new(".")),
+        )),
+        config,
+        generation_log: GenerationLog::default(),
+        agent_dumps: vec![],
+        model: args.model.clone(),
+        judge_model: args.judge_model.clone(),
+    };
+
+    let mut evaluations = vec![];
+    for record in records.iter() {
+        let result = match agent {
+            Some(ref agent) if args.target == Target::Agent => {
+                evaluator.evaluate_agent(record, agent).await
+            }
+            _ => evaluator
+                .evaluate_synthesis(record)
+                .await
+                .map(|e| e.into_iter().collect::<Vec<_>>()),
+        };
+
+        match result {
+            Ok(mut e) => evaluations.append(&mut e),
+            Err(e) => tracing::error!("Failed to evaluate plan {}: {e:?}", record.id),
+        }
+    }
+
+    let report = evaluator.render_report(&args, &evaluations).await;
+
+    match args.output {
+        Some(ref path) => std::fs::write(path, report)?,
+        None => println!("{report}"),
+    }
+
+    Ok(())
+}
+
+impl Evaluator {
+    /// Re-runs the synthesis of a plan with the candidate prompt and model.
+    async fn evaluate_synthesis(&self, record: &PlanRecord) -> anyhow::Result<Option<Evaluation>> {
+        if record.status == PlanStatus::Cancelled {
+            return Ok(None);
+        }
+
+        // The synthesis prompt is always the last message sent by the user.
+        let Some(synthesis_index) = record.messages.iter().rposition(|m| m.role == Role::User)
+        else {
+            return Ok(None);
+        };
+
+        let Some(original) = record.messages[synthesis_index..]
+            .iter()
+            .find(|m| m.role == Role::Assistant)
+        else {
+            return Ok(None);
+        };
+
+        let original = match original.content {
+            Content::Dynamic(ref value) => render_itinerary(
+                &serde_json::from_value::<Itinerary>(value.clone())?,
+                &self.config.templates,
+                &record.language,
+            )?,
+            Content::Plain(ref s) => s.clone(),
+        };
+
+        let messages = record.messages[..synthesis_index]
+            .iter()
+            .map(|m| m.to_openai_message())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let fitter = PromptFitter::new(
+            self.config.context_window.clone(),
+            self.config.templates.clone(),
+            self.llm_clients.clone(),
+            self.generation_log.clone(),
+        );
+
+        let synthesis_prompt = &self
+            .config
+            .language(&record.language)
+            .prompts
+            .synthesis
+            .prompt;
+        let results = fit_synthesis_results(
+            record.contexts.clone(),
+            synthesis_prompt,
+            &record.messages[..synthesis_index],
+            &self.model,
+            &fitter,
+        )
+        .await;
+
+        let synthesis_prompt =
+            render_synthesis_prompt(&self.config.templates, &record.language, results)?;
+
+        let request = build_synthesis_request(&self.model, messages, &synthesis_prompt)?;
+        let provider = Provider::for_model(&self.model);
+
+        let response = create_chat_completion(
+            &self.llm_clients.client(provider),
+            provider,
+            request,
+            GenerationStage::Synthesis,
+            &self.llm_clients,
+            &self.generation_log,
+        )
+        .await?;
+
+        let content = response.choices[0]
+            .message
+            .content
+            .clone()
+            .unwrap_or_default();
+        let candidate = render_itinerary(
+            &serde_json::from_str::<Itinerary>(&content)?,
+            &self.config.templates,
+            &record.language,
+        )?;
+
+        let judgement = self.judge(record, &original, &candidate).await;
+
+        Ok(Some(Evaluation {
+            record_id: record.id,
+            label: "Synthesis".into(),
+            original,
+            candidate,
+            judgement,
+        }))
+    }
+
+    /// Re-runs every task of the agent in a plan with the candidate prompts, using the stored
+    /// results of its dependencies as the context.
+    async fn evaluate_agent(
+        &mut self,
+        record: &PlanRecord,
+        agent: &Agent,
+    ) -> anyhow::Result<Vec<Evaluation>> {
+        let Some(orchestration) = record.messages.iter().find_map(|m| match m.content {
+            Content::Dynamic(ref value) if m.role == Role::Assistant => {
+                serde_json::from_value::<OrchestrationPlan>(value.clone()).ok()
+            }
+            _ => None,
+        }) else {
+            return Ok(vec![]);
+        };
+
+        let budget = Arc::new(PlanBudget::new(
+            None,
+            &self.config.budget,
+            self.config.prices.clone(),
+        ));
+
+        let agent_config = self.config.agent(agent)?;
+        let mut evaluations = vec![];
+
+        for task in orchestration.tasks.iter().filter(|t| &t.agent == agent) {
+            let Some(original) = record.contexts.iter().find(|c| c.task_id == task.task_id) else {
+                continue;
+            };
+
+            // Dependencies without a stored context had failed, as they would in a live plan.
+            let outcomes = task
+                .dependencies
+                .iter()
+                .map(|dependency| {
+                    let outcome = record
+                        .contexts
+                        .iter()
+                        .find(|c| &c.task_id == dependency)
+                        .map_or(TaskOutcome::Failed, |c| TaskOutcome::Completed(c.clone()));
+                    (dependency.clone(), outcome)
+                })
+                .collect::<DashMap<_, _>>();
+
+            let mut executor = Executor {
+                task_id: task.task_id.clone(),
+                language: record.language.clone(),
+                instruction: task.instruction.clone(),
+                agent_type: agent.clone(),
+                agent_name: agent_config.name.clone(),
+                dependencies: task.dependencies.clone(),
+                templates: self.config.templates.clone(),
+                context: "".into(),
+                results: vec![],
+                tools: agent_config
+                    .tools
+                    .iter()
+                    .map(|tool| create_tool(tool))
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+                panel: agent_config.panel.clone(),
+                aggregation_model: agent_config.aggregation_model.clone(),
+                response_format: match agent_config.output {
+                    AgentOutput::Text => None,
+                    AgentOutput::CostEstimate => Some(cost_estimate_format(
+                        &self.config.language(&record.language).currency,
+                    )),
+                },
+                optional: false,
+                budget: budget.clone(),
+                ranking: self.config.ranking.clone(),
+                context_window: self.config.context_window.clone(),
+            };
+
+            let result = executor
+                .execute(
+                    Arc::new(outcomes),
+                    self.llm_clients.clone(),
+                    CancellationToken::new(),
+                )
+                .await;
+
+            let result = match result {
+                Ok((choice, dumps)) => {
+                    let result = self
+                        .replay_tool_calls(record, &executor, choice, &dumps)
+                        .await;
+                    self.agent_dumps.append(&mut dumps.snapshot().await);
+                    result
+                }
+                Err(e) => Err(e),
+            };
+
+            let candidate = match result {
+                Ok(candidate) => candidate,
+                Err(e) => {
+                    tracing::error!("Failed to re-run task {}: {e:?}", task.task_id);
+                    continue;
+                }
+            };
+
+            let judgement = self.judge(record, &original.content, &candidate).await;
+
+            evaluations.push(Evaluation {
+                record_id: record.id,
+                label: format!("{} ({})", task.task_id, agent),
+                original: original.content.clone(),
+                candidate,
+                judgement,
+            });
+        }
+
+        Ok(evaluations)
+    }
+
+    /// Answers the tool calls of a re-run task with the outputs the tools gave in the plan until
+    /// the agent responds, so that both runs see the same data. A call that wasn't made in the
+    /// plan gets an output of the same tool if there is one.
+    async fn replay_tool_calls(
+        &self,
+        record: &PlanRecord,
+        executor: &Executor,
+        mut choice: ChatChoice,
+        generation_log: &GenerationLog,
+    ) -> anyhow::Result<String> {
+        let mut tool_outputs = record
+            .tool_outputs
+            .iter()
+            .filter(|output| output.task_id == executor.task_id)
+            .collect::<Vec<_>>();
+
+        for retry_count in 0..MAX_TOOL_RETRY_COUNT {
+            let tool_call = match choice.finish_reason {
+                Some(FinishReason::ToolCalls) => choice
+                    .message
+                    .tool_calls
+                    .as_ref()
+                    .and_then(|v| v.first().cloned())
+                    .ok_or(anyhow::anyhow!(
+                        "The tool call is missing from the response."
+                    ))?,
+                _ => return Ok(choice.message.content.unwrap_or_default()),
+            };
+
+            let index = tool_outputs
+                .iter()
+                .position(|output| {
+                    output.tool == tool_call.function.name
+                        && output.arguments == tool_call.function.arguments
+                })
+                .or_else(|| {
+                    tool_outputs
+                        .iter()
+                        .position(|output| output.tool == tool_call.function.name)
+                })
+                .ok_or(anyhow::anyhow!(
+                    "No output of {} was recorded for task {}.",
+                    tool_call.function.name,
+                    executor.task_id
+                ))?;
+            let tool_output = tool_outputs.remove(index);
+
+            let agent_prompt = executor.agent_prompt(retry_count)?;
+            let user_prompt = executor.user_prompt(&agent_prompt)?;
+            let mut message_histories =
+                build_one_shot_messages(&executor.system_prompt()?, user_prompt.trim())?;
+            message_histories.push(ChatCompletionRequestMessage::Assistant(
+                ChatCompletionRequestAssistantMessageArgs::default()
+                    .content(choice.message.content.clone().unwrap_or_default())
+                    .tool_calls(vec![tool_call.clone()])
+                    .build()?,
+            ));
+
+            choice = build_tool_turn_message(
+                &mut message_histories,
+                tool_call.id.clone(),
+                tool_output.output.clone(),
+                &executor.tools,
+                &executor.aggregation_model,
+                executor.response_format.clone(),
+                self.llm_clients.clone(),
+                generation_log,
+            )
+            .await?;
+        }
+
+        Err(anyhow::anyhow!(
+            "The agent kept calling tools after {MAX_TOOL_RETRY_COUNT} turns."
+        ))
+    }
+
+    /// Asks the judge model to score both responses. Returns `None` if there is no judge or it
+    /// fails to answer.
+    async fn judge(
+        &self,
+        record: &PlanRecord,
+        original: &str,
+        candidate: &str,
+    ) -> Option<Judgement> {
+        let judge_model = self.judge_model.as_ref()?;
+
+        let user_request = record
+            .messages
+            .iter()
+            .find(|m| m.role == Role::User)
+            .and_then(|m| m.content.extract_content().ok())
+            .unwrap_or_default();
+
+        let user_prompt = format!(
+            "## Request\n{user_request}\n\n## Original response\n{original}\n\n## Candidate response\n{candidate}"
+        );
+
+        let result = async {
+            let request = CreateChatCompletionRequestArgs::default()
+                .model(judge_model)
+                .temperature(TEMPERATURE_LOW)
+                .messages(build_one_shot_messages(JUDGE_SYSTEM_PROMPT, &user_prompt)?)
+                .response_format(ResponseFormat::JsonSchema {
+                    json_schema: ResponseFormatJsonSchema {
+                        description: Some("Score the original and the candidate response.".into()),
+                        name: "judge_responses".into(),
+                        schema: Some(json!({
+                            "type": "object",
+                            "properties": {
+                                "original_score": {
+                                    "type": "number",
+                                    "description": "The score of the original response from 1 to 10."
+                                },
+                                "candidate_score": {
+                                    "type": "number",
+                                    "description": "The score of the candidate response from 1 to 10."
+                                },
+                                "reasoning": {
+                                    "type": "string",
+                                    "description": "A short explanation of the scores."
+                                }
+                            },
+                            "required": ["original_score", "candidate_score", "reasoning"],
+                            "additionalProperties": false
+                        })),
+                        strict: Some(true),
+                    },
+                })
+                .build()?;
+
+            let provider = Provider::for_model(judge_model);
+            let response = create_chat_completion(
+                &self.llm_clients.client(provider),
+                provider,
+                request,
+                GenerationStage::Judgement,
+                &self.llm_clients,
+                &self.generation_log,
+            )
+            .await?;
+
+            let content = response.choices[0]
+                .message
+                .content
+                .clone()
+                .unwrap_or_default();
+
+            anyhow::Ok(serde_json::from_str::<Judgement>(&content)?)
+        }
+        .await;
+
+        match result {
+            Ok(judgement) => Some(judgement),
+            Err(e) => {
+                tracing::error!("Failed to judge plan {}: {e:?}", record.id);
+                None
+            }
+        }
+    }
+
+    async fn render_report(&self, args: &Args, evaluations: &[Evaluation]) -> String {
+        let mut dumps = self.generation_log.snapshot().await;
+        dumps.extend(self.agent_dumps.iter().cloned());
+        let cost = CostSummary::new(&dumps, &self.config.prices);
+
+        let target = match (args.target, &args.agent) {
+            (Target::Agent, Some(agent)) => format!("{agent} agent"),
+            _ => format!("synthesis with `{}`", args.model),
+        };
+
+        let mut report = format!(
+            "# Evaluation of {target}\n\n- Config: `{}`\n- Evaluations: {}\n- Cost: ${:.4} ({} calls, {} failed)\n",
+            args.config.display(),
+            evaluations.len(),
+            cost.cost,
+            cost.calls,
+            cost.failed_calls
+        );
+
+        let judgements = evaluations
+            .iter()
+            .filter_map(|e| e.judgement.as_ref())
+            .collect::<Vec<_>>();
+
+        if !judgements.is_empty() {
+            let count = judgements.len() as f64;
+            let original = judgements.iter().map(|j| j.original_score).sum::<f64>() / count;
+            let candidate = judgements.iter().map(|j| j.candidate_score).sum::<f64>() / count;
+            let wins = judgements
+                .iter()
+                .filter(|j| j.candidate_score > j.original_score)
+                .count();
+
+            report.push_str(&format!(
+                "- Average score: {original:.2} (original) vs. {candidate:.2} (candidate)\n- Candidate wins: {wins}/{}\n",
+                judgements.len()
+            ));
+        }
+
+        for evaluation in evaluations.iter() {
+            report.push_str(&format!(
+                "\n## {} · {}\n\n| Original | Candidate |\n| --- | --- |\n| {} | {} |\n",
+                evaluation.record_id,
+                evaluation.label,
+                escape_cell(&evaluation.original),
+                escape_cell(&evaluation.candidate)
+            ));
+
+            if let Some(ref judgement) = evaluation.judgement {
+                report.push_str(&format!(
+                    "\n**Scores:** {} (original) vs. {} (candidate). {}\n",
+                    judgement.original_score, judgement.candidate_score, judgement.reasoning
+                ));
+            }
+        }
+
+        report
+    }
+}
+
+fn load_records(path: &std::path::Path) -> anyhow::Result<Vec<PlanRecord>> {
+    let raw_records = std::fs::read_to_string(path)?;
+
+    if raw_records.trim_start().starts_with('[') {
+        return Ok(serde_json::from_str::<Vec<PlanRecord>>(&raw_records)?);
+    }
+
+    raw_records
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str::<PlanRecord>(line)?))
+        .collect()
+}
+
+fn parse_agent(value: &str) -> anyhow::Result<Agent> {
+    Ok(Agent::new(value))
+}
+
+fn escape_cell(content: &str) -> String {
+    content.replace('|', "\\|").replace('\n', "<br>")
+}
@@ -0,0 +1,308 @@
+use command_macros::{component_handler, modal_handler};
+use minijinja::context;
+use serenity::all::{
+    ActionRowComponent, ButtonStyle, ChannelId, ComponentInteraction, CreateActionRow,
+    CreateButton, CreateEmbed, CreateInputText, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateModal, EditMessage, InputTextStyle,
+    ModalInteraction, UserId,
+};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::shared::EMBED_COLOR;
+use crate::shared::structs::AppState;
+use crate::shared::structs::agent::{Language, OrchestrationPlan};
+use crate::shared::structs::config::Configuration;
+use crate::shared::structs::discord::approval::{ApprovalDecision, PendingApproval};
+
+const APPROVAL_PREFIX: &str = "plan_approval";
+const REVISION_PREFIX: &str = "plan_revision";
+const REVISION_INPUT_ID: &str = "revision";
+
+const MAX_EMBED_FIELDS: usize = 25;
+const MAX_FIELD_NAME_LENGTH: usize = 256;
+const MAX_FIELD_VALUE_LENGTH: usize = 1024;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+
+/// Posts the orchestration plan to the discussion thread and waits until the requesting user
+/// approves, revises or cancels it. Timing out counts as a cancellation.
+pub async fn request_approval(
+    orchestration: &OrchestrationPlan,
+    plan_id: Uuid,
+    user_id: UserId,
+    thread_id: ChannelId,
+    language: &Language,
+    app_state: &AppState,
+) -> anyhow::Result<ApprovalDecision> {
+    let config = app_state.config();
+    let message_args = CreateMessage::new()
+        .embed(build_plan_embed(orchestration, language, &config)?)
+        .components(vec![build_approval_buttons(plan_id, language, &config)?]);
+
+    let (sender, receiver) = oneshot::channel();
+    app_state
+        .pending_approvals
+        .insert(plan_id, PendingApproval { user_id, sender });
+
+    let message = match app_state
+        .http
+        .send_message(thread_id, vec![], &message_args)
+        .await
+    {
+        Ok(message) => message,
+        Err(e) => {
+            app_state.pending_approvals.remove(&plan_id);
+            let error_msg = format!("Failed to send the plan for approval: {e:?}");
+            tracing::error!("{}", &error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        }
+    };
+
+    let timeout = std::time::Duration::from_secs(app_state.config().plan_approval_timeout);
+
+    match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(decision)) => Ok(decision),
+        Ok(Err(_)) => Ok(ApprovalDecision::Cancel),
+        Err(_) => {
+            app_state.pending_approvals.remove(&plan_id);
+            tracing::info!("Approval for plan {plan_id} timed out.");
+
+            let timed_out = config
+                .templates
+                .ui(language, "approval_timed_out", context! {})?;
+            let edit_message_args = EditMessage::new().content(timed_out).components(vec![]);
+
+            app_state
+                .http
+                .edit_message(thread_id, message.id, &edit_message_args, vec![])
+                .await?;
+
+            Ok(ApprovalDecision::Cancel)
+        }
+    }
+}
+
+#[component_handler("plan_approval")]
+pub async fn plan_approval(
+    interaction: ComponentInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let mut arguments = interaction.data.custom_id.split(':').skip(1);
+    let action = arguments.next().unwrap_or_default();
+    let plan_id = arguments.next().unwrap_or_default().parse::<Uuid>()?;
+
+    let config = app_state.config();
+    let language = config.resolve_language(&Language::new(&interaction.locale));
+
+    if let Some(response) =
+        reject_if_not_requester(plan_id, interaction.user.id, &language, &app_state)?
+    {
+        return Ok(response);
+    }
+
+    let (decision, status) = match action {
+        "approve" => (ApprovalDecision::Approve, "plan_approved"),
+        "cancel" => (ApprovalDecision::Cancel, "plan_rejected"),
+        "edit" => {
+            return Ok(CreateInteractionResponse::Modal(build_revision_modal(
+                plan_id, &language, &config,
+            )?));
+        }
+        _ => return Err(anyhow::anyhow!("Unknown approval action: {action}")),
+    };
+
+    let status = config.templates.ui(&language, status, context! {})?;
+
+    resolve_approval(plan_id, decision, &app_state);
+
+    Ok(CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .content(status)
+            .components(vec![]),
+    ))
+}
+
+#[modal_handler("plan_revision")]
+pub async fn plan_revision(
+    interaction: ModalInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let plan_id = interaction
+        .data
+        .custom_id
+        .split(':')
+        .nth(1)
+        .unwrap_or_default()
+        .parse::<Uuid>()?;
+
+    let config = app_state.config();
+    let language = config.resolve_language(&Language::new(&interaction.locale));
+
+    if let Some(response) =
+        reject_if_not_requester(plan_id, interaction.user.id, &language, &app_state)?
+    {
+        return Ok(response);
+    }
+
+    let revision = interaction
+        .data
+        .components
+        .iter()
+        .flat_map(|row| row.components.iter())
+        .find_map(|component| match component {
+            ActionRowComponent::InputText(input) if input.custom_id == REVISION_INPUT_ID => {
+                input.value.clone()
+            }
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    let revising = config
+        .templates
+        .ui(&language, "plan_revising", context! {})?;
+
+    resolve_approval(plan_id, ApprovalDecision::Revise(revision), &app_state);
+
+    Ok(CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .content(revising)
+            .components(vec![]),
+    ))
+}
+
+fn reject_if_not_requester(
+    plan_id: Uuid,
+    user_id: UserId,
+    language: &Language,
+    app_state: &AppState,
+) -> anyhow::Result<Option<CreateInteractionResponse>> {
+    let key = match app_state.pending_approvals.get(&plan_id) {
+        None => "approval_not_pending",
+        Some(pending) if pending.user_id != user_id => "approval_not_requester",
+        Some(_) => return Ok(None),
+    };
+
+    let content = app_state
+        .config()
+        .templates
+        .ui(language, key, context! {})?;
+
+    Ok(Some(CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    )))
+}
+
+fn resolve_approval(plan_id: Uuid, decision: ApprovalDecision, app_state: &AppState) {
+    if let Some((_, pending)) = app_state.pending_approvals.remove(&plan_id)
+        && pending.sender.send(decision).is_err()
+    {
+        tracing::warn!("Plan {plan_id} stopped waiting for approval before a decision was made.");
+    }
+}
+
+fn build_plan_embed(
+    orchestration: &OrchestrationPlan,
+    language: &Language,
+    config: &Configuration,
+) -> anyhow::Result<CreateEmbed> {
+    let templates = &config.templates;
+
+    let fields = orchestration
+        .tasks
+        .iter()
+        .take(MAX_EMBED_FIELDS)
+        .map(|task| {
+            let dependencies = if task.dependencies.is_empty() {
+                "-".to_string()
+            } else {
+                task.dependencies.join(", ")
+            };
+
+            let value = format!(
+                "{}\n{}",
+                truncate(&task.instruction, MAX_FIELD_VALUE_LENGTH - 64),
+                templates.ui(language, "approval_dependencies", context! { dependencies },)?
+            );
+
+            let agent_name = config
+                .agents
+                .get(&task.agent)
+                .map_or(task.agent.key(), |agent_config| agent_config.name.as_str());
+            let name = templates.ui(
+                language,
+                "approval_task",
+                context! {
+                    task => task.task_id,
+                    agent => agent_name,
+                    optional => task.optional,
+                },
+            )?;
+
+            anyhow::Ok((
+                truncate(&name, MAX_FIELD_NAME_LENGTH),
+                truncate(&value, MAX_FIELD_VALUE_LENGTH),
+                false,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(CreateEmbed::new()
+        .color(EMBED_COLOR)
+        .title(templates.ui(language, "approval_title", context! {})?)
+        .description(truncate(&orchestration.analysis, MAX_DESCRIPTION_LENGTH))
+        .fields(fields))
+}
+
+fn build_approval_buttons(
+    plan_id: Uuid,
+    language: &Language,
+    config: &Configuration,
+) -> anyhow::Result<CreateActionRow> {
+    let label = |key| config.templates.ui(language, key, context! {});
+
+    Ok(CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{APPROVAL_PREFIX}:approve:{plan_id}"))
+            .label(label("approve_button")?)
+            .style(ButtonStyle::Success),
+        CreateButton::new(format!("{APPROVAL_PREFIX}:edit:{plan_id}"))
+            .label(label("edit_button")?)
+            .style(ButtonStyle::Secondary),
+        CreateButton::new(format!("{APPROVAL_PREFIX}:cancel:{plan_id}"))
+            .label(label("cancel_button")?)
+            .style(ButtonStyle::Danger),
+    ]))
+}
+
+fn build_revision_modal(
+    plan_id: Uuid,
+    language: &Language,
+    config: &Configuration,
+) -> anyhow::Result<CreateModal> {
+    let text = |key| config.templates.ui(language, key, context! {});
+
+    Ok(CreateModal::new(
+        format!("{REVISION_PREFIX}:{plan_id}"),
+        text("revision_title")?,
+    )
+    .components(vec![CreateActionRow::InputText(
+        CreateInputText::new(
+            InputTextStyle::Paragraph,
+            text("revision_label")?,
+            REVISION_INPUT_ID,
+        )
+        .placeholder(text("revision_placeholder")?)
+        .required(true),
+    )]))
+}
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() <= max_length {
+        text.to_string()
+    } else {
+        let mut truncated = text.chars().take(max_length - 1).collect::<String>();
+        truncated.push('…');
+        truncated
+    }
+}
@@ -0,0 +1,92 @@
+use command_macros::{command_handler, component_handler};
+use minijinja::context;
+use serenity::all::{
+    ChannelId, CommandInteraction, ComponentInteraction, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditInteractionResponse, UserId,
+};
+
+use crate::shared::structs::AppState;
+use crate::shared::structs::agent::Language;
+
+pub const CANCEL_BUTTON_ID: &str = "plan_cancel";
+
+enum CancelOutcome {
+    Cancelled(usize),
+    NotRequester,
+    NothingRunning,
+}
+
+#[command_handler]
+pub async fn cancel(interaction: CommandInteraction, app_state: AppState) -> anyhow::Result<()> {
+    let config = app_state.config();
+    let language = config.resolve_language(&Language::new(&interaction.locale));
+
+    let (key, count) = match cancel_plans(interaction.channel_id, interaction.user.id, &app_state) {
+        CancelOutcome::Cancelled(1) => ("cancelling_plan", 1),
+        CancelOutcome::Cancelled(count) => ("cancelling_plans", count),
+        CancelOutcome::NotRequester => ("cancel_not_requester", 0),
+        CancelOutcome::NothingRunning => ("no_plans_running", 0),
+    };
+    let content = config.templates.ui(&language, key, context! { count })?;
+
+    let edit_content = EditInteractionResponse::new().content(content);
+
+    app_state
+        .http
+        .edit_original_interaction_response(&interaction.token, &edit_content, Vec::new())
+        .await?;
+
+    Ok(())
+}
+
+#[component_handler("plan_cancel")]
+pub async fn plan_cancel(
+    interaction: ComponentInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let key = match cancel_plans(interaction.channel_id, interaction.user.id, &app_state) {
+        CancelOutcome::Cancelled(_) => "cancelling_plan",
+        CancelOutcome::NotRequester => "cancel_not_requester",
+        CancelOutcome::NothingRunning => "plan_not_running",
+    };
+
+    let config = app_state.config();
+    let language = config.resolve_language(&Language::new(&interaction.locale));
+    let content = config.templates.ui(&language, key, context! {})?;
+
+    Ok(CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    ))
+}
+
+/// Cancels the plan running in the given thread, or every plan of the user when the command is
+/// used outside of a plan's thread.
+fn cancel_plans(channel_id: ChannelId, user_id: UserId, app_state: &AppState) -> CancelOutcome {
+    if let Some(running_plan) = app_state.running_plans.get(&channel_id) {
+        if running_plan.user_id != user_id {
+            return CancelOutcome::NotRequester;
+        }
+
+        tracing::info!("Cancelling plan {} on request.", running_plan.plan_id);
+        running_plan.cancellation_token.cancel();
+        return CancelOutcome::Cancelled(1);
+    }
+
+    let cancelled = app_state
+        .running_plans
+        .iter()
+        .filter(|entry| entry.user_id == user_id)
+        .map(|entry| {
+            tracing::info!("Cancelling plan {} on request.", entry.plan_id);
+            entry.cancellation_token.cancel();
+        })
+        .count();
+
+    if cancelled > 0 {
+        CancelOutcome::Cancelled(cancelled)
+    } else {
+        CancelOutcome::NothingRunning
+    }
+}
@@ -6,7 +6,8 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use serenity::all::{
-    CommandInteraction, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CommandInteraction, ComponentInteraction, CreateAutocompleteResponse,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Interaction, ModalInteraction,
 };
 use std::collections::HashMap;
 use std::future::Future;
@@ -19,8 +20,29 @@ use crate::shared::structs::discord::interaction::{InteractionRequest, Interacti
 type CommandHandler =
     fn(CommandInteraction, AppState) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
 
+type AutocompleteHandler =
+    fn(
+        CommandInteraction,
+        AppState,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<CreateAutocompleteResponse>> + Send>>;
+
+type ComponentHandler =
+    fn(
+        ComponentInteraction,
+        AppState,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<CreateInteractionResponse>> + Send>>;
+
+type ModalHandler =
+    fn(
+        ModalInteraction,
+        AppState,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<CreateInteractionResponse>> + Send>>;
+
 lazy_static::lazy_static! {
     pub static ref COMMAND_REGISTRY: Mutex<HashMap<String, CommandHandler>> = Mutex::new(HashMap::new());
+    pub static ref AUTOCOMPLETE_REGISTRY: Mutex<HashMap<String, AutocompleteHandler>> = Mutex::new(HashMap::new());
+    pub static ref COMPONENT_REGISTRY: Mutex<HashMap<String, ComponentHandler>> = Mutex::new(HashMap::new());
+    pub static ref MODAL_REGISTRY: Mutex<HashMap<String, ModalHandler>> = Mutex::new(HashMap::new());
 }
 
 pub fn register_command(name: &str, handler: CommandHandler) {
@@ -29,6 +51,24 @@ pub fn register_command(name: &str, handler: CommandHandler) {
         .insert(name.to_string(), handler);
 }
 
+pub fn register_autocomplete(name: &str, handler: AutocompleteHandler) {
+    AUTOCOMPLETE_REGISTRY
+        .blocking_lock()
+        .insert(name.to_string(), handler);
+}
+
+pub fn register_component(custom_id_prefix: &str, handler: ComponentHandler) {
+    COMPONENT_REGISTRY
+        .blocking_lock()
+        .insert(custom_id_prefix.to_string(), handler);
+}
+
+pub fn register_modal(custom_id_prefix: &str, handler: ModalHandler) {
+    MODAL_REGISTRY
+        .blocking_lock()
+        .insert(custom_id_prefix.to_string(), handler);
+}
+
 macro_rules! call_command {
     ($command_name:expr, $data:expr, $app_state:expr) => {{
         let registry = COMMAND_REGISTRY.lock().await;
@@ -43,8 +83,8 @@ macro_rules! call_command {
 pub async fn handle_interaction(State(app_state): State<AppState>, request: Bytes) -> Response {
     let bytes = request.to_vec();
 
-    match serde_json::from_slice::<CommandInteraction>(&bytes) {
-        Ok(command_interaction) => {
+    match serde_json::from_slice::<Interaction>(&bytes) {
+        Ok(Interaction::Command(command_interaction)) => {
             tokio::spawn(async move {
                 if let Err(e) = handle_command_interaction(command_interaction, app_state).await {
                     let error_msg = format!("Error when handling command interaction: {e:?}");
@@ -57,7 +97,45 @@ pub async fn handle_interaction(State(app_state): State<AppState>, request: Byte
 
             (StatusCode::OK, Json(response)).into_response()
         }
-        Err(_) => match serde_json::from_slice::<InteractionRequest>(&bytes) {
+        Ok(Interaction::Autocomplete(autocomplete_interaction)) => {
+            let response =
+                match handle_autocomplete_interaction(autocomplete_interaction, app_state).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        let error_msg =
+                            format!("Error when handling autocomplete interaction: {e:?}");
+                        tracing::error!("{}", error_msg);
+                        CreateAutocompleteResponse::new()
+                    }
+                };
+
+            (
+                StatusCode::OK,
+                Json(CreateInteractionResponse::Autocomplete(response)),
+            )
+                .into_response()
+        }
+        Ok(Interaction::Component(component_interaction)) => {
+            match handle_component_interaction(component_interaction, app_state).await {
+                Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+                Err(e) => {
+                    let error_msg = format!("Error when handling component interaction: {e:?}");
+                    tracing::error!("{}", error_msg);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        Ok(Interaction::Modal(modal_interaction)) => {
+            match handle_modal_interaction(modal_interaction, app_state).await {
+                Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+                Err(e) => {
+                    let error_msg = format!("Error when handling modal interaction: {e:?}");
+                    tracing::error!("{}", error_msg);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        _ => match serde_json::from_slice::<InteractionRequest>(&bytes) {
             Ok(ping_request) => {
                 if ping_request.r#type == 1 {
                     (StatusCode::OK, Json(InteractionResponse { r#type: 1 })).into_response()
@@ -83,3 +161,53 @@ async fn handle_command_interaction(
 
     Ok(())
 }
+
+async fn handle_autocomplete_interaction(
+    interaction: CommandInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateAutocompleteResponse> {
+    let command_name = interaction.data.name.clone();
+    let registry = AUTOCOMPLETE_REGISTRY.lock().await;
+
+    if let Some(handler) = registry.get(command_name.as_str()) {
+        handler(interaction, app_state).await
+    } else {
+        Err(anyhow::anyhow!(
+            "Unknown command for autocomplete: {}",
+            command_name
+        ))
+    }
+}
+
+async fn handle_component_interaction(
+    interaction: ComponentInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let prefix = custom_id_prefix(&interaction.data.custom_id).to_string();
+    let registry = COMPONENT_REGISTRY.lock().await;
+
+    if let Some(handler) = registry.get(prefix.as_str()) {
+        handler(interaction, app_state).await
+    } else {
+        Err(anyhow::anyhow!("Unknown component: {}", prefix))
+    }
+}
+
+async fn handle_modal_interaction(
+    interaction: ModalInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateInteractionResponse> {
+    let prefix = custom_id_prefix(&interaction.data.custom_id).to_string();
+    let registry = MODAL_REGISTRY.lock().await;
+
+    if let Some(handler) = registry.get(prefix.as_str()) {
+        handler(interaction, app_state).await
+    } else {
+        Err(anyhow::anyhow!("Unknown modal: {}", prefix))
+    }
+}
+
+/// Custom IDs are formatted as `prefix:arguments...`, where the prefix selects the handler.
+fn custom_id_prefix(custom_id: &str) -> &str {
+    custom_id.split(':').next().unwrap_or_default()
+}
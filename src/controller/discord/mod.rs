@@ -1,3 +1,5 @@
+pub mod approval;
+pub mod cancel;
 pub mod interaction;
 pub mod ping;
 pub mod plan;
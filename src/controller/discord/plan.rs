@@ -1,75 +1,492 @@
 use async_openai::types::{
-    ChatChoice, ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessageArgs,
-    ChatCompletionRequestMessage, ChatCompletionRequestToolMessageArgs,
-    ChatCompletionRequestToolMessageContent, ChatCompletionRequestUserMessageArgs,
-    ChatCompletionTool, ChatCompletionToolArgs, ChatCompletionToolChoiceOption,
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestUserMessageArgs, ChatCompletionToolArgs, ChatCompletionToolChoiceOption,
     ChatCompletionToolType, CreateChatCompletionRequestArgs, FinishReason, FunctionObjectArgs,
     ResponseFormat, ResponseFormatJsonSchema, Role,
 };
-use command_macros::command_handler;
+use command_macros::{autocomplete_handler, command_handler};
 use dashmap::DashMap;
+use minijinja::context;
 use serde_json::json;
 use serenity::all::{
-    ChannelId, CommandInteraction, CreateEmbed, CreateEmbedAuthor, CreateMessage, CreateThread,
-    EditInteractionResponse, EditMessage, GuildChannel, Message,
+    ButtonStyle, ChannelId, CommandInteraction, CreateActionRow, CreateAutocompleteResponse,
+    CreateButton, CreateEmbed, CreateEmbedAuthor, CreateMessage, CreateThread,
+    EditInteractionResponse, EditMessage, GuildChannel, Http, Message, UserId,
 };
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
 use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
+use crate::controller::discord::approval::request_approval;
+use crate::controller::discord::cancel::CANCEL_BUTTON_ID;
 use crate::shared::structs::AppState;
-use crate::shared::structs::agent::record::{Content, GenerationDump, PlanRecord};
+use crate::shared::structs::admission::Cooldown;
+use crate::shared::structs::agent::estimate::CostEstimate;
+use crate::shared::structs::agent::itinerary::Itinerary;
+use crate::shared::structs::agent::job::{JobStatus, PlanJob};
+use crate::shared::structs::agent::record::{
+    Content, CostSummary, GenerationStage, LanguageDecision, LanguageDecisionMethod, PlanRecord,
+    PlanStatus, ToolOutput,
+};
 use crate::shared::structs::agent::record::{Message as RecordMessage, PlanMapping};
 use crate::shared::structs::agent::{
-    Agent, Context, Executor, FinalResult, Language, LanguageModel, LanguageTriageArguments,
-    OrchestrationPlan, Task, Taskable,
+    Context, Executor, Language, LanguageTriageArguments, OrchestrationPlan, Provider, Task,
+    TaskOutcome, Taskable,
+};
+use crate::shared::structs::budget::{BudgetState, PlanBudget};
+use crate::shared::structs::config::{AgentOutput, Configuration};
+use crate::shared::structs::discord::approval::ApprovalDecision;
+use crate::shared::structs::discord::running_plan::{RunningPlan, RunningPlanGuard};
+use crate::shared::utility::budget::{
+    create_plan_budget, is_daily_budget_exhausted, record_daily_usage,
 };
-use crate::shared::structs::google_maps::{RouteWithDuration, TransferPlan};
-use crate::shared::utility::google_maps::{get_latitude_and_longitude, get_travel_time};
-use crate::shared::utility::{build_one_shot_messages, create_avatar_url};
+use crate::shared::utility::context_window::PromptFitter;
+use crate::shared::utility::estimate::{cost_estimate_format, render_cost_estimate};
+use crate::shared::utility::google_maps::suggest_places;
+use crate::shared::utility::itinerary::{locate_stops, render_itinerary};
+use crate::shared::utility::job::{INSTANCE_ID, JobHandle, save_job};
+use crate::shared::utility::language::detect_language;
+use crate::shared::utility::llm::{GenerationLog, create_chat_completion};
+use crate::shared::utility::synthesis::{
+    build_synthesis_request, fit_synthesis_results, render_synthesis_prompt,
+};
+use crate::shared::utility::tools::{ToolContext, build_tool_turn_message, call_tool, create_tool};
+use crate::shared::utility::validation::{describe_conflicts, find_schedule_conflicts};
+use crate::shared::utility::{build_one_shot_messages, create_avatar_url, split_message};
 use crate::shared::{
-    EMBED_COLOR, GEMINI_25_FLASH, GEMINI_25_PRO, GPT_41, MAX_TOOL_RETRY_COUNT,
-    PLAN_COLLECTION_NAME, PLAN_MAPPING_COLLECTION_NAME, TEMPERATURE_LOW, TEMPERATURE_MEDIUM,
+    EMBED_COLOR, GEMINI_25_FLASH, GEMINI_25_PRO, INTERACTION_TOKEN_LIFETIME, JOB_LEASE_DURATION,
+    MAX_FINAL_RESULT_MESSAGE_LENGTH, MAX_TOOL_RETRY_COUNT, MIN_AUTOCOMPLETE_QUERY_LENGTH,
+    PLAN_COLLECTION_NAME, PLAN_MAPPING_COLLECTION_NAME, QUEUE_POSITION_REFRESH_INTERVAL,
+    TEMPERATURE_LOW, TEMPERATURE_MEDIUM,
 };
 
-type PromptMap = HashMap<Language, HashMap<Agent, PromptSet>>;
-
-#[derive(Debug, Clone)]
-struct PromptSet {
-    pub system: String,
-    pub user: String,
-    pub agent: String,
-    pub transport_agent: String,
-    pub transport_agent_maximum_retry: String,
-}
+const PROMPT_OPTION_NAME: &str = "prompt";
+const DESTINATION_OPTION_NAME: &str = "destination";
+const MAX_CHOICE_NAME_LENGTH: usize = 100;
+/// The BCP-47 tag for a language that couldn't be determined.
+const UNDETERMINED_LANGUAGE: &str = "und";
 
 #[command_handler]
 pub async fn plan(interaction: CommandInteraction, app_state: AppState) -> anyhow::Result<()> {
-    let user_prompt = interaction.data.options[0]
-        .value
-        .as_str()
+    let user_prompt = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == PROMPT_OPTION_NAME)
+        .and_then(|option| option.value.as_str())
         .map(ToString::to_string)
         .unwrap_or_default();
 
-    let language = determine_language(&user_prompt, &app_state).await?;
+    let destination = interaction
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == DESTINATION_OPTION_NAME)
+        .and_then(|option| option.value.as_str())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    let user_prompt = match destination {
+        Some(destination) => format!("{user_prompt}\n\nDestination: {destination}"),
+        None => user_prompt,
+    };
+
+    let config = app_state.config();
+    let locale = config.resolve_language(&Language::new(&interaction.locale));
+
+    if is_daily_budget_exhausted(interaction.user.id, &app_state).await? {
+        let content = config
+            .templates
+            .ui(&locale, "daily_budget_reached", context! {})?;
+        let edit_content = EditInteractionResponse::new().content(content);
+        app_state
+            .http
+            .edit_original_interaction_response(&interaction.token, &edit_content, Vec::new())
+            .await?;
+
+        return Ok(());
+    }
+
+    if let Some(cooldown) = app_state
+        .admission
+        .try_start_cooldown(interaction.user.id, interaction.guild_id)
+    {
+        let content = match cooldown {
+            Cooldown::User(seconds) => {
+                config
+                    .templates
+                    .ui(&locale, "user_cooldown", context! { seconds })?
+            }
+            Cooldown::Guild(seconds) => {
+                config
+                    .templates
+                    .ui(&locale, "guild_cooldown", context! { seconds })?
+            }
+        };
+
+        let edit_content = EditInteractionResponse::new().content(content);
+        app_state
+            .http
+            .edit_original_interaction_response(&interaction.token, &edit_content, Vec::new())
+            .await?;
+
+        return Ok(());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    let job = PlanJob {
+        id: uuid::Uuid::now_v7(),
+        status: JobStatus::Received,
+        user_id: interaction.user.id,
+        channel_id: interaction.channel_id,
+        interaction_token: interaction.token.clone(),
+        user_prompt,
+        locale,
+        created_at: now,
+        owner: *INSTANCE_ID,
+        lease_expires_at: now + JOB_LEASE_DURATION,
+        greeting_message_id: None,
+        thread_id: None,
+        orchestration: None,
+        plan_record: None,
+        contexts: vec![],
+        final_result: None,
+    };
+
+    save_job(&job, &app_state.firestore_db).await?;
+
+    run_plan_job(job, app_state).await
+}
+
+/// Waits for a free plan slot, keeping the user informed of their position in the queue with a
+/// message in the discussion thread, which is removed once the wait is over. Returns `None` if
+/// the plan is cancelled while it's queued.
+async fn wait_for_admission(
+    job: &PlanJob,
+    thread_id: ChannelId,
+    language: &Language,
+    cancellation_token: &CancellationToken,
+    app_state: &AppState,
+) -> anyhow::Result<Option<OwnedSemaphorePermit>> {
+    let ticket = app_state.admission.enqueue(job.id);
+    let admitted = ticket.admitted();
+    tokio::pin!(admitted);
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(
+        QUEUE_POSITION_REFRESH_INTERVAL,
+    ));
+    let mut reported_position = None;
+    let mut queue_message: Option<Message> = None;
+
+    let permit = loop {
+        tokio::select! {
+            biased;
+            permit = &mut admitted => break permit.map(Some),
+            _ = cancellation_token.cancelled() => break Ok(None),
+            _ = interval.tick() => {
+                let position = ticket.position();
+                if position.is_none() || position == reported_position {
+                    continue;
+                }
+
+                reported_position = position;
+                let content = app_state.config().templates.ui(
+                    language,
+                    "queue_position",
+                    context! { position => position.unwrap_or_default() },
+                )?;
+
+                let result = match queue_message {
+                    Some(ref message) => {
+                        let edit_message_args = EditMessage::new().content(content);
+                        app_state
+                            .http
+                            .edit_message(thread_id, message.id, &edit_message_args, vec![])
+                            .await
+                    }
+                    None => {
+                        let message_args = CreateMessage::new().content(content);
+                        app_state
+                            .http
+                            .send_message(thread_id, vec![], &message_args)
+                            .await
+                    }
+                };
+
+                match result {
+                    Ok(message) => queue_message = Some(message),
+                    Err(e) => tracing::warn!("Failed to report the queue position of plan {}: {e:?}", job.id),
+                }
+            }
+        }
+    };
+
+    if let Some(message) = queue_message
+        && let Err(e) = app_state
+            .http
+            .delete_message(thread_id, message.id, None)
+            .await
+    {
+        tracing::warn!(
+            "Failed to remove the queue position of plan {}: {e:?}",
+            job.id
+        );
+    }
+
+    permit
+}
+
+/// Runs a plan job from its last checkpoint until it's completed, cancelled or failed.
+pub async fn run_plan_job(job: PlanJob, app_state: AppState) -> anyhow::Result<()> {
+    let job_id = job.id;
+    let job_handle = JobHandle::new(job, app_state.firestore_db.clone());
+    let _heartbeat = job_handle.start_heartbeat();
+
+    let result = drive_plan_job(&job_handle, &app_state).await;
+
+    if let Err(ref e) = result {
+        tracing::error!("Plan job {job_id} failed: {e:?}");
+
+        // What the plan spent before failing still counts against the user's daily budget.
+        let job = job_handle.snapshot().await;
+        if let Some(ref plan_record) = job.plan_record {
+            let cost = CostSummary::new(&plan_record.dumps, &app_state.config().prices).cost;
+            if let Err(e) =
+                record_daily_usage(job.user_id, job_id, cost, &app_state.firestore_db).await
+            {
+                tracing::warn!("Failed to record the cost of plan {job_id}: {e:?}");
+            }
+        }
+
+        job_handle
+            .update(|job| job.status = JobStatus::Failed)
+            .await?;
+    }
+
+    result
+}
+
+async fn drive_plan_job(job_handle: &JobHandle, app_state: &AppState) -> anyhow::Result<()> {
+    if job_handle.snapshot().await.status == JobStatus::Received {
+        orchestrate_job(job_handle, app_state).await?;
+    }
 
-    let orchestrator_system_prompt = match language {
-        Language::Chinese => app_state.config.chinese.orchestrator.prompt.clone(),
-        Language::Japanese => app_state.config.japanese.orchestrator.prompt.clone(),
-        _ => app_state.config.english.orchestrator.prompt.clone(),
+    let job = job_handle.snapshot().await;
+    let (Some(thread_id), Some(mut plan_record)) = (job.thread_id, job.plan_record.clone()) else {
+        return Err(anyhow::anyhow!(
+            "Plan job {} is missing its orchestration checkpoint.",
+            job.id
+        ));
     };
 
-    let orchestration_response =
-        orchestrate(&orchestrator_system_prompt, &user_prompt, &app_state).await;
+    let cancellation_token = CancellationToken::new();
+    app_state.running_plans.insert(
+        thread_id,
+        RunningPlan {
+            plan_id: job.id,
+            user_id: job.user_id,
+            cancellation_token: cancellation_token.clone(),
+        },
+    );
+    let _running_plan_guard = RunningPlanGuard {
+        thread_id,
+        running_plans: app_state.running_plans.clone(),
+    };
+
+    let mut orchestration = job.orchestration.clone().unwrap_or_default();
+
+    if job.status == JobStatus::Orchestrated {
+        if app_state.config().require_plan_approval {
+            let reviewed = tokio::select! {
+                reviewed = review_plan(
+                    orchestration,
+                    job.user_id,
+                    thread_id,
+                    &mut plan_record,
+                    app_state,
+                ) => reviewed?,
+                _ = cancellation_token.cancelled() => {
+                    app_state.pending_approvals.remove(&job.id);
+                    None
+                }
+            };
+
+            match reviewed {
+                Some(reviewed) => orchestration = reviewed,
+                None => {
+                    return record_cancelled_plan(plan_record, vec![], None, job_handle, app_state)
+                        .await;
+                }
+            }
+        }
+
+        job_handle
+            .update(|job| {
+                job.status = JobStatus::Approved;
+                job.orchestration = Some(orchestration.clone());
+                job.plan_record = Some(plan_record.clone());
+            })
+            .await?;
+    }
+
+    if job_handle.snapshot().await.status == JobStatus::Approved {
+        // Only taken once the plan is approved, so that a plan waiting for its user doesn't hold
+        // a slot that others are queued for. Orchestration and revisions aren't admitted, and are
+        // only limited by the concurrency of their provider.
+        let permit = wait_for_admission(
+            &job,
+            thread_id,
+            &plan_record.language,
+            &cancellation_token,
+            app_state,
+        )
+        .await?;
+        let Some(_permit) = permit else {
+            return record_cancelled_plan(plan_record, vec![], None, job_handle, app_state).await;
+        };
+
+        // Shared by the tasks and the synthesis, so that neither can overspend the plan.
+        let budget = create_plan_budget(job.user_id, &plan_record.dumps, app_state).await?;
+
+        let (maybe_message, results) = execute_plan(
+            orchestration,
+            thread_id,
+            &mut plan_record,
+            job_handle.clone(),
+            budget.clone(),
+            cancellation_token.clone(),
+            app_state,
+        )
+        .await?;
+
+        // Nothing was planned, so there is nothing to synthesize either.
+        let Some(message_mutex) = maybe_message else {
+            return record_empty_plan(plan_record, job_handle, app_state).await;
+        };
+
+        if cancellation_token.is_cancelled() {
+            return record_cancelled_plan(
+                plan_record,
+                results,
+                Some(message_mutex),
+                job_handle,
+                app_state,
+            )
+            .await;
+        }
+
+        {
+            let synthesizing = app_state.config().templates.ui(
+                &plan_record.language,
+                "synthesizing",
+                context! {},
+            )?;
+            let mut message = message_mutex.lock().await;
+            append_embed_line(&mut message, &synthesizing, false, &app_state.http).await?;
+        }
+
+        plan_record.contexts = results.clone();
+
+        let final_result = tokio::select! {
+            final_result = synthesize(plan_record.language.clone(), results.clone(), &mut plan_record, budget, app_state) => final_result?,
+            _ = cancellation_token.cancelled() => {
+                return record_cancelled_plan(
+                    plan_record,
+                    results,
+                    Some(message_mutex),
+                    job_handle,
+                    app_state,
+                )
+                .await;
+            }
+        };
+
+        plan_record.cost = CostSummary::new(&plan_record.dumps, &app_state.config().prices);
+        let cost_line = format_cost_line(&plan_record.cost, &plan_record.language, app_state)?;
+
+        job_handle
+            .update(|job| {
+                job.status = JobStatus::Synthesized;
+                job.final_result = Some(final_result);
+                job.plan_record = Some(plan_record);
+            })
+            .await?;
+
+        {
+            let mut message = message_mutex.lock().await;
+
+            if app_state.config().show_cost_in_embed {
+                append_embed_line(&mut message, &cost_line, true, &app_state.http).await?;
+            } else {
+                let edit_message_args = EditMessage::new().components(vec![]);
+
+                *message = app_state
+                    .http
+                    .edit_message(message.channel_id, message.id, &edit_message_args, vec![])
+                    .await?;
+            }
+        }
+    }
+
+    let job = job_handle.snapshot().await;
+    if job.status == JobStatus::Synthesized
+        && let (Some(plan_record), Some(final_result)) =
+            (job.plan_record.clone(), job.final_result.clone())
+    {
+        let cost = plan_record.cost.cost;
+        insert_record(plan_record, &job, app_state).await?;
+
+        send_final_result_message(final_result, thread_id, app_state).await?;
+
+        if let Err(e) = record_daily_usage(job.user_id, job.id, cost, &app_state.firestore_db).await
+        {
+            tracing::warn!("Failed to record the cost of plan {}: {e:?}", job.id);
+        }
+
+        job_handle
+            .update(|job| job.status = JobStatus::Completed)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Determines the language, orchestrates the tasks and opens the discussion thread.
+async fn orchestrate_job(job_handle: &JobHandle, app_state: &AppState) -> anyhow::Result<()> {
+    let job = job_handle.snapshot().await;
+
+    let generation_log = GenerationLog::default();
+
+    let (language, language_decision) =
+        determine_language(&job.user_prompt, &generation_log, app_state).await?;
+
+    let config = app_state.config();
+    let orchestrator_system_prompt = config.templates.render(
+        &config.templates.name(&language, "orchestrator"),
+        context! {},
+    )?;
+
+    let orchestration_response = orchestrate(
+        build_one_shot_messages(&orchestrator_system_prompt, &job.user_prompt)?,
+        &generation_log,
+        app_state,
+    )
+    .await;
     let (message, orchestration) = match orchestration_response {
         Ok(response) => (response.greeting_message.clone(), response),
         Err(e) => (format!("{e:?}"), OrchestrationPlan::default()),
     };
 
-    let mut plan_record = PlanRecord {
-        id: uuid::Uuid::now_v7(),
+    let greeting_message = send_greeting(&job, message, app_state).await?;
+    let thread = create_thread(&greeting_message, &language, &generation_log, app_state).await?;
+
+    let plan_record = PlanRecord {
+        id: job.id,
         language,
+        language_decision: Some(language_decision),
         messages: vec![
             RecordMessage {
                 role: Role::System,
@@ -77,69 +494,244 @@ pub async fn plan(interaction: CommandInteraction, app_state: AppState) -> anyho
             },
             RecordMessage {
                 role: Role::User,
-                content: Content::Plain(user_prompt.clone()),
+                content: Content::Plain(job.user_prompt.clone()),
             },
             RecordMessage {
                 role: Role::Assistant,
                 content: Content::Dynamic(serde_json::to_value(&orchestration)?),
             },
         ],
-        dumps: vec![GenerationDump {
-            model: LanguageModel::Gemini25Pro,
-            content: orchestration.to_string(),
-            ..Default::default()
-        }],
+        dumps: generation_log.snapshot().await,
+        status: PlanStatus::Completed,
+        contexts: vec![],
+        cost: Default::default(),
+        itinerary: None,
+        tool_outputs: vec![],
     };
 
-    let edited_message = send_greeting(&interaction, message, &app_state).await?;
-    let thread = create_thread(&edited_message, language, &app_state).await?;
+    job_handle
+        .update(|job| {
+            job.status = JobStatus::Orchestrated;
+            job.greeting_message_id = Some(greeting_message.id);
+            job.thread_id = Some(thread.id);
+            job.orchestration = Some(orchestration);
+            job.plan_record = Some(plan_record);
+        })
+        .await
+}
 
-    let (maybe_message, results) = execute_plan(
-        orchestration,
-        language,
-        thread.id,
-        &mut plan_record,
-        &app_state,
-    )
-    .await?;
+/// Stores whatever a cancelled plan has produced so far, so that it can be inspected later. The
+/// execution message is only there if the plan was cancelled after it started running.
+async fn record_cancelled_plan(
+    mut plan_record: PlanRecord,
+    results: Vec<Context>,
+    message_mutex: Option<Arc<Mutex<Message>>>,
+    job_handle: &JobHandle,
+    app_state: &AppState,
+) -> anyhow::Result<()> {
+    tracing::info!(
+        "Plan {} was cancelled after {} completed tasks.",
+        plan_record.id,
+        results.len()
+    );
 
-    if let Some(message_mutex) = maybe_message {
-        {
-            let mut message = message_mutex.lock().await;
+    plan_record.status = PlanStatus::Cancelled;
+    plan_record.contexts = results;
+    plan_record.cost = CostSummary::new(&plan_record.dumps, &app_state.config().prices);
+
+    if let Some(message_mutex) = message_mutex {
+        let plan_cancelled = app_state.config().templates.ui(
+            &plan_record.language,
+            "plan_cancelled",
+            context! {},
+        )?;
+        let mut message = message_mutex.lock().await;
+        append_embed_line(&mut message, &plan_cancelled, true, &app_state.http).await?;
+    }
 
-            if let Some(original_embed) = message.embeds.first()
-                && let Some(ref original_desc) = original_embed.description
-            {
-                let mut new_embed = original_embed.clone();
-                new_embed.description =
-                    Some(format!("{original_desc}\n🔄 Synthesizing final result..."));
+    let job = job_handle.snapshot().await;
+    let cost = plan_record.cost.cost;
+    insert_record(plan_record, &job, app_state).await?;
 
-                let edit_message_args = EditMessage::new().embed(CreateEmbed::from(new_embed));
+    if let Err(e) = record_daily_usage(job.user_id, job.id, cost, &app_state.firestore_db).await {
+        tracing::warn!("Failed to record the cost of plan {}: {e:?}", job.id);
+    }
 
-                let new_message = app_state
-                    .http
-                    .edit_message(message.channel_id, message.id, &edit_message_args, vec![])
-                    .await?;
+    job_handle
+        .update(|job| job.status = JobStatus::Cancelled)
+        .await
+}
 
-                *message = new_message;
-            }
-        }
+/// Stores a plan that had no tasks to run, so that its orchestration can be inspected later.
+async fn record_empty_plan(
+    mut plan_record: PlanRecord,
+    job_handle: &JobHandle,
+    app_state: &AppState,
+) -> anyhow::Result<()> {
+    plan_record.cost = CostSummary::new(&plan_record.dumps, &app_state.config().prices);
 
-        let final_result = synthesize(language, results, &mut plan_record, &app_state).await?;
+    let job = job_handle.snapshot().await;
+    let cost = plan_record.cost.cost;
+    insert_record(plan_record, &job, app_state).await?;
 
-        insert_record(plan_record, edited_message, thread.id, &app_state).await?;
+    if let Err(e) = record_daily_usage(job.user_id, job.id, cost, &app_state.firestore_db).await {
+        tracing::warn!("Failed to record the cost of plan {}: {e:?}", job.id);
+    }
+
+    job_handle
+        .update(|job| job.status = JobStatus::Completed)
+        .await
+}
+
+fn format_cost_line(
+    cost: &CostSummary,
+    language: &Language,
+    app_state: &AppState,
+) -> anyhow::Result<String> {
+    let templates = app_state.config().templates.clone();
 
-        send_final_result_message(final_result, thread.id, &app_state).await?;
+    let mut line = templates.ui(
+        language,
+        "cost_summary",
+        context! {
+            cost => format!("{:.4}", cost.cost),
+            tokens => cost.prompt_tokens + cost.completion_tokens,
+            calls => cost.calls,
+        },
+    )?;
+
+    if cost.failed_calls > 0 {
+        line.push_str(&templates.ui(
+            language,
+            "failed_calls",
+            context! { failed_calls => cost.failed_calls },
+        )?);
+    }
+
+    if let Some(slowest) = cost.slowest_model() {
+        line.push_str(&templates.ui(
+            language,
+            "slowest_model",
+            context! {
+                model => slowest.model_name,
+                seconds => format!("{:.1}", slowest.average_latency_ms as f64 / 1000.0),
+            },
+        )?);
+    }
+
+    Ok(line)
+}
+
+async fn append_embed_line(
+    message: &mut Message,
+    line: &str,
+    clear_components: bool,
+    http: &Http,
+) -> anyhow::Result<()> {
+    if let Some(original_embed) = message.embeds.first()
+        && let Some(ref original_desc) = original_embed.description
+    {
+        let mut new_embed = original_embed.clone();
+        new_embed.description = Some(format!("{original_desc}\n{line}"));
+
+        let mut edit_message_args = EditMessage::new().embed(CreateEmbed::from(new_embed));
+        if clear_components {
+            edit_message_args = edit_message_args.components(vec![]);
+        }
+
+        *message = http
+            .edit_message(message.channel_id, message.id, &edit_message_args, vec![])
+            .await?;
     }
 
     Ok(())
 }
 
-async fn determine_language(user_prompt: &str, app_state: &AppState) -> anyhow::Result<Language> {
-    let system_prompt = app_state.config.language_triage_prompt.clone();
+#[autocomplete_handler("plan")]
+pub async fn plan_autocomplete(
+    interaction: CommandInteraction,
+    app_state: AppState,
+) -> anyhow::Result<CreateAutocompleteResponse> {
+    let query = match interaction.data.autocomplete() {
+        Some(option) if option.name == DESTINATION_OPTION_NAME => option.value.trim().to_string(),
+        _ => return Ok(CreateAutocompleteResponse::new()),
+    };
+
+    if query.chars().count() < MIN_AUTOCOMPLETE_QUERY_LENGTH {
+        return Ok(CreateAutocompleteResponse::new());
+    }
+
+    let config = app_state.config();
+    let language = config.resolve_language(&Language::new(&interaction.locale));
+
+    let suggestions = suggest_places(
+        &query,
+        &language,
+        config.language(&language).geocoding_language(),
+        &app_state.firestore_db,
+        app_state.google_maps_client.clone(),
+    )
+    .await?;
+
+    let response = suggestions
+        .into_iter()
+        .map(|suggestion| {
+            suggestion
+                .name
+                .chars()
+                .take(MAX_CHOICE_NAME_LENGTH)
+                .collect::<String>()
+        })
+        .filter(|name| !name.is_empty())
+        .fold(CreateAutocompleteResponse::new(), |response, name| {
+            response.add_string_choice(name.clone(), name)
+        });
+
+    Ok(response)
+}
+
+/// Detects the language locally, and only asks the model when the prompt is mixed or ambiguous.
+async fn determine_language(
+    user_prompt: &str,
+    generation_log: &GenerationLog,
+    app_state: &AppState,
+) -> anyhow::Result<(Language, LanguageDecision)> {
+    let config = app_state.config();
+
+    let detection = detect_language(user_prompt, &config.languages, &config.language_detection);
+    let decision = |method| LanguageDecision {
+        method,
+        detected: detection.detected.map(ToString::to_string),
+        confidence: detection.confidence,
+    };
+
+    if let Some(ref language) = detection.language {
+        tracing::info!(
+            "Detected {language} with a confidence of {:.2}.",
+            detection.confidence
+        );
+        return Ok((language.clone(), decision(LanguageDecisionMethod::Detector)));
+    }
+
+    let system_prompt = config
+        .templates
+        .render("language_triage_prompt", context! {})?;
 
     let messages = build_one_shot_messages(&system_prompt, user_prompt)?;
 
+    let languages = config
+        .languages
+        .iter()
+        .map(|(language, language_config)| format!("{language} ({})", language_config.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut tags = config
+        .languages
+        .keys()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    tags.push(UNDETERMINED_LANGUAGE.into());
+
     let tool = ChatCompletionToolArgs::default()
         .r#type(ChatCompletionToolType::Function)
         .function(FunctionObjectArgs::default()
@@ -150,8 +742,8 @@ async fn determine_language(user_prompt: &str, app_state: &AppState) -> anyhow::
                 "properties": {
                     "language": {
                         "type": "string",
-                        "description": "The language of the user's prompt, e.g. Simplified Chinese, English, Japanese, etc.",
-                        "enum": ["English", "Chinese", "Japanese", "Other"]
+                        "description": format!("The BCP-47 tag of the language of the user's prompt. One of {languages}, or {UNDETERMINED_LANGUAGE} for any other language."),
+                        "enum": tags
                     }
                 },
                 "required": ["language"],
@@ -161,20 +753,26 @@ async fn determine_language(user_prompt: &str, app_state: &AppState) -> anyhow::
             .build()?)
         .build()?;
 
+    let model = &config.language_detection.model;
     let request = CreateChatCompletionRequestArgs::default()
-        .model(GPT_41)
+        .model(model)
         .messages(messages)
         .temperature(TEMPERATURE_LOW)
         .tools(vec![tool])
         .tool_choice(ChatCompletionToolChoiceOption::Required)
         .build()?;
 
-    let response = app_state
-        .llm_clients
-        .openai_client
-        .chat()
-        .create(request)
-        .await;
+    let provider = Provider::for_model(model);
+
+    let response = create_chat_completion(
+        &app_state.llm_clients.client(provider),
+        provider,
+        request,
+        GenerationStage::Triage,
+        &app_state.llm_clients,
+        generation_log,
+    )
+    .await;
 
     match response {
         Ok(res) => {
@@ -189,24 +787,45 @@ async fn determine_language(user_prompt: &str, app_state: &AppState) -> anyhow::
                     })
                     .unwrap_or_default();
 
-            Ok(serde_json::from_str::<LanguageTriageArguments>(&arguments)?.language)
+            let language = serde_json::from_str::<LanguageTriageArguments>(&arguments)?.language;
+            Ok((
+                config.resolve_language(&language),
+                decision(LanguageDecisionMethod::Model),
+            ))
         }
         Err(e) => {
-            let error_msg = format!("Failed to call OpenAI API: {e:?}. Fall back to English.");
+            let error_msg = format!(
+                "Failed to determine the language with {model}: {e:?}. Fall back to {}.",
+                config.default_language
+            );
             tracing::error!("{}", error_msg);
-            Ok(Language::English)
+            Ok((
+                config.default_language.clone(),
+                decision(LanguageDecisionMethod::Default),
+            ))
         }
     }
 }
 
 async fn orchestrate(
-    system_prompt: &str,
-    user_prompt: &str,
+    messages: Vec<ChatCompletionRequestMessage>,
+    generation_log: &GenerationLog,
     app_state: &AppState,
 ) -> anyhow::Result<OrchestrationPlan> {
-    let messages = build_one_shot_messages(system_prompt, user_prompt)?;
+    let config = app_state.config();
+    let agent_keys = config
+        .agents
+        .keys()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    let agent_descriptions = config
+        .agents
+        .iter()
+        .map(|(agent, agent_config)| format!("`{agent}`: {}", agent_config.description.trim()))
+        .collect::<Vec<_>>()
+        .join(" ");
 
-    let request = CreateChatCompletionRequestArgs::default()
+    let mut request = CreateChatCompletionRequestArgs::default()
         .model(GEMINI_25_PRO)
         .messages(messages)
         .temperature(TEMPERATURE_LOW)
@@ -236,8 +855,8 @@ async fn orchestrate(
                                 },
                                 "agent": {
                                     "type": "string",
-                                    "description": "Agent name to assign this task to.",
-                                    "enum": ["Food", "History", "Modern", "Nature", "Transport"]
+                                    "description": format!("Agent name to assign this task to. {agent_descriptions}"),
+                                    "enum": agent_keys
                                 },
                                 "instruction": {
                                     "type": "string",
@@ -249,9 +868,13 @@ async fn orchestrate(
                                     "items": {
                                         "type": "string"
                                     }
+                                },
+                                "optional": {
+                                    "type": "boolean",
+                                    "description": "Whether the final result is still useful without this task. Optional tasks may be skipped to save cost, so no other task should depend on them."
                                 }
                             },
-                            "required": ["task_id", "agent", "instruction", "dependencies"],
+                            "required": ["task_id", "agent", "instruction", "dependencies", "optional"],
                             "additionalProperties": false
                         }
                     },
@@ -267,45 +890,75 @@ async fn orchestrate(
         } })
         .build()?;
 
+    let mut retry_count = 0;
     loop {
         let request_clone = request.clone();
 
-        let response = app_state
-            .llm_clients
-            .open_router_clients
-            .get(&Agent::default())
-            .expect("Failed to get the Open Router client for orchestration.")
-            .chat()
-            .create(request_clone)
-            .await;
+        let response = create_chat_completion(
+            &app_state.llm_clients.client(Provider::OpenRouter),
+            Provider::OpenRouter,
+            request_clone,
+            GenerationStage::Orchestration,
+            &app_state.llm_clients,
+            generation_log,
+        )
+        .await;
 
         match response {
             Ok(res) => {
                 let content = res.choices[0].message.content.clone().unwrap_or_default();
-                let orchestration_plan = serde_json::from_str::<OrchestrationPlan>(&content)?;
+                let mut orchestration_plan = serde_json::from_str::<OrchestrationPlan>(&content)?;
 
-                let mut all_dependencies = orchestration_plan
+                let all_task_ids = orchestration_plan
                     .tasks
                     .iter()
-                    .flat_map(|t| t.dependencies.clone())
+                    .map(|t| t.task_id.clone())
                     .collect::<Vec<_>>();
 
-                all_dependencies.sort();
-                all_dependencies.dedup();
-
-                let all_task_ids = orchestration_plan
+                let mut unknown_dependencies = orchestration_plan
                     .tasks
                     .iter()
-                    .map(|t| t.task_id.clone())
+                    .flat_map(|t| t.dependencies.clone())
+                    .filter(|dep| !all_task_ids.contains(dep))
                     .collect::<Vec<_>>();
 
-                if all_dependencies
-                    .into_iter()
-                    .all(|dep| all_task_ids.contains(&dep))
-                {
+                unknown_dependencies.sort();
+                unknown_dependencies.dedup();
+
+                if unknown_dependencies.is_empty() {
+                    require_dependencies_of_required_tasks(&mut orchestration_plan);
+                    add_agent_dependencies(&mut orchestration_plan, &config);
                     tracing::info!("Orchestration response: {:?}", &orchestration_plan);
                     return Ok(orchestration_plan);
                 }
+
+                retry_count += 1;
+                if retry_count >= MAX_TOOL_RETRY_COUNT {
+                    return Err(anyhow::anyhow!(
+                        "Orchestration kept depending on unknown tasks: {unknown_dependencies:?}"
+                    ));
+                }
+
+                tracing::warn!(
+                    "Orchestration depends on unknown tasks {unknown_dependencies:?}. Retrying."
+                );
+
+                // Tells the model what was wrong, so that the retry isn't the same request again.
+                request
+                    .messages
+                    .push(ChatCompletionRequestMessage::Assistant(
+                        ChatCompletionRequestAssistantMessageArgs::default()
+                            .content(content)
+                            .build()?,
+                    ));
+                request.messages.push(ChatCompletionRequestMessage::User(
+                    ChatCompletionRequestUserMessageArgs::default()
+                        .content(format!(
+                            "These dependencies aren't the `task_id` of any task: {}. Only depend on tasks in `tasks`.",
+                            unknown_dependencies.join(", ")
+                        ))
+                        .build()?,
+                ));
             }
             Err(e) => {
                 let error_msg = format!("Error when creating orchestration tasks: {e:?}");
@@ -316,17 +969,183 @@ async fn orchestrate(
     }
 }
 
+/// Marks the tasks that required tasks wait for, directly or through other tasks, as required,
+/// since optional tasks may be skipped and would leave those waiting for nothing.
+fn require_dependencies_of_required_tasks(orchestration: &mut OrchestrationPlan) {
+    let mut pending = orchestration
+        .tasks
+        .iter()
+        .filter(|task| !task.optional)
+        .flat_map(|task| task.dependencies.clone())
+        .collect::<Vec<_>>();
+
+    while let Some(dependency) = pending.pop() {
+        let Some(task) = orchestration
+            .tasks
+            .iter_mut()
+            .find(|task| task.task_id == dependency && task.optional)
+        else {
+            continue;
+        };
+
+        tracing::info!(
+            "Task {} is required by another task, so it's no longer optional.",
+            task.task_id
+        );
+        task.optional = false;
+        pending.extend(task.dependencies.iter().cloned());
+    }
+}
+
+/// Makes the tasks of agents with `depends_on` wait for the tasks of those agents. Optional tasks
+/// are left out since they may be skipped, and so are tasks that would end up waiting for each
+/// other.
+fn add_agent_dependencies(orchestration: &mut OrchestrationPlan, config: &Configuration) {
+    for index in 0..orchestration.tasks.len() {
+        let Some(agent_config) = config.agents.get(&orchestration.tasks[index].agent) else {
+            continue;
+        };
+
+        let task_id = orchestration.tasks[index].task_id.clone();
+        let dependencies = orchestration
+            .tasks
+            .iter()
+            .filter(|task| {
+                agent_config.depends_on.contains(&task.agent)
+                    && !task.optional
+                    && !depends_on_task(&orchestration.tasks, &task.task_id, &task_id)
+            })
+            .map(|task| task.task_id.clone())
+            .collect::<Vec<_>>();
+
+        let task = &mut orchestration.tasks[index];
+        for dependency in dependencies.into_iter() {
+            if !task.dependencies.contains(&dependency) {
+                task.dependencies.push(dependency);
+            }
+        }
+    }
+}
+
+/// Whether `task_id` waits for `dependency`, directly or through other tasks.
+fn depends_on_task(tasks: &[Task], task_id: &str, dependency: &str) -> bool {
+    let mut pending = vec![task_id];
+    let mut visited = HashSet::new();
+
+    while let Some(current) = pending.pop() {
+        if current == dependency {
+            return true;
+        }
+
+        if !visited.insert(current) {
+            continue;
+        }
+
+        if let Some(task) = tasks.iter().find(|task| task.task_id == current) {
+            pending.extend(task.dependencies.iter().map(String::as_str));
+        }
+    }
+
+    false
+}
+
+/// Asks the requesting user to approve the plan before any agent runs, re-orchestrating with
+/// their feedback as many times as they ask for revisions. Returns `None` if cancelled.
+async fn review_plan(
+    mut orchestration: OrchestrationPlan,
+    user_id: UserId,
+    discussion_thread_id: ChannelId,
+    plan_record: &mut PlanRecord,
+    app_state: &AppState,
+) -> anyhow::Result<Option<OrchestrationPlan>> {
+    loop {
+        if orchestration.tasks.is_empty() {
+            return Ok(Some(orchestration));
+        }
+
+        let decision = request_approval(
+            &orchestration,
+            plan_record.id,
+            user_id,
+            discussion_thread_id,
+            &plan_record.language,
+            app_state,
+        )
+        .await?;
+
+        match decision {
+            ApprovalDecision::Approve => return Ok(Some(orchestration)),
+            ApprovalDecision::Cancel => return Ok(None),
+            ApprovalDecision::Revise(revision) => {
+                plan_record.messages.push(RecordMessage {
+                    role: Role::User,
+                    content: Content::Plain(revision),
+                });
+
+                let messages = plan_record
+                    .messages
+                    .iter()
+                    .map(|m| m.to_openai_message())
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+
+                let generation_log = GenerationLog::default();
+                let orchestration_result = orchestrate(messages, &generation_log, app_state).await;
+                plan_record
+                    .dumps
+                    .append(&mut generation_log.snapshot().await);
+
+                // The previous plan is still valid, so the user gets to review it again.
+                let Ok(revised) = orchestration_result.inspect_err(|e| {
+                    tracing::error!("Failed to revise plan {}: {e:?}", plan_record.id);
+                }) else {
+                    plan_record.messages.pop();
+
+                    let revision_failed = app_state.config().templates.ui(
+                        &plan_record.language,
+                        "revision_failed",
+                        context! {},
+                    )?;
+                    let message_args = CreateMessage::new().content(revision_failed);
+                    app_state
+                        .http
+                        .send_message(discussion_thread_id, vec![], &message_args)
+                        .await?;
+
+                    continue;
+                };
+                orchestration = revised;
+
+                plan_record.messages.push(RecordMessage {
+                    role: Role::Assistant,
+                    content: Content::Dynamic(serde_json::to_value(&orchestration)?),
+                });
+            }
+        }
+    }
+}
+
 async fn send_greeting(
-    interaction: &CommandInteraction,
+    job: &PlanJob,
     message: String,
     app_state: &AppState,
 ) -> anyhow::Result<Message> {
-    let edit_content = EditInteractionResponse::new().content(message);
+    // A resumed job may have outlived its interaction token, in which case the greeting can
+    // only be sent as a new message.
+    let response = if chrono::Utc::now().timestamp() - job.created_at < INTERACTION_TOKEN_LIFETIME {
+        let edit_content = EditInteractionResponse::new().content(message);
 
-    let response = app_state
-        .http
-        .edit_original_interaction_response(&interaction.token, &edit_content, Vec::new())
-        .await;
+        app_state
+            .http
+            .edit_original_interaction_response(&job.interaction_token, &edit_content, Vec::new())
+            .await
+    } else {
+        let message_args = CreateMessage::new().content(message);
+
+        app_state
+            .http
+            .send_message(job.channel_id, Vec::new(), &message_args)
+            .await
+    };
 
     match response {
         Ok(message) => Ok(message),
@@ -340,10 +1159,11 @@ async fn send_greeting(
 
 async fn create_thread(
     message: &Message,
-    language: Language,
+    language: &Language,
+    generation_log: &GenerationLog,
     app_state: &AppState,
 ) -> anyhow::Result<GuildChannel> {
-    let title = name_thread(message, language, app_state).await?;
+    let title = name_thread(message, language, generation_log, app_state).await?;
 
     let create_thread_args =
         CreateThread::new(title).auto_archive_duration(serenity::all::AutoArchiveDuration::OneWeek);
@@ -365,14 +1185,14 @@ async fn create_thread(
 
 async fn name_thread(
     message: &Message,
-    language: Language,
+    language: &Language,
+    generation_log: &GenerationLog,
     app_state: &AppState,
 ) -> anyhow::Result<String> {
-    let system_prompt = match language {
-        Language::Chinese => app_state.config.chinese.naming.prompt.clone(),
-        Language::Japanese => app_state.config.japanese.naming.prompt.clone(),
-        _ => app_state.config.english.naming.prompt.clone(),
-    };
+    let config = app_state.config();
+    let system_prompt = config
+        .templates
+        .render(&config.templates.name(language, "naming"), context! {})?;
 
     let messages = build_one_shot_messages(&system_prompt, &message.content)?;
 
@@ -382,54 +1202,94 @@ async fn name_thread(
         .messages(messages)
         .build()?;
 
-    let response = app_state
-        .llm_clients
-        .open_router_clients
-        .get(&Agent::default())
-        .expect("Failed to get the Open Router client for renaming thread.")
-        .chat()
-        .create(request)
-        .await
-        .map(|res| {
-            res.choices
-                .first()
-                .and_then(|choice| choice.message.content.clone())
-                .unwrap_or_default()
-        });
+    let response = create_chat_completion(
+        &app_state.llm_clients.client(Provider::OpenRouter),
+        Provider::OpenRouter,
+        request,
+        GenerationStage::Naming,
+        &app_state.llm_clients,
+        generation_log,
+    )
+    .await?;
 
-    Ok(response?)
+    Ok(response
+        .choices
+        .first()
+        .and_then(|choice| choice.message.content.clone())
+        .unwrap_or_default())
 }
 
 async fn execute_plan(
     orchestration: OrchestrationPlan,
-    language: Language,
     discussion_thread_id: ChannelId,
     plan_record: &mut PlanRecord,
+    job_handle: JobHandle,
+    budget: Arc<PlanBudget>,
+    cancellation_token: CancellationToken,
     app_state: &AppState,
 ) -> anyhow::Result<(Option<Arc<Mutex<Message>>>, Vec<Context>)> {
     if orchestration.tasks.is_empty() {
         return Ok((None, vec![]));
     }
 
+    let language = plan_record.language.clone();
+    let config = app_state.config();
+    let language_config = config.language(&language);
+    let tool_context = ToolContext {
+        response_language: language_config.geocoding_language(),
+        google_maps_client: app_state.google_maps_client.clone(),
+        language: language.clone(),
+        currency: language_config.currency.clone(),
+        exchange_rates: app_state.exchange_rates.clone(),
+    };
+
+    let job = job_handle.snapshot().await;
+
+    // Tasks completed before the job was resumed are skipped, but still satisfy dependencies.
+    let completed_contexts = job.contexts;
+    let outcomes = Arc::new(
+        completed_contexts
+            .iter()
+            .map(|c| (c.task_id.clone(), TaskOutcome::Completed(c.clone())))
+            .collect::<DashMap<_, _>>(),
+    );
+
+    let remaining_tasks = orchestration
+        .tasks
+        .iter()
+        .filter(|task| !outcomes.contains_key(&task.task_id))
+        .cloned()
+        .collect::<Vec<_>>();
+
     let app_info = app_state.http.get_current_application_info().await?;
     let icon_hash = app_info
         .icon
         .expect("Failed to get application's icon hash.");
     let icon_url = create_avatar_url(app_info.id.get(), icon_hash);
 
-    let description = format!(
-        "- Analysis: {}\n- Number of tasks: {}\n\n🚀 Executing tasks...",
-        &orchestration.analysis,
-        orchestration.tasks.len()
-    );
-
-    let message_args = CreateMessage::new().embed(
-        CreateEmbed::new()
-            .author(CreateEmbedAuthor::new(&app_info.name).icon_url(icon_url))
-            .color(EMBED_COLOR)
-            .description(description)
-            .title("Execution Plan"),
-    );
+    let templates = config.templates.clone();
+    let description = templates.ui(
+        &language,
+        "plan_summary",
+        context! {
+            analysis => orchestration.analysis,
+            task_count => orchestration.tasks.len(),
+        },
+    )?;
+
+    let message_args = CreateMessage::new()
+        .embed(
+            CreateEmbed::new()
+                .author(CreateEmbedAuthor::new(&app_info.name).icon_url(icon_url))
+                .color(EMBED_COLOR)
+                .description(description)
+                .title(templates.ui(&language, "execution_plan_title", context! {})?),
+        )
+        .components(vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(CANCEL_BUTTON_ID)
+                .label(templates.ui(&language, "cancel_button", context! {})?)
+                .style(ButtonStyle::Danger),
+        ])]);
 
     let embed_message = app_state
         .http
@@ -438,24 +1298,30 @@ async fn execute_plan(
 
     let message_mutex = Arc::new(tokio::sync::Mutex::new(embed_message));
 
-    let executors = create_executors(&orchestration.tasks, language, app_state);
+    let executors = create_executors(&remaining_tasks, &language, budget.clone(), app_state)?;
 
     let mut join_set = JoinSet::new();
-    let contexts = Arc::new(DashMap::new());
 
     for mut executor in executors.into_iter() {
+        if cancellation_token.is_cancelled() || budget.state() == BudgetState::Exhausted {
+            break;
+        }
+
         {
             let mut message = message_mutex.lock().await;
             if let Some(original_embed) = message.embeds.first()
                 && let Some(ref original_desc) = original_embed.description
             {
                 let mut new_embed = original_embed.clone();
-                new_embed.description = Some(format!(
-                    "{}\nExecuting {} with {} Agent...",
-                    original_desc,
-                    executor.task_id.clone(),
-                    executor.agent_type
-                ));
+                let executing_task = templates.ui(
+                    &language,
+                    "executing_task",
+                    context! {
+                        task => executor.task_id,
+                        agent => executor.agent_name,
+                    },
+                )?;
+                new_embed.description = Some(format!("{original_desc}\n{executing_task}"));
 
                 let edit_message_args = EditMessage::new().embed(CreateEmbed::from(new_embed));
 
@@ -469,16 +1335,26 @@ async fn execute_plan(
         }
 
         let llm_clients_clone = app_state.llm_clients.clone();
-        let contexts_clone = contexts.clone();
+        let outcomes_clone = outcomes.clone();
         let task_id = executor.task_id.clone();
         let message_mutex_clone = message_mutex.clone();
         let http_clone = app_state.http.clone();
-        let google_maps_client_clone = app_state.google_maps_client.clone();
+        let tool_context = tool_context.clone();
+        let cancellation_token = cancellation_token.clone();
+        let job_handle = job_handle.clone();
+        let templates = templates.clone();
+        let language = language.clone();
 
         join_set.spawn(async move {
-            let clone = contexts_clone.clone();
-
-            match executor.execute(clone, llm_clients_clone.clone()).await {
+            let result = executor
+                .execute(
+                    outcomes_clone.clone(),
+                    llm_clients_clone.clone(),
+                    cancellation_token.clone(),
+                )
+                .await;
+
+            let (context, generation_dumps, tool_outputs) = match result {
                 Ok((choice, dumps)) => {
                     if choice.message.content.is_some() {
                         let mut message = message_mutex_clone.lock().await;
@@ -487,16 +1363,16 @@ async fn execute_plan(
                             && let Some(ref original_desc) = original_embed.description
                         {
                             let mut new_embed = original_embed.clone();
-                            new_embed.description = Some(format!(
-                                "{}\n✅ {} completed.",
-                                original_desc,
-                                task_id.clone()
-                            ));
+                            let task_completed = templates
+                                .ui(&language, "task_completed", context! { task => task_id })
+                                .unwrap_or_else(|_| format!("✅ {task_id}"));
+                            new_embed.description =
+                                Some(format!("{original_desc}\n{task_completed}"));
 
                             let edit_message_args =
                                 EditMessage::new().embed(CreateEmbed::from(new_embed));
 
-                            let new_message = http_clone
+                            match http_clone
                                 .edit_message(
                                     message.channel_id,
                                     message.id,
@@ -504,158 +1380,177 @@ async fn execute_plan(
                                     vec![],
                                 )
                                 .await
-                                .expect("Failed to update message.");
-
-                            *message = new_message;
+                            {
+                                Ok(new_message) => *message = new_message,
+                                Err(e) => tracing::warn!(
+                                    "Failed to report the completion of task {task_id}: {e:?}"
+                                ),
+                            }
                         }
                     }
 
-                    let generation_dumps = {
-                        let dumps_lock = dumps.lock().await;
-                        dumps_lock.clone()
-                    };
-
-                    let context = match executor.agent_type {
-                        Agent::Transport => {
-                            if let Some(reason) = choice.finish_reason
-                                && reason == FinishReason::ToolCalls
-                                && let Some(mut tool_call) = choice
-                                    .message
-                                    .tool_calls
-                                    .as_ref()
-                                    .and_then(|v| v.first().cloned())
+                    let mut tool_outputs = vec![];
+                    let context = if !executor.tools.is_empty()
+                    && let Some(reason) = choice.finish_reason
+                        && reason == FinishReason::ToolCalls
+                        && let Some(mut tool_call) = choice
+                            .message
+                            .tool_calls
+                            .as_ref()
+                            .and_then(|v| v.first().cloned())
+                    {
+                        let mut completed_context = None;
+
+                        let mut assistant_message = choice.message.clone();
+
+                        let mut retry_count = 0;
+                        loop {
+                            if retry_count >= MAX_TOOL_RETRY_COUNT
+                                || cancellation_token.is_cancelled()
                             {
-                                let mut completed_context = None;
+                                break;
+                            }
 
-                                let mut assistant_message = choice.message.clone();
+                            // A prompt that can't be rendered fails the task rather than the plan.
+                            let prompts = executor.system_prompt().and_then(|system_prompt| {
+                                let agent_prompt = executor.agent_prompt(retry_count)?;
+                                let user_prompt = executor.user_prompt(&agent_prompt)?;
+                                Ok((system_prompt, user_prompt.trim().to_string()))
+                            });
+                            let (system_prompt, user_prompt) = match prompts {
+                                Ok(prompts) => prompts,
+                                Err(e) => {
+                                    tracing::error!(
+                                        "Failed to render the prompts of agent {}: {e:?}",
+                                        executor.agent_type
+                                    );
+                                    break;
+                                }
+                            };
 
-                                let maximum_try_prompt = executor.transport_agent_maximum_try.clone().unwrap_or_default();
+                            tracing::info!("Retry system prompt: {}", &system_prompt);
+                            tracing::info!("Retry user prompt: {user_prompt}");
 
-                                let mut retry_count = 0;
-                                loop {
-                                    if retry_count >= MAX_TOOL_RETRY_COUNT {
-                                        break;
-                                    }
+                            let mut message_histories = match build_one_shot_messages(
+                                &system_prompt, &user_prompt)
+                            {
+                                Ok(message_histories) => message_histories,
+                                Err(e) => {
+                                    tracing::error!(
+                                        "Failed to build the messages of agent {}: {e:?}",
+                                        executor.agent_type
+                                    );
+                                    break;
+                                }
+                            };
 
-                                    let user_prompt = executor
-                                        .user_prompt
-                                        .replace("$RETRY_COUNT", &retry_count.to_string())
-                                        .replace("$MAXIMUM_RETRY_REACHED", if retry_count == MAX_TOOL_RETRY_COUNT - 1 {
-                                            &maximum_try_prompt
-                                        } else {
-                                            ""
-                                        })
-                                        .trim()
-                                        .to_string();
-
-                                    tracing::info!("Retry system prompt: {}", &executor.system_prompt);
-                                    tracing::info!("Retry user prompt: {user_prompt}");
-
-                                    let mut message_histories = build_one_shot_messages(
-                                        &executor.system_prompt, &user_prompt)
-                                        .expect("Failed to build one-shot message with system prompt and user prompt.");
-
-                                    let tool_call_id = assistant_message
-                                        .tool_calls
-                                        .as_ref()
-                                        .and_then(|v| v.first())
-                                        .map(|call| call.id.clone())
-                                        .unwrap_or_default();
-
-                                    message_histories.push(ChatCompletionRequestMessage::Assistant(
-                                        ChatCompletionRequestAssistantMessageArgs::default()
-                                            .content(assistant_message.content.clone().unwrap_or_default())
-                                            .tool_calls(assistant_message.tool_calls.clone().unwrap_or_default())
-                                            .build()
-                                            .expect("Failed to add assistant message to message histories.")));
-
-                                    let mut tool_call_failed = false;
-                                    let results = handle_tool_call(
-                                        tool_call.clone(),
-                                        language,
-                                        google_maps_client_clone.clone(),
-                                    )
-                                    .await
-                                    .map_err(|e| {
-                                        tracing::error!("Failed to handle tool call: {e:?}");
-                                        tool_call_failed = true;
-                                    })
-                                    .unwrap_or_default();
-
-                                    if tool_call_failed {
-                                        retry_count += 1;
-                                        continue;
-                                    }
+                            let tool_call_id = assistant_message
+                                .tool_calls
+                                .as_ref()
+                                .and_then(|v| v.first())
+                                .map(|call| call.id.clone())
+                                .unwrap_or_default();
+
+                            message_histories.push(ChatCompletionRequestMessage::Assistant(
+                                ChatCompletionRequestAssistantMessageArgs::default()
+                                    .content(assistant_message.content.clone().unwrap_or_default())
+                                    .tool_calls(assistant_message.tool_calls.clone().unwrap_or_default())
+                                    .build()
+                                    .expect("Failed to add assistant message to message histories.")));
+
+                            let mut tool_call_failed = false;
+                            let results = call_tool(&tool_call, &tool_context)
+                            .await
+                            .map_err(|e| {
+                                tracing::error!("Failed to handle tool call: {e:?}");
+                                tool_call_failed = true;
+                            })
+                            .unwrap_or_default();
+
+                            if tool_call_failed {
+                                retry_count += 1;
+                                continue;
+                            }
 
-                                    let last_message = build_transport_agent_final_message(
-                                        &mut message_histories,
-                                        tool_call_id.clone(),
-                                        results,
-                                        executor.get_transit_time_tool.clone(),
-                                        llm_clients_clone.clone(),
-                                    )
-                                    .await
-                                    .expect("Failed to build final message for transport agent.");
-
-                                    if let Some(reason) = last_message.finish_reason
-                                        && reason != FinishReason::ToolCalls
-                                    {
-                                        completed_context =
-                                            last_message.message.content.map(|s| {
-                                                let ctx = Context {
-                                                    task_id: task_id.clone(),
-                                                    agent_type: executor.agent_type,
-                                                    content: s,
-                                                };
-
-                                                contexts_clone.insert(task_id, ctx.clone());
-
-                                                ctx
-                                            });
-
-                                        break;
-                                    } else {
-                                        tool_call = last_message
-                                            .message
-                                            .tool_calls
-                                            .as_ref()
-                                            .and_then(|v| v.first().cloned())
-                                            .expect("Failed to extract tool call from response.");
-
-                                        assistant_message = last_message.message.clone();
-                                        retry_count += 1;
-                                    }
+                            tool_outputs.push(ToolOutput {
+                                task_id: task_id.clone(),
+                                tool: tool_call.function.name.clone(),
+                                arguments: tool_call.function.arguments.clone(),
+                                output: results.clone(),
+                            });
+
+                            let last_message = tokio::select! {
+                                last_message = build_tool_turn_message(
+                                    &mut message_histories,
+                                    tool_call_id.clone(),
+                                    results,
+                                    &executor.tools,
+                                    &executor.aggregation_model,
+                                    executor.response_format.clone(),
+                                    llm_clients_clone.clone(),
+                                    &dumps,
+                                ) => last_message,
+                                _ = cancellation_token.cancelled() => break,
+                            };
+                            let last_message = match last_message {
+                                Ok(last_message) => last_message,
+                                Err(e) => {
+                                    tracing::error!(
+                                        "Failed to build the message of the tool turn of agent {}: {e:?}",
+                                        executor.agent_type
+                                    );
+                                    break;
                                 }
+                            };
 
-                                completed_context
-                            } else {
-                                choice.message.content.map(|s| {
-                                    let ctx = Context {
+                            if let Some(reason) = last_message.finish_reason
+                                && reason != FinishReason::ToolCalls
+                            {
+                                completed_context =
+                                    last_message.message.content.map(|s| Context {
                                         task_id: task_id.clone(),
-                                        agent_type: executor.agent_type,
+                                        agent_type: executor.agent_type.clone(),
                                         content: s,
-                                    };
+                                    });
 
-                                    contexts_clone.insert(task_id, ctx.clone());
+                                break;
+                            } else {
+                                tool_call = last_message
+                                    .message
+                                    .tool_calls
+                                    .as_ref()
+                                    .and_then(|v| v.first().cloned())
+                                    .expect("Failed to extract tool call from response.");
 
-                                    ctx
-                                })
+                                assistant_message = last_message.message.clone();
+                                retry_count += 1;
                             }
                         }
-                        _ => choice.message.content.map(|s| {
-                            let ctx = Context {
-                                task_id: task_id.clone(),
-                                agent_type: executor.agent_type,
-                                content: s,
-                            };
-
-                            contexts_clone.insert(task_id, ctx.clone());
 
-                            ctx
-                        }),
+                        completed_context
+                    } else {
+                        choice.message.content.map(|s| Context {
+                            task_id: task_id.clone(),
+                            agent_type: executor.agent_type.clone(),
+                            content: s,
+                        })
                     };
 
-                    (context, generation_dumps)
+                    let generation_dumps = dumps.snapshot().await;
+
+                    if let Some(ref ctx) = context
+                        && let Err(e) = job_handle
+                            .record_task(
+                                ctx.clone(),
+                                generation_dumps.clone(),
+                                tool_outputs.clone(),
+                            )
+                            .await
+                    {
+                        tracing::error!("Failed to checkpoint task {}: {e:?}", ctx.task_id);
+                    }
+
+                    (context, generation_dumps, tool_outputs)
                 }
                 Err(e) => {
                     let error_msg = format!(
@@ -663,9 +1558,19 @@ async fn execute_plan(
                         executor.agent_type, e
                     );
                     tracing::error!("{}", &error_msg);
-                    (None, vec![])
+                    (None, vec![], vec![])
                 }
-            }
+            };
+
+            // Dependents wait until every task they depend on has an outcome, so one is recorded
+            // whether the task completed or not.
+            let outcome = match context {
+                Some(ref ctx) => TaskOutcome::Completed(ctx.clone()),
+                None => TaskOutcome::Failed,
+            };
+            outcomes_clone.insert(task_id, outcome);
+
+            (context, generation_dumps, tool_outputs)
         });
 
         tokio::time::sleep(std::time::Duration::from_secs(1)).await;
@@ -673,154 +1578,155 @@ async fn execute_plan(
 
     let results = join_set.join_all().await;
 
+    if budget.state() == BudgetState::Exhausted {
+        tracing::info!(
+            "Plan {} reached its budget after spending ${:.4}.",
+            plan_record.id,
+            budget.spent()
+        );
+
+        let plan_budget_reached = templates.ui(&language, "plan_budget_reached", context! {})?;
+        let mut message = message_mutex.lock().await;
+        append_embed_line(&mut message, &plan_budget_reached, false, &app_state.http).await?;
+    }
+
     let mut dumps = results
         .iter()
-        .flat_map(|(_ctx, d)| (*d).clone())
+        .flat_map(|(_ctx, d, _t)| (*d).clone())
+        .collect::<Vec<_>>();
+    let mut tool_outputs = results
+        .iter()
+        .flat_map(|(_ctx, _d, t)| (*t).clone())
         .collect::<Vec<_>>();
 
     plan_record.dumps.append(&mut dumps);
+    plan_record.tool_outputs.append(&mut tool_outputs);
 
-    let results = results
+    let results = completed_contexts
         .into_iter()
-        .filter_map(|(ctx, _d)| ctx)
+        .chain(results.into_iter().filter_map(|(ctx, _d, _t)| ctx))
         .collect::<Vec<_>>();
 
     Ok((Some(message_mutex), results))
 }
 
-fn create_executors(tasks: &[Task], language: Language, app_state: &AppState) -> Vec<Executor> {
-    let prompt_map = build_prompt_map(app_state);
+fn create_executors(
+    tasks: &[Task],
+    language: &Language,
+    budget: Arc<PlanBudget>,
+    app_state: &AppState,
+) -> anyhow::Result<Vec<Executor>> {
+    let config = app_state.config();
 
     tasks
         .iter()
-        .map(|task| Executor {
-            task_id: task.task_id.clone(),
-            system_prompt: prompt_map[&language][&task.agent].system.clone(),
-            user_prompt: prompt_map[&language][&task.agent]
-                .user
-                .replace("$INSTRUCTION", &task.instruction),
-            agent_type: task.agent,
-            agent_prompt: prompt_map[&language][&task.agent].agent.clone(),
-            dependencies: task.dependencies.clone(),
-            transport_agent: match task.agent {
-                Agent::Transport => {
-                    Some(prompt_map[&language][&task.agent].transport_agent.clone())
-                }
-                _ => None,
-            },
-            transport_agent_maximum_try: match task.agent {
-                Agent::Transport => Some(
-                    prompt_map[&language][&task.agent]
-                        .transport_agent_maximum_retry
-                        .clone(),
-                ),
-                _ => None,
-            },
-            get_transit_time_tool: match task.agent {
-                Agent::Transport => Some(
-                    create_get_transit_time_tool()
-                        .expect("Failed to create get_transit_time tool."),
-                ),
-                _ => None,
-            },
+        .map(|task| {
+            let agent_config = config.agent(&task.agent)?;
+
+            Ok(Executor {
+                task_id: task.task_id.clone(),
+                language: language.clone(),
+                instruction: task.instruction.clone(),
+                agent_type: task.agent.clone(),
+                agent_name: agent_config.name.clone(),
+                dependencies: task.dependencies.clone(),
+                templates: config.templates.clone(),
+                context: "".into(),
+                results: vec![],
+                tools: agent_config
+                    .tools
+                    .iter()
+                    .map(|tool| create_tool(tool))
+                    .collect::<anyhow::Result<Vec<_>>>()?,
+                panel: agent_config.panel.clone(),
+                aggregation_model: agent_config.aggregation_model.clone(),
+                response_format: match agent_config.output {
+                    AgentOutput::Text => None,
+                    AgentOutput::CostEstimate => {
+                        Some(cost_estimate_format(&config.language(language).currency))
+                    }
+                },
+                optional: task.optional,
+                budget: budget.clone(),
+                ranking: config.ranking.clone(),
+                context_window: config.context_window.clone(),
+            })
         })
         .collect()
 }
 
-fn build_prompt_map(app_state: &AppState) -> PromptMap {
-    let languages = [Language::Chinese, Language::Japanese, Language::English];
-    let agent_types = [
-        Agent::Food,
-        Agent::History,
-        Agent::Modern,
-        Agent::Nature,
-        Agent::Transport,
-    ];
-
-    let language_map = [
-        (Language::Chinese, &app_state.config.chinese),
-        (Language::Japanese, &app_state.config.japanese),
-        (Language::English, &app_state.config.english),
-    ]
-    .into_iter()
-    .collect::<HashMap<_, _>>();
-
-    let mut prompt_map = HashMap::new();
-
-    for language in languages.into_iter() {
-        let entry = prompt_map.entry(language).or_insert(HashMap::new());
-
-        for agent in agent_types.into_iter() {
-            match agent {
-                Agent::Food => {
-                    entry.insert(agent, &language_map[&language].food);
-                }
-                Agent::Transport => {
-                    entry.insert(agent, &language_map[&language].transport);
-                }
-                Agent::History => {
-                    entry.insert(agent, &language_map[&language].history);
-                }
-                Agent::Modern => {
-                    entry.insert(agent, &language_map[&language].modern);
-                }
-                Agent::Nature => {
-                    entry.insert(agent, &language_map[&language].nature);
-                }
+/// Takes the results of agents with the `cost_estimate` output out of the synthesis, since they
+/// are rendered as a budget section instead. Results that aren't valid estimates are synthesized
+/// like the others.
+fn extract_cost_estimates(
+    results: Vec<Context>,
+    config: &Configuration,
+) -> (Vec<CostEstimate>, Vec<Context>) {
+    let mut estimates = vec![];
+    let mut remaining = vec![];
+
+    for result in results.into_iter() {
+        let is_estimate = config
+            .agents
+            .get(&result.agent_type)
+            .is_some_and(|agent_config| agent_config.output == AgentOutput::CostEstimate);
+
+        if !is_estimate {
+            remaining.push(result);
+            continue;
+        }
+
+        match serde_json::from_str::<CostEstimate>(&result.content) {
+            Ok(estimate) => estimates.push(estimate),
+            Err(e) => {
+                tracing::warn!(
+                    "Task {} didn't produce a valid cost estimate: {e:?}",
+                    result.task_id
+                );
+                remaining.push(result);
             }
         }
     }
 
-    prompt_map
-        .into_iter()
-        .map(|(k, v)| {
-            let new_v = v
-                .into_iter()
-                .map(|(inner_k, inner_v)| {
-                    (
-                        inner_k,
-                        PromptSet {
-                            system: inner_v.system_prompt.clone(),
-                            user: inner_v.user_prompt.clone(),
-                            agent: language_map[&k].agent.prompt.clone(),
-                            transport_agent: language_map[&k].transport_agent.prompt.clone(),
-                            transport_agent_maximum_retry: language_map[&k]
-                                .transport_agent_maximum_try
-                                .prompt
-                                .clone(),
-                        },
-                    )
-                })
-                .collect::<HashMap<_, _>>();
-
-            (k, new_v)
-        })
-        .collect()
+    (estimates, remaining)
 }
 
+/// Synthesizes the final result from the results of the tasks. The synthesis itself runs even if
+/// the plan's budget is exhausted, since the plan would be wasted otherwise, but the repair of
+/// the schedule doesn't.
 async fn synthesize(
     language: Language,
     results: Vec<Context>,
     plan_record: &mut PlanRecord,
+    budget: Arc<PlanBudget>,
     app_state: &AppState,
 ) -> anyhow::Result<String> {
-    let synthesis_prompt = match language {
-        Language::Chinese => app_state.config.chinese.synthesis.prompt.clone(),
-        Language::Japanese => app_state.config.japanese.synthesis.prompt.clone(),
-        _ => app_state.config.english.synthesis.prompt.clone(),
-    };
+    let config = app_state.config();
+    let generation_log = GenerationLog::new(Some(budget.clone()));
+    let fitter = PromptFitter::new(
+        config.context_window.clone(),
+        config.templates.clone(),
+        app_state.llm_clients.clone(),
+        generation_log.clone(),
+    );
 
-    let results = results
-        .into_iter()
-        .map(|c| (c.task_id.clone(), c))
-        .collect::<HashMap<_, _>>();
+    let (estimates, results) = extract_cost_estimates(results, &config);
+
+    let synthesis_prompt = &config.language(&language).prompts.synthesis.prompt;
+    let results = fit_synthesis_results(
+        results,
+        synthesis_prompt,
+        &plan_record.messages,
+        GEMINI_25_PRO,
+        &fitter,
+    )
+    .await;
 
-    let synthesis_prompt =
-        synthesis_prompt.replace("$RESULTS", &serde_json::to_string_pretty(&results)?);
+    let synthesis_prompt = render_synthesis_prompt(&config.templates, &language, results)?;
 
     tracing::info!("Synthesis prompt: {:?}", &synthesis_prompt);
 
-    let mut messages = plan_record
+    let messages = plan_record
         .messages
         .iter()
         .map(|m| {
@@ -829,65 +1735,73 @@ async fn synthesize(
         })
         .collect::<Vec<_>>();
 
-    messages.push(ChatCompletionRequestMessage::User(
-        ChatCompletionRequestUserMessageArgs::default()
-            .content(synthesis_prompt.as_str())
-            .build()?,
-    ));
-
     plan_record.messages.push(RecordMessage {
         role: Role::User,
         content: Content::Plain(synthesis_prompt.clone()),
     });
 
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(GEMINI_25_PRO)
-        .temperature(TEMPERATURE_LOW)
-        .messages(messages)
-        .response_format(ResponseFormat::JsonSchema { json_schema: ResponseFormatJsonSchema {
-            description: Some("Synthesize the results of subtasks into the final response.".into()),
-            name: "synthesize_tasks".into(),
-            schema: Some(json!({
-                "type": "object",
-                "properties": {
-                    "final_result": {
-                        "type": "string",
-                        "description": "The combined and synthesized result to respond to the user's request."
-                    }
-                },
-                "required": ["final_result"],
-                "additionalProperties": false
-            })),
-            strict: Some(true) } })
-        .build()?;
+    let request = build_synthesis_request(GEMINI_25_PRO, messages.clone(), &synthesis_prompt)?;
 
-    let response = app_state
-        .llm_clients
-        .open_router_clients
-        .get(&Agent::default())
-        .expect("Failed to get the Open Router client for synthesis.")
-        .chat()
-        .create(request)
-        .await;
+    let response = create_chat_completion(
+        &app_state.llm_clients.client(Provider::OpenRouter),
+        Provider::OpenRouter,
+        request,
+        GenerationStage::Synthesis,
+        &app_state.llm_clients,
+        &generation_log,
+    )
+    .await;
+
+    plan_record
+        .dumps
+        .append(&mut generation_log.snapshot().await);
 
     match response {
         Ok(res) => {
             let content = res.choices[0].message.content.clone().unwrap_or_default();
-            let final_result = serde_json::from_str::<FinalResult>(&content)?;
+            let mut itinerary = serde_json::from_str::<Itinerary>(&content)?;
 
             plan_record.messages.push(RecordMessage {
                 role: Role::Assistant,
-                content: Content::Dynamic(serde_json::to_value(&final_result)?),
+                content: Content::Dynamic(serde_json::to_value(&itinerary)?),
             });
 
-            plan_record.dumps.push(GenerationDump {
-                model: LanguageModel::Gemini25Pro,
-                content: final_result.to_string(),
-                is_final_result: true,
-            });
+            tracing::info!("Itinerary: {:?}", &itinerary);
 
-            tracing::info!("Final result: {:?}", &final_result);
-            Ok(final_result.final_result)
+            locate_stops(
+                &mut itinerary,
+                config.language(&language).geocoding_language(),
+                &app_state.google_maps_client,
+            )
+            .await;
+
+            let itinerary = if config.validation.enabled {
+                repair_schedule(
+                    itinerary,
+                    messages,
+                    &synthesis_prompt,
+                    &language,
+                    plan_record,
+                    budget,
+                    app_state,
+                )
+                .await
+            } else {
+                itinerary
+            };
+
+            let mut sections = vec![render_itinerary(&itinerary, &config.templates, &language)?];
+            for estimate in estimates.iter() {
+                sections.push(render_cost_estimate(
+                    estimate,
+                    &config.templates,
+                    &language,
+                )?);
+            }
+
+            plan_record.itinerary = Some(itinerary);
+
+            Ok(sections.join("\n\n"))
         }
         Err(e) => {
             let error_msg = format!("Failed to get final result via API: {:?}", &e);
@@ -897,19 +1811,130 @@ async fn synthesize(
     }
 }
 
+/// Checks the synthesized itinerary for stops that can't work out as scheduled, and has the
+/// synthesis model repair it once if there are any. The original itinerary is kept if the repair
+/// fails or the plan's budget is exhausted.
+async fn repair_schedule(
+    itinerary: Itinerary,
+    mut messages: Vec<ChatCompletionRequestMessage>,
+    synthesis_prompt: &str,
+    language: &Language,
+    plan_record: &mut PlanRecord,
+    budget: Arc<PlanBudget>,
+    app_state: &AppState,
+) -> Itinerary {
+    let conflicts = find_schedule_conflicts(&itinerary, language, app_state).await;
+    if conflicts.is_empty() {
+        tracing::info!("The itinerary has no schedule conflicts.");
+        return itinerary;
+    }
+
+    if budget.state() == BudgetState::Exhausted {
+        tracing::info!(
+            "The plan's budget is exhausted, so the schedule conflicts aren't repaired: {conflicts:?}"
+        );
+        return itinerary;
+    }
+
+    tracing::info!("Repairing schedule conflicts: {conflicts:?}");
+
+    let generation_log = GenerationLog::new(Some(budget));
+
+    let repaired = async {
+        let repair_prompt = app_state.config().templates.render(
+            "validation.repair_prompt",
+            context! { conflicts => describe_conflicts(&conflicts) },
+        )?;
+
+        messages.push(ChatCompletionRequestMessage::User(
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(synthesis_prompt)
+                .build()?,
+        ));
+        messages.push(ChatCompletionRequestMessage::Assistant(
+            ChatCompletionRequestAssistantMessageArgs::default()
+                .content(serde_json::to_string(&itinerary)?)
+                .build()?,
+        ));
+
+        let request = build_synthesis_request(GEMINI_25_PRO, messages, &repair_prompt)?;
+
+        let response = create_chat_completion(
+            &app_state.llm_clients.client(Provider::OpenRouter),
+            Provider::OpenRouter,
+            request,
+            GenerationStage::Repair,
+            &app_state.llm_clients,
+            &generation_log,
+        )
+        .await?;
+
+        let content = response
+            .choices
+            .first()
+            .and_then(|c| c.message.content.clone())
+            .unwrap_or_default();
+        let repaired = serde_json::from_str::<Itinerary>(&content)?;
+        let repaired_value = serde_json::to_value(&repaired)?;
+
+        anyhow::Ok((repair_prompt, repaired, repaired_value))
+    }
+    .await;
+
+    plan_record
+        .dumps
+        .append(&mut generation_log.snapshot().await);
+
+    match repaired {
+        Ok((repair_prompt, mut repaired, repaired_value)) => {
+            plan_record.messages.push(RecordMessage {
+                role: Role::User,
+                content: Content::Plain(repair_prompt),
+            });
+            plan_record.messages.push(RecordMessage {
+                role: Role::Assistant,
+                content: Content::Dynamic(repaired_value),
+            });
+
+            tracing::info!("Repaired itinerary: {:?}", &repaired);
+
+            locate_stops(
+                &mut repaired,
+                app_state.config().language(language).geocoding_language(),
+                &app_state.google_maps_client,
+            )
+            .await;
+
+            repaired
+        }
+        Err(e) => {
+            tracing::error!("Failed to repair the itinerary, so it's posted as is: {e:?}");
+            itinerary
+        }
+    }
+}
+
 async fn insert_record(
     plan_record: PlanRecord,
-    original_message: Message,
-    thread_id: ChannelId,
+    job: &PlanJob,
     app_state: &AppState,
 ) -> anyhow::Result<()> {
     let record_id = plan_record.id.to_string();
 
+    let (Some(thread_id), Some(greeting_message_id)) = (job.thread_id, job.greeting_message_id)
+    else {
+        return Err(anyhow::anyhow!(
+            "Plan job {} has no discussion thread to map the record to.",
+            job.id
+        ));
+    };
+
+    // Upsert rather than insert, since a resumed job may have stored the record already.
     let result = app_state
         .firestore_db
         .fluent()
-        .insert()
-        .into(PLAN_COLLECTION_NAME)
+        .update()
+        .in_col(PLAN_COLLECTION_NAME)
         .document_id(record_id.as_str())
         .object(&plan_record)
         .execute::<PlanRecord>()
@@ -924,15 +1949,15 @@ async fn insert_record(
     let mapping = PlanMapping {
         plan_id: plan_record.id,
         thread_id,
-        channel_id: original_message.channel_id.get().to_string(),
-        original_message_id: original_message.id.get().to_string(),
+        channel_id: job.channel_id.get().to_string(),
+        original_message_id: greeting_message_id.get().to_string(),
     };
 
     let result = app_state
         .firestore_db
         .fluent()
-        .insert()
-        .into(PLAN_MAPPING_COLLECTION_NAME)
+        .update()
+        .in_col(PLAN_MAPPING_COLLECTION_NAME)
         .document_id(record_id.as_str())
         .object(&mapping)
         .execute::<PlanMapping>()
@@ -948,32 +1973,11 @@ async fn insert_record(
 }
 
 async fn send_final_result_message(
-    mut final_result: String,
+    final_result: String,
     thread_id: ChannelId,
     app_state: &AppState,
 ) -> anyhow::Result<()> {
-    let mut character_count = final_result.chars().count();
-    let messages = if character_count > 1000 {
-        let mut container = vec![];
-
-        while character_count > 0 {
-            if character_count >= 1000 {
-                let drained = final_result.chars().take(1000).collect::<String>();
-                container.push(drained);
-                final_result = final_result.chars().skip(1000).collect();
-                character_count = final_result.chars().count();
-            } else {
-                container.push(final_result.clone());
-                character_count = 0;
-            }
-        }
-
-        container
-    } else {
-        vec![final_result]
-    };
-
-    for message in messages.into_iter() {
+    for message in split_message(&final_result, MAX_FINAL_RESULT_MESSAGE_LENGTH).into_iter() {
         let message_args = CreateMessage::new().content(message);
 
         let _ = app_state
@@ -984,131 +1988,3 @@ async fn send_final_result_message(
 
     Ok(())
 }
-
-async fn handle_tool_call(
-    tool_call: ChatCompletionMessageToolCall,
-    language: Language,
-    google_maps_client: Arc<::google_maps::Client>,
-) -> anyhow::Result<Vec<RouteWithDuration>> {
-    let transfer_plan = serde_json::from_str::<TransferPlan>(&tool_call.function.arguments)?;
-
-    tracing::info!("Transfer Plan: {transfer_plan:?}");
-
-    let mut routes = Vec::with_capacity(transfer_plan.routes.len());
-
-    let lat_lngs = Arc::new(DashMap::new());
-
-    for route in transfer_plan.routes.iter() {
-        let (from, to) = get_latitude_and_longitude(
-            route,
-            language,
-            lat_lngs.clone(),
-            google_maps_client.clone(),
-        )
-        .await?;
-        routes.push((from, to, route.by));
-    }
-
-    let routes = routes
-        .into_iter()
-        .zip(transfer_plan.routes.into_iter())
-        .collect::<Vec<_>>();
-
-    let mut results = Vec::with_capacity(routes.len());
-
-    for (values, route) in routes.into_iter() {
-        let (duration, alternative) =
-            get_travel_time(values, language, google_maps_client.clone()).await?;
-        results.push(RouteWithDuration {
-            from: route.from,
-            to: route.to,
-            by: route.by,
-            duration,
-            alternative,
-        });
-    }
-
-    tracing::info!("Direction UI results: {results:?}");
-
-    Ok(results)
-}
-
-async fn build_transport_agent_final_message(
-    message_histories: &mut Vec<ChatCompletionRequestMessage>,
-    tool_call_id: String,
-    results: Vec<RouteWithDuration>,
-    get_transit_time_tool: Option<ChatCompletionTool>,
-    llm_clients: Arc<crate::shared::structs::LLMClients>,
-) -> anyhow::Result<ChatChoice> {
-    let results = serde_json::to_string_pretty(&results)?;
-
-    message_histories.push(ChatCompletionRequestMessage::Tool(
-        ChatCompletionRequestToolMessageArgs::default()
-            .content(ChatCompletionRequestToolMessageContent::Text(results))
-            .tool_call_id(tool_call_id)
-            .build()?,
-    ));
-
-    let mut request = CreateChatCompletionRequestArgs::default();
-    request
-        .model(GEMINI_25_PRO)
-        .temperature(TEMPERATURE_MEDIUM)
-        .messages(message_histories.clone());
-
-    if let Some(tool) = get_transit_time_tool {
-        request.tools(vec![tool]);
-    }
-
-    let client = &*llm_clients
-        .open_router_clients
-        .get(&Agent::Transport)
-        .expect("Failed to get open router client for transport agent.");
-
-    let response = client.chat().create(request.build()?).await?;
-
-    response.choices.first().cloned().ok_or(anyhow::anyhow!(
-        "Failed to generate final message for transport agent."
-    ))
-}
-
-fn create_get_transit_time_tool() -> anyhow::Result<ChatCompletionTool> {
-    Ok(ChatCompletionToolArgs::default()
-                .r#type(ChatCompletionToolType::Function)
-                .function(FunctionObjectArgs::default()
-                    .name("get_transit_time")
-                    .description("Get transit time needed to navigate from one place to another.")
-                    .strict(true)
-                    .parameters(json!({
-                        "type": "object",
-                        "properties": {
-                            "routes": {
-                                "type": "array",
-                                "description": "A list of routes covered in the itinerary.",
-                                "items": {
-                                    "type": "object",
-                                    "properties": {
-                                        "from": {
-                                            "type": "string",
-                                            "description": "The origin or start point of a route. Make sure that it's a valid and correct place name."
-                                        },
-                                        "to": {
-                                            "type": "string",
-                                            "description": "The destination, goal, or end point of a route. Make sure that it's a valid and correct place name."
-                                        },
-                                        "by": {
-                                            "type": "string",
-                                            "description": "The preferred type of transit to take.",
-                                            "enum": ["drive_or_taxi", "public_transport"]
-                                        }
-                                    },
-                                    "required": ["from", "to", "by"],
-                                    "additionalProperties": false
-                                }
-                            }
-                        },
-                        "required": ["routes"],
-                        "additionalProperties": false
-                    }))
-                    .build()?)
-                .build()?)
-}
@@ -1,2 +1,4 @@
 pub mod discord;
+pub mod reload;
 pub mod revise;
+pub mod worker;
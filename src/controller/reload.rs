@@ -0,0 +1,58 @@
+use std::sync::Arc;
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::shared::CONFIG_RELOAD_DELAY;
+use crate::shared::structs::AppState;
+use crate::shared::structs::config::Configuration;
+
+/// Reloads the config whenever its file changes. A config that fails validation is rejected and
+/// the current one stays in place. Settings, admission, provider limits, the response cache and the
+/// path of the exchange rates are set up once at startup, so changes to them only apply after a
+/// restart.
+pub async fn watch_config_file(app_state: AppState) -> anyhow::Result<()> {
+    let config_directory = app_state.settings.config_directory.clone();
+    let config_path = app_state.settings.config_path();
+    let config_file_name = config_path.file_name().map(|name| name.to_os_string());
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    let mut watcher =
+        notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+            Ok(event) => {
+                let is_config_file = event.paths.iter().any(|path| {
+                    path.file_name().map(|name| name.to_os_string()) == config_file_name
+                });
+
+                if is_config_file
+                    && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                {
+                    let _ = sender.send(());
+                }
+            }
+            Err(e) => tracing::warn!("Failed to watch the config file: {e:?}"),
+        })?;
+
+    // Editors often replace the file instead of writing to it, so the directory is watched.
+    watcher.watch(&config_directory, RecursiveMode::NonRecursive)?;
+
+    tracing::info!("Watching {} for changes.", config_path.display());
+
+    while receiver.recv().await.is_some() {
+        // Saving a file can take several writes, so wait for them to settle before reading it.
+        tokio::time::sleep(std::time::Duration::from_millis(CONFIG_RELOAD_DELAY)).await;
+        while receiver.try_recv().is_ok() {}
+
+        match Configuration::load_from_path(&config_path) {
+            Ok(config) => {
+                app_state.config.store(Arc::new(config));
+                tracing::info!("Reloaded the config from {}.", config_path.display());
+            }
+            Err(e) => {
+                tracing::error!("Kept the current config because the new one can't be used: {e}");
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use crate::controller::discord::plan::run_plan_job;
+use crate::shared::JOB_POLL_INTERVAL;
+use crate::shared::structs::AppState;
+use crate::shared::utility::job::{claim_job, load_resumable_jobs};
+
+/// Periodically picks up unfinished plan jobs whose owner has stopped renewing their lease,
+/// e.g. because the instance running them was restarted.
+pub async fn resume_plan_jobs(app_state: AppState) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(JOB_POLL_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        let jobs = match load_resumable_jobs(&app_state.firestore_db).await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                tracing::error!("Failed to load resumable jobs: {e:?}");
+                continue;
+            }
+        };
+
+        for mut job in jobs.into_iter() {
+            match claim_job(&mut job, &app_state.firestore_db).await {
+                Ok(true) => {
+                    tracing::info!("Resuming plan job {} at stage {}.", job.id, job.status);
+                    let app_state = app_state.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = run_plan_job(job, app_state).await {
+                            tracing::error!("Failed to resume plan job: {e:?}");
+                        }
+                    });
+                }
+                Ok(false) => {}
+                Err(e) => tracing::error!("Failed to claim plan job {}: {e:?}", job.id),
+            }
+        }
+    }
+}
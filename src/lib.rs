@@ -0,0 +1,3 @@
+//! The building blocks shared by the bot and its tooling, e.g. the evaluation harness.
+
+pub mod shared;
@@ -1,34 +1,58 @@
 use std::sync::Arc;
 
-use axum::{Router, middleware::from_fn, routing::post};
+use arc_swap::ArcSwap;
+use axum::{Router, middleware::from_fn_with_state, routing::post};
+use clap::Parser;
+use dashmap::DashMap;
 use firestore::{FirestoreDb, FirestoreDbOptions};
 use serenity::all::{ApplicationId, Http};
-use tracing::Level;
+use travel_agency::shared;
 
 use crate::{
-    controller::discord::interaction::{COMMAND_REGISTRY, handle_interaction},
+    controller::{
+        discord::interaction::{COMMAND_REGISTRY, handle_interaction},
+        reload::watch_config_file,
+        worker::resume_plan_jobs,
+    },
     shared::{
         USER_AGENT,
         middleware::discord_validation::validate_interaction,
-        structs::{AppState, LLMClients, config::Configuration},
+        structs::{
+            AppState, LLMClients,
+            admission::AdmissionController,
+            config::{
+                Configuration,
+                settings::{Settings, SettingsLayer},
+            },
+        },
+        utility::currency::ExchangeRates,
     },
 };
 
 mod controller;
-mod shared;
+
+#[derive(Parser, Debug)]
+#[command(about = "A Discord bot that plans trips with a team of agents.")]
+struct Args {
+    #[command(flatten)]
+    settings: SettingsLayer,
+    /// Prints the resolved settings with secrets redacted, then exits.
+    #[arg(long)]
+    print_config: bool,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let log_level = match std::env::var("LOG_LEVEL").unwrap_or_default().as_str() {
-        "TRACE" => Level::TRACE,
-        "INFO" => Level::INFO,
-        "WARN" => Level::WARN,
-        "ERROR" => Level::ERROR,
-        _ => Level::DEBUG,
-    };
+    let args = Args::parse();
+    let settings = Settings::load(args.settings)?;
+
+    if args.print_config {
+        print!("{}", settings.to_redacted_toml()?);
+        return Ok(());
+    }
 
     let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_max_level(log_level)
+        .with_max_level(settings.max_log_level())
         .finish();
 
     if let Err(e) = tracing::subscriber::set_global_default(subscriber) {
@@ -44,42 +68,65 @@ async fn main() -> anyhow::Result<()> {
 
     tracing::info!("Available commands: {:?}", &available_commands);
 
-    let bot_token = std::env::var("BOT_TOKEN")?;
-
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to initialize TLS.");
 
-    let sa_path = Configuration::config_directory()?.join(std::env::var("SA_FILE_NAME")?);
+    let missing_api_keys = settings.api_keys.missing();
+    if !missing_api_keys.is_empty() {
+        tracing::warn!("No API key was given for {}.", missing_api_keys.join(", "));
+    }
+
+    let discord_http = Arc::new(Http::new(settings.bot_token.expose()));
+    discord_http.set_application_id(ApplicationId::new(settings.application_id));
 
-    let discord_http = Arc::new(Http::new(&bot_token));
-    discord_http.set_application_id(ApplicationId::new(
-        std::env::var("APPLICATION_ID")?.parse::<u64>()?,
+    let config = Configuration::load_from_config_file(&settings.config_path())?;
+    let llm_clients = Arc::new(LLMClients::new(
+        &config,
+        &settings.api_keys,
+        &settings.config_directory,
+    ));
+    let admission = Arc::new(AdmissionController::new(&config.admission));
+    let exchange_rates = Arc::new(ExchangeRates::new(
+        &config.exchange_rates,
+        &settings.config_directory,
     ));
 
     let app_state = AppState {
-        config: Configuration::load_from_config_file()?,
-        llm_clients: Arc::new(LLMClients::new()),
+        config: Arc::new(ArcSwap::from_pointee(config)),
+        settings: Arc::new(settings.clone()),
+        llm_clients,
         http_client: reqwest::Client::builder().user_agent(USER_AGENT).build()?,
         http: discord_http,
         firestore_db: FirestoreDb::with_options_service_account_key_file(
-            FirestoreDbOptions::new(std::env::var("PROJECT_ID")?),
-            sa_path,
+            FirestoreDbOptions::new(settings.project_id.clone()),
+            settings.service_account_path(),
         )
         .await?,
-        google_maps_client: Arc::new(::google_maps::Client::try_new(std::env::var(
-            "GOOGLE_API_KEY",
-        )?)?),
+        google_maps_client: Arc::new(::google_maps::Client::try_new(
+            settings.google_api_key.expose(),
+        )?),
+        pending_approvals: Arc::new(DashMap::new()),
+        running_plans: Arc::new(DashMap::new()),
+        admission,
+        exchange_rates,
     };
 
+    tokio::spawn(resume_plan_jobs(app_state.clone()));
+
+    let reload_state = app_state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = watch_config_file(reload_state).await {
+            tracing::error!("Stopped watching the config file: {e:?}");
+        }
+    });
+
     let app = Router::new()
         .route("/api/discord/interaction", post(handle_interaction))
-        .layer(from_fn(validate_interaction))
+        .layer(from_fn_with_state(app_state.clone(), validate_interaction))
         .with_state(app_state);
 
-    let server_bind_point = std::env::var("SERVER_BIND_POINT")?;
-    let port = std::env::var("PORT")?;
-    let server_bind_point = format!("{server_bind_point}:{port}");
+    let server_bind_point = format!("{}:{}", settings.server_bind_point, settings.port);
 
     let listener = tokio::net::TcpListener::bind(&server_bind_point).await?;
     axum::serve(listener, app).await?;
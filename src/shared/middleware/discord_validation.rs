@@ -1,15 +1,19 @@
 use axum::{
     body::{Body, Bytes},
+    extract::State,
     http::HeaderMap,
     middleware::Next,
     response::{IntoResponse, Response},
 };
 use http_body_util::BodyExt;
 
+use crate::shared::structs::AppState;
+
 const SIGNATURE_HEADER: &str = "X-Signature-Ed25519";
 const TIMESTAMP_HEADER: &str = "X-Signature-Timestamp";
 
 pub async fn validate_interaction(
+    State(app_state): State<AppState>,
     headers: HeaderMap,
     request: axum::extract::Request,
     next: Next,
@@ -29,7 +33,9 @@ pub async fn validate_interaction(
         .and_then(|v| v.to_str().map(ToString::to_string).ok())
         .unwrap_or_default();
 
-    match buffer_request_body(request, signature, timestamp).await {
+    let public_key = &app_state.settings.application_public_key;
+
+    match buffer_request_body(request, public_key, signature, timestamp).await {
         Ok(request) => next.run(request).await,
         Err(e) => e,
     }
@@ -37,6 +43,7 @@ pub async fn validate_interaction(
 
 async fn buffer_request_body(
     request: axum::extract::Request,
+    public_key: &str,
     signature: String,
     timestamp: String,
 ) -> Result<axum::extract::Request, Response> {
@@ -52,17 +59,19 @@ async fn buffer_request_body(
         })?
         .to_bytes();
 
-    match validate(bytes, signature, timestamp) {
+    match validate(bytes, public_key, signature, timestamp) {
         Ok(bytes) => Ok(axum::extract::Request::from_parts(parts, Body::from(bytes))),
         Err(e) => Err(e),
     }
 }
 
 #[allow(clippy::result_large_err)]
-fn validate(bytes: Bytes, signature: String, timestamp: String) -> Result<Bytes, Response> {
-    let public_key =
-        std::env::var("APPLICATION_PUBLIC_KEY").expect("Failed to get application public key.");
-
+fn validate(
+    bytes: Bytes,
+    public_key: &str,
+    signature: String,
+    timestamp: String,
+) -> Result<Bytes, Response> {
     let body = bytes.to_vec();
 
     match String::from_utf8(body) {
@@ -72,7 +81,7 @@ fn validate(bytes: Bytes, signature: String, timestamp: String) -> Result<Bytes,
             let signature_bytes =
                 hex::decode(&signature).expect("Failed to decode public key from hex value.");
             let public_key_bytes =
-                hex::decode(&public_key).expect("Failed to decode public key from hex value.");
+                hex::decode(public_key).expect("Failed to decode public key from hex value.");
 
             let result =
                 nacl::sign::verify(&signature_bytes, message.as_bytes(), &public_key_bytes);
@@ -17,6 +17,9 @@ pub const TEMPERATURE_HIGH: f32 = 1.0;
 
 pub const PLAN_COLLECTION_NAME: &str = "travel_agency_plans";
 pub const PLAN_MAPPING_COLLECTION_NAME: &str = "travel_agency_plan_mappings";
+pub const PLACE_COLLECTION_NAME: &str = "travel_agency_places";
+pub const JOB_COLLECTION_NAME: &str = "travel_agency_jobs";
+pub const USAGE_COLLECTION_NAME: &str = "travel_agency_usage";
 
 pub const GPT_41: &str = "gpt-4.1";
 pub const GEMINI_25_PRO: &str = "google/gemini-2.5-pro";
@@ -41,6 +44,8 @@ pub const MISTRAL_LARGE: &str = "mistralai/mistral-large-2411";
 pub const MINIMAX_M1: &str = "minimax/minimax-m1";
 pub const ERNIE_45_300B_A47B: &str = "baidu/ernie-4.5-300b-a47b";
 
+pub const PLACES_TEXT_SEARCH_ENDPOINT: &str = "https://places.googleapis.com/v1/places:searchText";
+
 pub const DISCORD_ROOT_ENDPOINT: &str = "https://discord.com/api/v10";
 pub const DISCORD_INTERACTION_CALLBACK_ENDPOINT: &str =
     "/interactions/$INTERACTION_ID/$INTERACTION_TOKEN/callback";
@@ -49,6 +54,37 @@ pub const DISCORD_INTERACTION_EDIT_ENDPOINT: &str =
 pub const DISCORD_CREATE_THREAD_ENDPOINT: &str =
     "/channels/$CHANNEL_ID/messages/$MESSAGE_ID/threads";
 
+/// The language used when the prompt's language isn't configured.
+pub const DEFAULT_LANGUAGE: &str = "en";
 pub const EMBED_COLOR: Colour = Colour::from_rgb(147, 156, 149);
 
 pub const MAX_TOOL_RETRY_COUNT: u8 = 5;
+pub const MAX_CENTRALITY_AREAS: usize = 6;
+pub const MAX_CENTRALITY_PLACES: usize = 12;
+/// Routes looked up per centrality request, shared between its areas.
+pub const MAX_CENTRALITY_ROUTES: usize = 30;
+/// Seconds a centrality request may take before the remaining routes are given up on.
+pub const CENTRALITY_TIMEOUT: u64 = 20;
+pub const MAX_CLIMATE_PLACES: usize = 8;
+pub const MAX_CALENDAR_DATES: usize = 31;
+/// Stops of an itinerary located on the map and checked against opening hours and travel times.
+pub const MAX_ITINERARY_STOPS: usize = 40;
+/// Kilometres from a place to the closest destination of the climate dataset.
+pub const MAX_CLIMATE_STATION_DISTANCE: f64 = 150.0;
+/// Characters of the final result sent per message.
+pub const MAX_FINAL_RESULT_MESSAGE_LENGTH: usize = 1000;
+pub const MAX_AUTOCOMPLETE_CHOICES: usize = 10;
+/// Milliseconds place suggestions may take, since Discord drops autocomplete responses after
+/// three seconds.
+pub const AUTOCOMPLETE_TIMEOUT: u64 = 2000;
+/// Seconds until cached place suggestions are geocoded again.
+pub const PLACE_CACHE_TTL: i64 = 30 * 24 * 60 * 60;
+pub const MIN_AUTOCOMPLETE_QUERY_LENGTH: usize = 2;
+
+pub const JOB_LEASE_DURATION: i64 = 120;
+pub const JOB_POLL_INTERVAL: u64 = 60;
+/// Milliseconds to wait after the config file changed before it's reloaded.
+pub const CONFIG_RELOAD_DELAY: u64 = 500;
+pub const QUEUE_POSITION_REFRESH_INTERVAL: u64 = 5;
+/// Interaction tokens are valid for 15 minutes; leave some leeway before giving up on them.
+pub const INTERACTION_TOKEN_LIFETIME: i64 = 60 * 14;
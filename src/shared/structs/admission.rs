@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use dashmap::DashMap;
+use serenity::all::{GuildId, UserId};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
+
+use crate::shared::structs::config::Admission;
+
+/// Decides when a plan may start, so that simultaneous requests don't exhaust provider quotas.
+#[derive(Debug)]
+pub struct AdmissionController {
+    plan_slots: Arc<Semaphore>,
+    queue: Arc<Mutex<VecDeque<Uuid>>>,
+    user_cooldowns: DashMap<UserId, i64>,
+    guild_cooldowns: DashMap<GuildId, i64>,
+    user_cooldown: i64,
+    guild_cooldown: i64,
+}
+
+/// The remaining seconds of a cooldown that prevented a plan from starting.
+#[derive(Debug, Clone, Copy)]
+pub enum Cooldown {
+    User(i64),
+    Guild(i64),
+}
+
+/// A plan waiting for a free slot. Leaves the queue when dropped.
+#[derive(Debug)]
+pub struct QueueTicket {
+    plan_id: Uuid,
+    plan_slots: Arc<Semaphore>,
+    queue: Arc<Mutex<VecDeque<Uuid>>>,
+}
+
+impl AdmissionController {
+    pub fn new(admission: &Admission) -> Self {
+        AdmissionController {
+            plan_slots: Arc::new(Semaphore::new(admission.max_concurrent_plans.max(1))),
+            queue: Arc::new(Mutex::new(VecDeque::new())),
+            user_cooldowns: DashMap::new(),
+            guild_cooldowns: DashMap::new(),
+            user_cooldown: admission.user_cooldown as i64,
+            guild_cooldown: admission.guild_cooldown as i64,
+        }
+    }
+
+    /// Starts the cooldowns of the user and the guild, unless one of them is still active.
+    pub fn try_start_cooldown(
+        &self,
+        user_id: UserId,
+        guild_id: Option<GuildId>,
+    ) -> Option<Cooldown> {
+        let now = chrono::Utc::now().timestamp();
+
+        if let Some(last_started) = self.user_cooldowns.get(&user_id)
+            && now - *last_started < self.user_cooldown
+        {
+            return Some(Cooldown::User(self.user_cooldown - (now - *last_started)));
+        }
+
+        if let Some(guild_id) = guild_id
+            && let Some(last_started) = self.guild_cooldowns.get(&guild_id)
+            && now - *last_started < self.guild_cooldown
+        {
+            return Some(Cooldown::Guild(self.guild_cooldown - (now - *last_started)));
+        }
+
+        self.user_cooldowns.insert(user_id, now);
+        if let Some(guild_id) = guild_id {
+            self.guild_cooldowns.insert(guild_id, now);
+        }
+
+        None
+    }
+
+    pub fn enqueue(&self, plan_id: Uuid) -> QueueTicket {
+        self.queue
+            .lock()
+            .expect("Failed to lock the plan queue.")
+            .push_back(plan_id);
+
+        QueueTicket {
+            plan_id,
+            plan_slots: self.plan_slots.clone(),
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl QueueTicket {
+    /// The 1-based position in the queue, or `None` once the plan has been admitted.
+    pub fn position(&self) -> Option<usize> {
+        self.queue
+            .lock()
+            .expect("Failed to lock the plan queue.")
+            .iter()
+            .position(|id| *id == self.plan_id)
+            .map(|index| index + 1)
+    }
+
+    /// Waits for a free slot. The plan keeps the slot until the permit is dropped.
+    pub async fn admitted(&self) -> anyhow::Result<OwnedSemaphorePermit> {
+        let permit = self.plan_slots.clone().acquire_owned().await?;
+        self.leave_queue();
+        Ok(permit)
+    }
+
+    fn leave_queue(&self) {
+        self.queue
+            .lock()
+            .expect("Failed to lock the plan queue.")
+            .retain(|id| *id != self.plan_id);
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.leave_queue();
+    }
+}
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// What a trip is expected to cost, as produced by agents with the `cost_estimate` output.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CostEstimate {
+    /// An ISO 4217 code such as `JPY`.
+    pub currency: String,
+    pub days: Vec<DayCost>,
+    /// The assumptions behind the estimate, e.g. the class of hotel.
+    pub notes: String,
+}
+
+/// The costs of one day of the trip by category, in the currency of the estimate.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct DayCost {
+    pub day: u32,
+    pub food: f64,
+    pub transit: f64,
+    pub admission: f64,
+    pub lodging: f64,
+}
+
+impl DayCost {
+    pub fn total(&self) -> f64 {
+        self.food + self.transit + self.admission + self.lodging
+    }
+}
+
+impl CostEstimate {
+    /// The costs of all days added up by category.
+    pub fn totals(&self) -> DayCost {
+        self.days
+            .iter()
+            .fold(DayCost::default(), |totals, day| DayCost {
+                day: 0,
+                food: totals.food + day.food,
+                transit: totals.transit + day.transit,
+                admission: totals.admission + day.admission,
+                lodging: totals.lodging + day.lodging,
+            })
+    }
+}
@@ -0,0 +1,72 @@
+use google_maps::LatLng;
+use serde::{Deserialize, Serialize};
+
+use crate::shared::structs::google_maps::TransferMethod;
+
+/// The final result of a plan as produced by synthesis.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Itinerary {
+    /// The introduction, tips and everything else that isn't a stop, in markdown. Plans made
+    /// before itineraries were structured only have this as `final_result`.
+    #[serde(alias = "final_result")]
+    pub narrative: String,
+    #[serde(default)]
+    pub days: Vec<ItineraryDay>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ItineraryDay {
+    pub day: u32,
+    /// In the `YYYY-MM-DD` format, if the user gave dates.
+    pub date: Option<String>,
+    pub title: String,
+    pub stops: Vec<ItineraryStop>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ItineraryStop {
+    /// In the `HH:MM` format.
+    pub start: Option<String>,
+    pub end: Option<String>,
+    pub place: String,
+    pub category: StopCategory,
+    pub notes: String,
+    /// How to get here from the previous stop of the day.
+    pub transit: Option<TransitLeg>,
+    /// Geocoded after synthesis, since the model's coordinates can't be trusted.
+    #[serde(default)]
+    pub location: Option<LatLng>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct TransitLeg {
+    pub by: TransferMethod,
+    pub minutes: u32,
+    pub notes: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopCategory {
+    Food,
+    Culture,
+    Nature,
+    Shopping,
+    Entertainment,
+    Lodging,
+    Other,
+}
+
+impl StopCategory {
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            StopCategory::Food => "🍽️",
+            StopCategory::Culture => "🏛️",
+            StopCategory::Nature => "🌳",
+            StopCategory::Shopping => "🛍️",
+            StopCategory::Entertainment => "🎭",
+            StopCategory::Lodging => "🏨",
+            StopCategory::Other => "📍",
+        }
+    }
+}
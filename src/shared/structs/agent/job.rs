@@ -0,0 +1,68 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, MessageId, UserId};
+use uuid::Uuid;
+
+use crate::shared::structs::agent::{Context, Language, OrchestrationPlan, record::PlanRecord};
+
+/// A `/plan` request persisted together with its checkpoints, so that it can be resumed by
+/// another instance when the one running it goes away.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlanJob {
+    pub id: Uuid,
+    pub status: JobStatus,
+    pub user_id: UserId,
+    pub channel_id: ChannelId,
+    pub interaction_token: String,
+    pub user_prompt: String,
+    /// The language of the user's Discord client, used for messages sent before the plan's own
+    /// language is known.
+    #[serde(default)]
+    pub locale: Language,
+    pub created_at: i64,
+    pub owner: Uuid,
+    pub lease_expires_at: i64,
+    pub greeting_message_id: Option<MessageId>,
+    pub thread_id: Option<ChannelId>,
+    pub orchestration: Option<OrchestrationPlan>,
+    pub plan_record: Option<PlanRecord>,
+    pub contexts: Vec<Context>,
+    pub final_result: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Received,
+    Orchestrated,
+    Approved,
+    Synthesized,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+impl JobStatus {
+    pub const UNFINISHED: [JobStatus; 4] = [
+        JobStatus::Received,
+        JobStatus::Orchestrated,
+        JobStatus::Approved,
+        JobStatus::Synthesized,
+    ];
+}
+
+impl Display for JobStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let string = match self {
+            JobStatus::Received => "Received",
+            JobStatus::Orchestrated => "Orchestrated",
+            JobStatus::Approved => "Approved",
+            JobStatus::Synthesized => "Synthesized",
+            JobStatus::Completed => "Completed",
+            JobStatus::Cancelled => "Cancelled",
+            JobStatus::Failed => "Failed",
+        };
+
+        write!(f, "{string}")
+    }
+}
@@ -3,22 +3,38 @@ use std::{collections::HashMap, fmt::Display, sync::Arc};
 use async_openai::types::{
     ChatChoice, ChatCompletionRequestMessage, ChatCompletionRequestProvider, ChatCompletionTool,
     ChatCompletionToolChoiceOption, CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
-    CreateChatCompletionResponse,
+    ResponseFormat,
 };
 use async_trait::async_trait;
 use dashmap::DashMap;
+use minijinja::context;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use tokio::{sync::Mutex, task::JoinSet};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
 
 use crate::shared::{
-    DEEP_SEEK_R1, DEEP_SEEK_V3, DOUBAO_SEED_16, ERNIE_45_300B_A47B, GEMINI_25_PRO, GLM_45,
-    GPT_5_CHAT_LATEST, GPT_41, GPT5, GROK_3, GROK_4, KIMI_K2, MISTRAL_LARGE, OPUS_41,
-    QWEN_3_235B_A22B, QWEN_MAX, SONNET_4, TEMPERATURE_HIGH, TEMPERATURE_MEDIUM,
-    structs::{LLMClients, agent::record::GenerationDump},
-    utility::build_one_shot_messages,
+    DEEP_SEEK_R1, DEEP_SEEK_V3, DEFAULT_LANGUAGE, DOUBAO_SEED_16, ERNIE_45_300B_A47B,
+    GEMINI_25_PRO, GLM_45, GPT_5_CHAT_LATEST, GPT_41, GPT5, GROK_3, GROK_4, KIMI_K2,
+    MAX_TOOL_RETRY_COUNT, MISTRAL_LARGE, OPUS_41, QWEN_3_235B_A22B, QWEN_MAX, SONNET_4,
+    TEMPERATURE_HIGH, TEMPERATURE_MEDIUM,
+    structs::{
+        LLMClients,
+        agent::record::GenerationStage,
+        budget::{BudgetState, PlanBudget},
+        config::{ContextWindow, Ranking, templates::PromptTemplates},
+    },
+    utility::{
+        build_one_shot_messages,
+        context_window::{PromptFitter, context_window_of, smallest_panel_context_window},
+        llm::{GenerationLog, create_chat_completion},
+        ranking::{Candidate, rank_candidates},
+    },
 };
 
+pub mod estimate;
+pub mod itinerary;
+pub mod job;
 pub mod record;
 
 pub type TaskId = String;
@@ -61,28 +77,21 @@ pub static MODEL_NAME_MAP: Lazy<DashMap<LanguageModel, String>> = Lazy::new(|| {
 pub trait Taskable {
     async fn execute(
         &mut self,
-        contexts: Arc<DashMap<TaskId, Context>>,
+        outcomes: Arc<DashMap<TaskId, TaskOutcome>>,
         llm_clients: Arc<LLMClients>,
-    ) -> anyhow::Result<(ChatChoice, Arc<Mutex<Vec<GenerationDump>>>)>;
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<(ChatChoice, GenerationLog)>;
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Hash, Default)]
-pub enum Agent {
-    #[default]
-    Food,
-    Transport,
-    History,
-    Modern,
-    Nature,
-}
+/// The key of one of the agents in the config, e.g. `food`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+#[serde(transparent)]
+pub struct Agent(String);
 
-#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Language {
-    English,
-    Japanese,
-    Chinese,
-    Other,
-}
+/// A BCP-47 language tag such as `ja` or `zh-TW`, which selects one of the configured languages.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Language(String);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, Default)]
 pub enum LanguageModel {
@@ -121,6 +130,18 @@ pub enum LanguageModel {
     Ernie45300BA47B,
 }
 
+/// The API a language model is served through, which is what concurrency limits apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Provider {
+    OpenAI,
+    OpenRouter,
+    VolcEngine,
+    Moonshot,
+    StepFun,
+    Zhipu,
+    DeepSeek,
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct LanguageTriageArguments {
     pub language: Language,
@@ -140,18 +161,35 @@ pub struct Task {
     pub agent: Agent,
     pub dependencies: Vec<TaskId>,
     pub instruction: String,
+    /// Optional tasks are the first to be skipped when the plan runs low on budget.
+    #[serde(default)]
+    pub optional: bool,
 }
 
 pub struct Executor {
     pub task_id: TaskId,
-    pub system_prompt: String,
-    pub user_prompt: String,
+    pub language: Language,
+    pub instruction: String,
     pub agent_type: Agent,
-    pub agent_prompt: String,
+    /// The name of the agent shown to users, e.g. `Food`.
+    pub agent_name: String,
     pub dependencies: Vec<TaskId>,
-    pub transport_agent: Option<String>,
-    pub transport_agent_maximum_try: Option<String>,
-    pub get_transit_time_tool: Option<ChatCompletionTool>,
+    pub templates: Arc<PromptTemplates>,
+    /// The dependency contexts and the candidates picked for aggregation, kept so that the prompts
+    /// can be rendered again for tool turns.
+    pub context: String,
+    pub results: Vec<String>,
+    /// The tools the aggregator may call. Agents with tools run the tool loop after aggregating.
+    pub tools: Vec<ChatCompletionTool>,
+    /// The models to fan out to. All models are used if it's empty.
+    pub panel: Vec<String>,
+    pub aggregation_model: String,
+    /// Makes the aggregator answer with structured output, such as a cost estimate.
+    pub response_format: Option<ResponseFormat>,
+    pub optional: bool,
+    pub budget: Arc<PlanBudget>,
+    pub ranking: Ranking,
+    pub context_window: ContextWindow,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -161,22 +199,189 @@ pub struct Context {
     pub content: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
-pub struct FinalResult {
-    pub final_result: String,
+/// How a task of the plan ended, so that the tasks depending on it know when to go on.
+#[derive(Debug, Clone)]
+pub enum TaskOutcome {
+    Completed(Context),
+    /// The task failed or was skipped, so its dependents go on without its context.
+    Failed,
+}
+
+impl Agent {
+    /// Agent keys are lowercase, so the `Food` of older plans becomes `food`.
+    pub fn new(key: &str) -> Self {
+        Agent(key.trim().to_lowercase())
+    }
+
+    pub fn key(&self) -> &str {
+        &self.0
+    }
 }
 
 impl Display for Agent {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let string = match self {
-            Agent::Food => "Food",
-            Agent::Transport => "Transport",
-            Agent::History => "History",
-            Agent::Modern => "Modern",
-            Agent::Nature => "Nature",
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Agent {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Agent::new(&String::deserialize(deserializer)?))
+    }
+}
+
+impl Language {
+    /// Normalizes the case of a tag, e.g. `ZH-tw` becomes `zh-TW` and `zh-hant` becomes
+    /// `zh-Hant`. Discord locales such as `en-US` are tags as well.
+    pub fn new(tag: &str) -> Self {
+        let normalized = tag
+            .trim()
+            .replace('_', "-")
+            .split('-')
+            .enumerate()
+            .map(|(index, subtag)| match (index, subtag.len()) {
+                (0, _) => subtag.to_lowercase(),
+                (_, 2) => subtag.to_uppercase(),
+                (_, 4) => {
+                    let mut chars = subtag.chars();
+                    chars.next().map_or(String::new(), |first| {
+                        first
+                            .to_uppercase()
+                            .chain(chars.flat_map(char::to_lowercase))
+                            .collect()
+                    })
+                }
+                _ => subtag.to_lowercase(),
+            })
+            .collect::<Vec<_>>()
+            .join("-");
+
+        Language(normalized)
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.0
+    }
+
+    /// The language without script or region, e.g. `zh` for `zh-TW`.
+    pub fn primary(&self) -> &str {
+        self.0.split('-').next().unwrap_or_default()
+    }
+
+    /// Picks the closest of the available languages: the same tag, or else the first one with
+    /// the same primary language, e.g. `zh-TW` for `zh-HK`.
+    pub fn closest<'a>(
+        &self,
+        available: impl IntoIterator<Item = &'a Language>,
+    ) -> Option<&'a Language> {
+        let mut same_primary = None;
+
+        for language in available.into_iter() {
+            if language == self {
+                return Some(language);
+            }
+
+            if same_primary.is_none() && language.primary() == self.primary() {
+                same_primary = Some(language);
+            }
+        }
+
+        same_primary
+    }
+}
+
+impl Default for Language {
+    fn default() -> Self {
+        Language::new(DEFAULT_LANGUAGE)
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Language {
+    /// Also accepts the language names stored by older versions.
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let tag = String::deserialize(deserializer)?;
+
+        let language = match tag.as_str() {
+            "English" => Language::new("en"),
+            "Japanese" => Language::new("ja"),
+            "Chinese" => Language::new("zh-TW"),
+            "Other" => Language::new("und"),
+            tag => Language::new(tag),
         };
 
-        write!(f, "{string}")
+        Ok(language)
+    }
+}
+
+impl LanguageModel {
+    pub fn from_name(model_name: &str) -> Option<Self> {
+        MODEL_NAME_MAP
+            .iter()
+            .find(|entry| entry.value() == model_name)
+            .map(|entry| *entry.key())
+    }
+
+    /// The number of tokens the model accepts, prompt and response included.
+    pub fn context_window(&self) -> usize {
+        match self {
+            LanguageModel::Gpt5Chat => 128_000,
+            LanguageModel::Gpt41 => 1_047_576,
+            LanguageModel::Gpt5 => 400_000,
+            LanguageModel::Sonnet4 | LanguageModel::Opus41 => 200_000,
+            LanguageModel::Gemini25Pro => 1_048_576,
+            LanguageModel::Grok3 => 131_072,
+            LanguageModel::Grok4 => 256_000,
+            LanguageModel::DeepSeekV3 | LanguageModel::DeepSeekR1 => 65_536,
+            LanguageModel::Glm45 => 128_000,
+            LanguageModel::Step216k => 16_384,
+            LanguageModel::QwenMax => 32_768,
+            LanguageModel::Qwen3235BA22B => 131_072,
+            LanguageModel::DoubaoSeed16 => 256_000,
+            LanguageModel::KimiK2 => 131_072,
+            LanguageModel::MistralLarge => 131_072,
+            LanguageModel::MiniMaxM1 => 1_000_000,
+            LanguageModel::Ernie45300BA47B => 123_000,
+        }
+    }
+
+    pub fn provider(&self) -> Provider {
+        match self {
+            m if OPENAI_MODELS.contains(m) => Provider::OpenAI,
+            LanguageModel::DoubaoSeed16 => Provider::VolcEngine,
+            LanguageModel::Glm45 => Provider::Zhipu,
+            LanguageModel::Step216k => Provider::StepFun,
+            LanguageModel::DeepSeekV3 | LanguageModel::DeepSeekR1 => Provider::DeepSeek,
+            _ => Provider::OpenRouter,
+        }
+    }
+}
+
+impl Provider {
+    /// The provider serving a model, falling back to Open Router for unknown models.
+    pub fn for_model(model_name: &str) -> Self {
+        LanguageModel::from_name(model_name).map_or(Provider::OpenRouter, |m| m.provider())
+    }
+}
+
+impl Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Provider::OpenAI => "open_ai",
+            Provider::OpenRouter => "open_router",
+            Provider::VolcEngine => "volc_engine",
+            Provider::Moonshot => "moonshot",
+            Provider::StepFun => "step_fun",
+            Provider::Zhipu => "zhipu",
+            Provider::DeepSeek => "deep_seek",
+        };
+
+        write!(f, "{name}")
     }
 }
 
@@ -212,12 +417,69 @@ impl Display for Task {
     }
 }
 
-impl Display for FinalResult {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}",
-            serde_json::to_string_pretty(self).unwrap_or_default()
+impl Executor {
+    pub fn system_prompt(&self) -> anyhow::Result<String> {
+        self.templates.render(
+            &self
+                .templates
+                .agent_name(&self.language, &self.agent_type, "system_prompt"),
+            context! {},
+        )
+    }
+
+    /// The user prompt of the task. The aggregation instructions go into `agent`, which is left
+    /// empty for fan-outs.
+    pub fn user_prompt(&self, agent: &str) -> anyhow::Result<String> {
+        self.templates.render(
+            &self
+                .templates
+                .agent_name(&self.language, &self.agent_type, "user_prompt"),
+            context! {
+                instruction => self.instruction,
+                context => self.context,
+                agent => agent.trim(),
+            },
+        )
+    }
+
+    /// The aggregation instructions. Agents with tools also get the instructions for their
+    /// `retry_count`th tool turn.
+    pub fn agent_prompt(&self, retry_count: u8) -> anyhow::Result<String> {
+        let agent_transport = if !self.tools.is_empty() {
+            let is_last_retry = retry_count + 1 == MAX_TOOL_RETRY_COUNT;
+            let maximum_retry_reached = if is_last_retry {
+                self.templates.render(
+                    &self
+                        .templates
+                        .name(&self.language, "transport_agent_maximum_try"),
+                    context! {},
+                )?
+            } else {
+                "".into()
+            };
+
+            self.templates
+                .render(
+                    &self.templates.name(&self.language, "transport_agent"),
+                    context! {
+                        retry_count,
+                        is_last_retry,
+                        maximum_retry_reached,
+                    },
+                )?
+                .trim()
+                .to_string()
+        } else {
+            "".into()
+        };
+
+        self.templates.render(
+            &self.templates.name(&self.language, "agent"),
+            context! {
+                results => serde_json::to_string_pretty(&self.results)?,
+                candidates => self.results,
+                agent_transport,
+            },
         )
     }
 }
@@ -226,148 +488,208 @@ impl Display for FinalResult {
 impl Taskable for Executor {
     async fn execute(
         &mut self,
-        contexts: Arc<DashMap<TaskId, Context>>,
+        outcomes: Arc<DashMap<TaskId, TaskOutcome>>,
         llm_clients: Arc<LLMClients>,
-    ) -> anyhow::Result<(ChatChoice, Arc<Mutex<Vec<GenerationDump>>>)> {
+        cancellation_token: CancellationToken,
+    ) -> anyhow::Result<(ChatChoice, GenerationLog)> {
         let dependencies = self.dependencies.clone();
 
         loop {
-            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+            if cancellation_token.is_cancelled() {
+                return Err(anyhow::anyhow!("Task {} was cancelled.", self.task_id));
+            }
 
-            let context_keys = contexts
-                .iter()
-                .map(|entry| entry.key().clone())
-                .collect::<Vec<_>>();
+            if self.budget.state() == BudgetState::Exhausted {
+                return Err(anyhow::anyhow!(
+                    "Task {} was skipped because the plan's budget is exhausted.",
+                    self.task_id
+                ));
+            }
 
             if dependencies
                 .iter()
-                .all(|task_id| context_keys.contains(task_id))
+                .all(|task_id| outcomes.contains_key(task_id))
             {
                 break;
             }
+
+            tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
 
-        let context = contexts
+        let failed_dependencies = dependencies
             .iter()
-            .filter(|c| dependencies.contains(c.key()))
-            .map(|c| (c.key().clone(), c.value().content.clone()))
-            .collect::<HashMap<_, _>>();
+            .filter(|task_id| {
+                outcomes
+                    .get(*task_id)
+                    .is_some_and(|outcome| matches!(outcome.value(), TaskOutcome::Failed))
+            })
+            .collect::<Vec<_>>();
+        if !failed_dependencies.is_empty() {
+            tracing::warn!(
+                "Task {} goes on without {failed_dependencies:?}, which failed or were skipped.",
+                self.task_id
+            );
+        }
 
-        let context = if !context.is_empty() {
-            serde_json::to_string_pretty(&context)?
-        } else {
-            "".into()
-        };
+        if self.optional && self.budget.state() != BudgetState::Normal {
+            return Err(anyhow::anyhow!(
+                "Optional task {} was skipped to stay within the plan's budget.",
+                self.task_id
+            ));
+        }
 
-        self.user_prompt = self.user_prompt.replace("$CONTEXT", &context);
+        let generation_dumps = GenerationLog::new(Some(self.budget.clone()));
+        let fitter = PromptFitter::new(
+            self.context_window.clone(),
+            self.templates.clone(),
+            llm_clients.clone(),
+            generation_dumps.clone(),
+        );
 
-        let subtask_user_prompt = self.user_prompt.replace("$AGENT", "");
-        let messages = build_one_shot_messages(&self.system_prompt, &subtask_user_prompt)?;
+        let dependency_contexts = outcomes
+            .iter()
+            .filter(|outcome| dependencies.contains(outcome.key()))
+            .filter_map(|outcome| match outcome.value() {
+                TaskOutcome::Completed(context) => Some(context.clone()),
+                TaskOutcome::Failed => None,
+            })
+            .collect::<Vec<_>>();
+
+        let system_prompt = self.system_prompt()?;
+        let empty_user_prompt = self.user_prompt("")?;
+
+        // The panel is picked with the contexts in full, which overestimates the cost if they
+        // have to be shortened, so that the contexts can then be fitted to the picked models.
+        self.context = format_contexts(dependency_contexts.clone())?;
+        let panel = self
+            .budget
+            .select_panel(&self.user_prompt("")?, &self.panel);
+        if panel.models.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Task {} was skipped because the plan's budget is exhausted.",
+                self.task_id
+            ));
+        }
+
+        let available_tokens = fitter.available_tokens(
+            smallest_panel_context_window(&panel.models),
+            &[&system_prompt, &empty_user_prompt],
+        );
+        let contexts = fitter
+            .fit_contexts(dependency_contexts, available_tokens)
+            .await;
+
+        let prompt = format_contexts(contexts).and_then(|context| {
+            self.context = context;
+            let subtask_user_prompt = self.user_prompt("")?;
+            let messages = build_one_shot_messages(&system_prompt, &subtask_user_prompt)?;
+            Ok((subtask_user_prompt, messages))
+        });
+        let (subtask_user_prompt, messages) = match prompt {
+            Ok(prompt) => prompt,
+            Err(e) => {
+                self.budget.release(panel.reservation);
+                return Err(e);
+            }
+        };
         let mut join_set = JoinSet::new();
 
-        let generation_dumps = Arc::new(Mutex::new(Vec::new()));
+        tracing::info!(
+            "Fanning out task {} to {} models.",
+            self.task_id,
+            panel.models.len()
+        );
 
-        for entry in MODEL_NAME_MAP.iter() {
-            let (model, model_name) = (*entry.key(), entry.value().clone());
+        for (model, model_name) in panel.models.into_iter() {
             let request = build_llm_request(model, model_name.clone(), messages.clone())?;
             let llm_clients_clone = llm_clients.clone();
-            let agent_type = self.agent_type;
+            let agent_type = self.agent_type.clone();
             let dumps = generation_dumps.clone();
+            let cancellation_token = cancellation_token.clone();
 
             join_set.spawn(async move {
-                let open_router_client = llm_clients_clone
-                    .open_router_clients
-                    .get(&agent_type)
-                    .expect("Failed to get the Open Router client for the agent.");
-
-                let result = match model {
-                    m if OPENAI_MODELS.contains(&m) => {
-                        let chat = llm_clients_clone.openai_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
-                    }
-                    LanguageModel::DoubaoSeed16 => {
-                        let chat = llm_clients_clone.volc_engine_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
-                    }
-                    LanguageModel::Glm45 => {
-                        let chat = llm_clients_clone.zhipu_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
-                    }
-                    LanguageModel::Step216k => {
-                        let chat = llm_clients_clone.step_fun_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
-                    }
-                    m if m == LanguageModel::DeepSeekV3 || m == LanguageModel::DeepSeekR1 => {
-                        let chat = llm_clients_clone.deepseek_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
-                    }
-                    _ => {
-                        let chat = open_router_client.chat();
-                        let future = chat.create(request);
-                        tokio::time::timeout(std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT), future).await
+                let client = llm_clients_clone.client(model.provider());
+
+                let generation = create_chat_completion(
+                    &client,
+                    model.provider(),
+                    request,
+                    GenerationStage::FanOut,
+                    &llm_clients_clone,
+                    &dumps,
+                );
+
+                let result = tokio::select! {
+                    result = generation => result,
+                    _ = cancellation_token.cancelled() => {
+                        let error_msg = format!("Failed to complete a {agent_type} task with {model} because the plan was cancelled.");
+                        tracing::info!("{}", &error_msg);
+                        return Err(anyhow::anyhow!("{}", error_msg));
                     }
                 };
 
                 match result {
-                    Ok(res) => match res {
-                        Ok(r) => {
-                            tracing::info!("{model} has completed a {agent_type} task.");
-                            let extracted = extract_response_content(r);
-
-                            {
-                                let mut dumps_lock = dumps.lock().await;
-                                dumps_lock.push(GenerationDump { model, content: extracted.clone(), ..Default::default() });
-                            }
-
-                            (model, extracted)
-                        },
-                        Err(e) => {
-                            let error_msg = format!("Failed to get response from model {model} when trying to complete a {agent_type} task: {e:?}");
-                            tracing::error!("{}", &error_msg);
-                            (model, error_msg)
-                        }
-                    },
-                    Err(_e) => {
-                        let error_msg = format!("{model} timed out when trying to complete a {agent_type} task.");
+                    Ok(r) => {
+                        tracing::info!("{model} has completed a {agent_type} task.");
+                        Ok(Candidate::new(model, model_name, r))
+                    }
+                    Err(e) => {
+                        let error_msg = format!("Failed to get response from model {model} when trying to complete a {agent_type} task: {e:?}");
                         tracing::error!("{}", &error_msg);
-                        (model, error_msg)
+                        Err(anyhow::anyhow!("{}", error_msg))
                     }
                 }
             });
         }
 
         let results = join_set.join_all().await;
+        self.budget.release(panel.reservation);
 
-        let results = results
+        if cancellation_token.is_cancelled() {
+            return Err(anyhow::anyhow!("Task {} was cancelled.", self.task_id));
+        }
+
+        let candidates = results
             .into_iter()
-            .map(|(_, result)| result)
-            .filter(|s| !s.starts_with("Fail"))
+            .filter_map(|result| result.ok())
             .collect::<Vec<_>>();
 
-        let results_dump = serde_json::to_string_pretty(&results)?;
-
-        let transport_agent_prompt = if let Some(ref p) = self.transport_agent {
-            p.trim().to_string()
-        } else {
-            "".into()
+        let candidates = tokio::select! {
+            candidates = rank_candidates(
+                candidates,
+                &subtask_user_prompt,
+                &self.ranking,
+                &self.templates,
+                &llm_clients,
+                &generation_dumps,
+            ) => candidates,
+            _ = cancellation_token.cancelled() => {
+                return Err(anyhow::anyhow!("Task {} was cancelled.", self.task_id));
+            }
         };
 
-        self.user_prompt = self.user_prompt.replace(
-            "$AGENT",
-            self.agent_prompt
-                .replace("$RESULTS", &results_dump)
-                .replace("$AGENT_TRANSPORT", &transport_agent_prompt)
-                .trim(),
-        );
+        if candidates.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Task {} has no usable responses to aggregate.",
+                self.task_id
+            ));
+        }
 
-        let messages = build_one_shot_messages(&self.system_prompt, &self.user_prompt)?;
+        let results = candidates
+            .into_iter()
+            .map(|candidate| candidate.content)
+            .collect::<Vec<_>>();
+
+        let agent_model = self.aggregation_model.as_str();
+
+        let available_tokens = fitter.available_tokens(
+            context_window_of(agent_model),
+            &[&system_prompt, &self.user_prompt(&self.agent_prompt(0)?)?],
+        );
+        self.results = fitter.fit(results, available_tokens).await;
 
-        let agent_model = GEMINI_25_PRO;
+        let user_prompt = self.user_prompt(&self.agent_prompt(0)?)?;
+        let messages = build_one_shot_messages(&system_prompt, &user_prompt)?;
 
         let mut request = CreateChatCompletionRequestArgs::default();
         request
@@ -375,34 +697,55 @@ impl Taskable for Executor {
             .temperature(TEMPERATURE_MEDIUM)
             .messages(messages);
 
-        let client = &*llm_clients
-            .open_router_clients
-            .get(&self.agent_type)
-            .expect("Failed to get the Open Router client for the agent.");
+        let provider = Provider::for_model(agent_model);
+        let client = llm_clients.client(provider);
 
-        if self.agent_type == Agent::Transport
-            && let Some(ref tool) = self.get_transit_time_tool
-        {
+        if !self.tools.is_empty() {
             request
-                .tools(vec![tool.clone()])
+                .tools(self.tools.clone())
                 .tool_choice(ChatCompletionToolChoiceOption::Required);
+        }
 
-            // client = &llm_clients.openai_client;
+        if let Some(ref response_format) = self.response_format {
+            request.response_format(response_format.clone());
         }
 
-        client
-            .chat()
-            .create(request.build()?)
-            .await
-            .map_err(|e| anyhow::anyhow!("{e:?}"))
-            .and_then(|res| {
-                res.choices
-                    .first()
-                    .cloned()
-                    .map(|c| (c, generation_dumps))
-                    .ok_or(anyhow::anyhow!("Failed to generate a response from model."))
-            })
+        let response = tokio::select! {
+            response = create_chat_completion(
+                &client,
+                provider,
+                request.build()?,
+                GenerationStage::Aggregation,
+                &llm_clients,
+                &generation_dumps,
+            ) => response,
+            _ = cancellation_token.cancelled() => {
+                return Err(anyhow::anyhow!("Task {} was cancelled.", self.task_id));
+            }
+        };
+
+        response.and_then(|res| {
+            res.choices
+                .first()
+                .cloned()
+                .map(|c| (c, generation_dumps))
+                .ok_or(anyhow::anyhow!("Failed to generate a response from model."))
+        })
+    }
+}
+
+/// The contexts of a task's dependencies as the JSON object its prompt is given.
+fn format_contexts(contexts: Vec<Context>) -> anyhow::Result<String> {
+    if contexts.is_empty() {
+        return Ok("".into());
     }
+
+    let contexts = contexts
+        .into_iter()
+        .map(|c| (c.task_id, c.content))
+        .collect::<HashMap<_, _>>();
+
+    Ok(serde_json::to_string_pretty(&contexts)?)
 }
 
 fn build_llm_request(
@@ -439,11 +782,3 @@ fn build_llm_request(
 
     Ok(request)
 }
-
-fn extract_response_content(response: CreateChatCompletionResponse) -> String {
-    response.choices[0]
-        .message
-        .content
-        .clone()
-        .unwrap_or_default()
-}
@@ -2,12 +2,17 @@ use async_openai::types::{
     ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
     ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs, Role,
 };
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use serenity::all::ChannelId;
 use uuid::Uuid;
 
-use crate::shared::structs::agent::{Language, LanguageModel};
+use crate::shared::structs::{
+    agent::{Context, Language, LanguageModel, TaskId, itinerary::Itinerary},
+    config::ModelPrice,
+};
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 #[serde(untagged)]
@@ -21,7 +26,58 @@ pub struct PlanRecord {
     pub id: Uuid,
     pub messages: Vec<Message>,
     pub language: Language,
+    /// How the language was decided. Missing from plans made before it was recorded.
+    #[serde(default)]
+    pub language_decision: Option<LanguageDecision>,
     pub dumps: Vec<GenerationDump>,
+    #[serde(default)]
+    pub status: PlanStatus,
+    #[serde(default)]
+    pub contexts: Vec<Context>,
+    #[serde(default)]
+    pub cost: CostSummary,
+    /// The final itinerary, after validation. Missing from plans made before it was structured.
+    #[serde(default)]
+    pub itinerary: Option<Itinerary>,
+    /// What the tools answered the agents, so that they can be replayed without calling the tools
+    /// again. Missing from plans made before they were recorded.
+    #[serde(default)]
+    pub tool_outputs: Vec<ToolOutput>,
+}
+
+/// The output of one tool call made by the agent of a task.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ToolOutput {
+    pub task_id: TaskId,
+    pub tool: String,
+    pub arguments: String,
+    pub output: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct LanguageDecision {
+    pub method: LanguageDecisionMethod,
+    /// The ISO 639-3 code the local detector found, e.g. `jpn`, even if the model decided.
+    pub detected: Option<String>,
+    /// The local detector's confidence from 0 to 1.
+    pub confidence: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LanguageDecisionMethod {
+    /// The local detector was confident.
+    Detector,
+    /// The prompt was mixed or ambiguous, so the model decided.
+    Model,
+    /// The model failed, so the default language was used.
+    Default,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlanStatus {
+    #[default]
+    Completed,
+    Cancelled,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -43,6 +99,168 @@ pub struct GenerationDump {
     pub model: LanguageModel,
     pub content: String,
     pub is_final_result: bool,
+    #[serde(default)]
+    pub stage: Option<GenerationStage>,
+    #[serde(default)]
+    pub model_name: String,
+    #[serde(default)]
+    pub provider: String,
+    #[serde(default)]
+    pub status: GenerationStatus,
+    #[serde(default)]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub usage: TokenUsage,
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Served from the response cache, so it didn't cost anything.
+    #[serde(default)]
+    pub cached: bool,
+    /// How a fan-out candidate was scored before aggregation.
+    #[serde(default)]
+    pub score: Option<CandidateScore>,
+}
+
+/// The step of a plan an LLM call was made for.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GenerationStage {
+    Triage,
+    Orchestration,
+    Naming,
+    FanOut,
+    Ranking,
+    Summarization,
+    Aggregation,
+    ToolTurn,
+    Synthesis,
+    Repair,
+    /// Scoring in the evaluation harness, which isn't part of a plan.
+    Judgement,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct CandidateScore {
+    /// From 0 to 1. Zero means the candidate was rejected outright.
+    pub heuristic: f64,
+    /// From 0 to 10, if a judge model ranked the candidates.
+    #[serde(default)]
+    pub judge: Option<f64>,
+    /// Whether the candidate was passed on to the aggregator.
+    pub selected: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GenerationStatus {
+    #[default]
+    Succeeded,
+    Failed,
+    TimedOut,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub reasoning_tokens: u32,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct CostSummary {
+    pub calls: usize,
+    pub failed_calls: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub reasoning_tokens: u64,
+    /// In US dollars. Models missing from the price table count as free.
+    pub cost: f64,
+    pub models: Vec<ModelCost>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ModelCost {
+    pub model_name: String,
+    pub calls: usize,
+    pub failed_calls: usize,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    pub average_latency_ms: u64,
+}
+
+impl GenerationDump {
+    pub fn cost(&self, prices: &HashMap<String, ModelPrice>) -> f64 {
+        if self.cached {
+            return 0.0;
+        }
+
+        prices.get(&self.model_name).map_or(0.0, |price| {
+            (self.usage.prompt_tokens as f64 * price.prompt
+                + self.usage.completion_tokens as f64 * price.completion)
+                / 1_000_000.0
+        })
+    }
+}
+
+impl CostSummary {
+    pub fn new(dumps: &[GenerationDump], prices: &HashMap<String, ModelPrice>) -> Self {
+        // Dumps from before usage was tracked carry no model name and are left out.
+        let dumps = dumps
+            .iter()
+            .filter(|dump| !dump.model_name.is_empty())
+            .collect::<Vec<_>>();
+
+        let mut models: HashMap<&str, (ModelCost, u64)> = HashMap::new();
+
+        for dump in dumps.iter() {
+            let (model_cost, total_latency) =
+                models.entry(dump.model_name.as_str()).or_insert_with(|| {
+                    (
+                        ModelCost {
+                            model_name: dump.model_name.clone(),
+                            ..Default::default()
+                        },
+                        0,
+                    )
+                });
+
+            model_cost.calls += 1;
+            if dump.status != GenerationStatus::Succeeded {
+                model_cost.failed_calls += 1;
+            }
+            model_cost.prompt_tokens += dump.usage.prompt_tokens as u64;
+            model_cost.completion_tokens += dump.usage.completion_tokens as u64;
+            model_cost.cost += dump.cost(prices);
+            *total_latency += dump.latency_ms;
+        }
+
+        let mut models = models
+            .into_values()
+            .map(|(mut model_cost, total_latency)| {
+                model_cost.average_latency_ms = total_latency / model_cost.calls as u64;
+                model_cost
+            })
+            .collect::<Vec<_>>();
+
+        models.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+
+        CostSummary {
+            calls: dumps.len(),
+            failed_calls: models.iter().map(|m| m.failed_calls).sum(),
+            prompt_tokens: models.iter().map(|m| m.prompt_tokens).sum(),
+            completion_tokens: models.iter().map(|m| m.completion_tokens).sum(),
+            reasoning_tokens: dumps
+                .iter()
+                .map(|dump| dump.usage.reasoning_tokens as u64)
+                .sum(),
+            cost: models.iter().map(|m| m.cost).sum(),
+            models,
+        }
+    }
+
+    /// The model with the highest average latency, if any calls were made.
+    pub fn slowest_model(&self) -> Option<&ModelCost> {
+        self.models.iter().max_by_key(|m| m.average_latency_ms)
+    }
 }
 
 impl Message {
@@ -0,0 +1,172 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+use serenity::all::UserId;
+use uuid::Uuid;
+
+use crate::shared::structs::{
+    agent::{LanguageModel, MODEL_NAME_MAP, record::GenerationDump},
+    config::{Budget, ModelPrice},
+};
+use crate::shared::utility::context_window::estimate_tokens;
+
+/// Rough number of tokens a model writes for a subtask, used to estimate a fan-out's cost.
+const ESTIMATED_COMPLETION_TOKENS: u32 = 2000;
+
+/// Tracks what a plan has spent, and decides how much of the model panel it can still afford.
+#[derive(Debug)]
+pub struct PlanBudget {
+    limit: Option<f64>,
+    degrade_ratio: f64,
+    economy_panel_size: usize,
+    prices: HashMap<String, ModelPrice>,
+    ledger: Mutex<Ledger>,
+}
+
+#[derive(Debug, Default)]
+struct Ledger {
+    spent: f64,
+    reserved: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetState {
+    /// Everything can run with the full panel.
+    Normal,
+    /// Optional tasks are skipped and only the cheapest models are asked.
+    Tight,
+    /// No more subtasks are started, and the plan is synthesized from what it has.
+    Exhausted,
+}
+
+/// What a fan-out is allowed to do under the current budget.
+#[derive(Debug, Clone)]
+pub struct PanelSelection {
+    pub models: Vec<(LanguageModel, String)>,
+    /// The estimated cost held back for the fan-out until it's released.
+    pub reservation: f64,
+}
+
+/// A user's spending on a single UTC day.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct DailyUsage {
+    pub user_id: UserId,
+    pub date: String,
+    pub cost: f64,
+    pub plans: usize,
+    /// The plans already charged, so that a resumed plan isn't charged twice.
+    #[serde(default)]
+    pub plan_ids: Vec<Uuid>,
+}
+
+impl PlanBudget {
+    pub fn new(limit: Option<f64>, budget: &Budget, prices: HashMap<String, ModelPrice>) -> Self {
+        PlanBudget {
+            limit,
+            degrade_ratio: budget.degrade_ratio,
+            economy_panel_size: budget.economy_panel_size.max(1),
+            prices,
+            ledger: Mutex::new(Ledger::default()),
+        }
+    }
+
+    pub fn charge(&self, dump: &GenerationDump) {
+        let cost = dump.cost(&self.prices);
+        self.lock_ledger().spent += cost;
+    }
+
+    pub fn spent(&self) -> f64 {
+        self.lock_ledger().spent
+    }
+
+    pub fn state(&self) -> BudgetState {
+        let Some(limit) = self.limit else {
+            return BudgetState::Normal;
+        };
+
+        let ledger = self.lock_ledger();
+        let committed = ledger.spent + ledger.reserved;
+
+        if ledger.spent >= limit {
+            BudgetState::Exhausted
+        } else if committed >= limit * self.degrade_ratio {
+            BudgetState::Tight
+        } else {
+            BudgetState::Normal
+        }
+    }
+
+    /// Picks the models to fan out to, cheapest first, as long as their estimated cost still
+    /// fits into the budget. The estimate stays reserved until `release` is called. Only the
+    /// models of the agent's `panel` are considered, or all of them if it's empty.
+    pub fn select_panel(&self, prompt: &str, agent_panel: &[String]) -> PanelSelection {
+        let prompt_tokens = estimate_tokens(prompt) as u32;
+
+        let mut panel = MODEL_NAME_MAP
+            .iter()
+            .filter(|entry| agent_panel.is_empty() || agent_panel.contains(entry.value()))
+            .map(|entry| {
+                let estimate = self.estimate(entry.value(), prompt_tokens);
+                (*entry.key(), entry.value().clone(), estimate)
+            })
+            .collect::<Vec<_>>();
+
+        panel.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+        let Some(limit) = self.limit else {
+            return PanelSelection {
+                models: panel.into_iter().map(|(m, n, _)| (m, n)).collect(),
+                reservation: 0.0,
+            };
+        };
+
+        let state = self.state();
+        let mut ledger = self.lock_ledger();
+        let mut available = limit - ledger.spent - ledger.reserved;
+
+        let maximum_panel_size = match state {
+            BudgetState::Normal => panel.len(),
+            BudgetState::Tight => self.economy_panel_size,
+            BudgetState::Exhausted => 0,
+        };
+
+        let mut models = vec![];
+        let mut reservation = 0.0;
+
+        for (model, model_name, estimate) in panel.into_iter().take(maximum_panel_size) {
+            if estimate > available {
+                break;
+            }
+
+            available -= estimate;
+            reservation += estimate;
+            models.push((model, model_name));
+        }
+
+        ledger.reserved += reservation;
+
+        PanelSelection {
+            models,
+            reservation,
+        }
+    }
+
+    pub fn release(&self, reservation: f64) {
+        let mut ledger = self.lock_ledger();
+        ledger.reserved = (ledger.reserved - reservation).max(0.0);
+    }
+
+    fn estimate(&self, model_name: &str, prompt_tokens: u32) -> f64 {
+        self.prices.get(model_name).map_or(0.0, |price| {
+            (prompt_tokens as f64 * price.prompt
+                + ESTIMATED_COMPLETION_TOKENS as f64 * price.completion)
+                / 1_000_000.0
+        })
+    }
+
+    fn lock_ledger(&self) -> std::sync::MutexGuard<'_, Ledger> {
+        self.ledger
+            .lock()
+            .expect("Failed to lock the budget ledger.")
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// The holidays and usual closures of a country in the bundled dataset.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CountryCalendar {
+    pub name: String,
+    /// The years whose holidays are in the dataset.
+    pub years: Vec<i32>,
+    pub weekly_closures: Vec<WeeklyClosure>,
+    pub holidays: Vec<Holiday>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct WeeklyClosure {
+    /// The English name of the day, e.g. `Monday`.
+    pub weekday: String,
+    pub note: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Holiday {
+    pub date: NaiveDate,
+    pub name: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalendarRequest {
+    /// Dates in the `YYYY-MM-DD` format.
+    pub dates: Vec<String>,
+    pub location: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalendarInfo {
+    pub location: String,
+    pub country: Option<String>,
+    pub days: Vec<CalendarDay>,
+    /// Why there's no calendar for the location.
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CalendarDay {
+    pub date: String,
+    pub day_of_week: Option<String>,
+    pub holidays: Vec<String>,
+    /// Venues that are usually closed on this day of the week.
+    pub closures: Vec<String>,
+    /// Why the day couldn't be looked up, e.g. a year that isn't in the dataset.
+    pub error: Option<String>,
+}
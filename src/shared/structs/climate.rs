@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// The monthly climate normals of a destination in the bundled dataset. Each list has an entry
+/// per month, starting with January.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClimateStation {
+    pub name: String,
+    pub lat: f64,
+    pub lng: f64,
+    pub mean_high_c: [f64; 12],
+    pub mean_low_c: [f64; 12],
+    /// Days with at least 1 mm of rain or snow.
+    pub rain_days: [f64; 12],
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClimateRequest {
+    pub places: Vec<String>,
+    /// The months of the trip, from 1 to 12.
+    pub months: Vec<u32>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ClimateNormals {
+    pub place: String,
+    /// The destination of the dataset closest to the place.
+    pub station: Option<String>,
+    pub distance_km: Option<f64>,
+    pub months: Vec<MonthlyNormals>,
+    /// Why there are no normals for the place.
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct MonthlyNormals {
+    pub month: u32,
+    pub mean_high_c: f64,
+    pub mean_low_c: f64,
+    pub rain_days: f64,
+    /// The time between sunrise and sunset in the middle of the month.
+    pub daylight_hours: f64,
+}
@@ -1,31 +1,361 @@
-use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
 
+use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
 
+use crate::shared::structs::config::templates::PromptTemplates;
+
+use crate::shared::structs::agent::{self, Agent, LanguageModel};
+use crate::shared::utility::tools::{
+    CONVERT_CURRENCY, GET_CALENDAR_INFO, GET_CLIMATE_NORMALS, GET_TRANSIT_TIME, SCORE_CENTRALITY,
+    TOOL_NAMES,
+};
+use crate::shared::{GEMINI_25_FLASH, GEMINI_25_PRO, GPT_41};
+
+pub mod settings;
+pub mod templates;
+
+/// Translations of the UI strings keyed by language tag. English is the `Default` of `UiStrings`.
+static BUNDLED_UI_STRINGS: Lazy<
+    BTreeMap<agent::Language, serde_json::Map<String, serde_json::Value>>,
+> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../../../data/ui_strings.json"))
+        .expect("Failed to parse the bundled UI strings.")
+});
+
+/// The prompts of the agents added after agent prompts moved into `agents`, which older configs
+/// don't have. Keyed by agent, then by language tag.
+static BUNDLED_AGENT_PROMPTS: Lazy<BTreeMap<Agent, BTreeMap<agent::Language, PromptPair>>> =
+    Lazy::new(|| {
+        serde_json::from_str(include_str!("../../../../data/agent_prompts.json"))
+            .expect("Failed to parse the bundled agent prompts.")
+    });
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Configuration {
-    pub server_bind_point: String,
     pub server_address: String,
-    pub log_level: String,
     pub language_triage_prompt: String,
-    pub english: Language,
-    pub chinese: Language,
-    pub japanese: Language,
+    #[serde(default)]
+    pub require_plan_approval: bool,
+    #[serde(default = "default_plan_approval_timeout")]
+    pub plan_approval_timeout: u64,
+    #[serde(default)]
+    pub admission: Admission,
+    /// Per-model prices keyed by the model name sent to the provider.
+    #[serde(default)]
+    pub prices: HashMap<String, ModelPrice>,
+    #[serde(default)]
+    pub show_cost_in_embed: bool,
+    #[serde(default)]
+    pub budget: Budget,
+    #[serde(default)]
+    pub cache: Cache,
+    #[serde(default)]
+    pub ranking: Ranking,
+    #[serde(default)]
+    pub context_window: ContextWindow,
+    #[serde(default)]
+    pub language_detection: LanguageDetection,
+    #[serde(default)]
+    pub exchange_rates: ExchangeRateConfig,
+    #[serde(default)]
+    pub validation: Validation,
+    /// The supported languages keyed by BCP-47 tag, e.g. `ja` or `zh-TW`.
+    #[serde(default)]
+    pub languages: BTreeMap<agent::Language, LanguageConfig>,
+    /// Used for prompts in languages that aren't configured.
+    #[serde(default)]
+    pub default_language: agent::Language,
+    /// The agents the orchestrator can assign tasks to, keyed by a lowercase name such as
+    /// `food`.
+    #[serde(default)]
+    pub agents: BTreeMap<Agent, AgentConfig>,
+    #[serde(flatten, skip_serializing)]
+    legacy_languages: LegacyLanguages,
+    /// The prompts above, compiled when the config is loaded.
+    #[serde(skip)]
+    pub templates: Arc<PromptTemplates>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Admission {
+    /// How many approved plans may execute at once. Further plans wait in a queue. Orchestration
+    /// and plan reviews don't take a slot, and are only limited by `provider_concurrency`.
+    pub max_concurrent_plans: usize,
+    /// Seconds a user has to wait between two plans.
+    pub user_cooldown: u64,
+    /// Seconds between two plans in the same guild.
+    pub guild_cooldown: u64,
+    /// Concurrent requests allowed per provider, e.g. `open_router = 24`.
+    pub provider_concurrency: HashMap<String, usize>,
+    /// Used for providers missing from `provider_concurrency`.
+    pub default_provider_concurrency: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Budget {
+    /// Maximum cost of a single plan in US dollars. Unlimited if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_plan_cost: Option<f64>,
+    /// Maximum cost of all plans of a user per UTC day in US dollars. Unlimited if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_user_daily_cost: Option<f64>,
+    /// Share of the budget after which optional tasks are skipped and the panel shrinks.
+    pub degrade_ratio: f64,
+    /// How many of the cheapest models are kept in the panel once the budget runs low.
+    pub economy_panel_size: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Cache {
+    pub mode: CacheMode,
+    /// Where cached responses are stored. Relative paths are resolved against the config
+    /// directory.
+    pub directory: String,
+    /// Seconds until a cached response expires. Ignored in replay mode.
+    pub ttl: u64,
+    /// Maximum size of the cache in megabytes. The oldest responses are evicted first.
+    pub max_size_mb: u64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    #[default]
+    Off,
+    ReadWrite,
+    /// Serves responses from the cache only, and fails on a miss. Useful for tests and
+    /// debugging, since the same plan is replayed deterministically.
+    Replay,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Ranking {
+    /// How many of the best fan-out candidates are passed to the aggregator.
+    pub top_k: usize,
+    /// Candidates shorter than this many characters are scored down.
+    pub min_candidate_length: usize,
+    /// Scores the candidates with this model when more of them pass the heuristic checks than
+    /// `top_k`. Heuristics alone decide if absent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub judge_model: Option<String>,
+    /// A template given the subtask as `task` and the numbered candidates as `candidates`.
+    pub judge_prompt: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ContextWindow {
+    /// Tokens kept free in every context window for the model's response.
+    pub output_reserve: usize,
+    /// Summarizes payloads that don't fit instead of only trimming them.
+    pub summarize: bool,
+    pub summary_model: String,
+    /// A template given the target length as `tokens` and the text to condense as `content`.
+    pub summary_prompt: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct LanguageDetection {
+    /// Prompts the local detector is less sure about than this, from 0 to 1, are left to the
+    /// model.
+    pub min_confidence: f64,
+    /// Decides the language of mixed or ambiguous prompts.
+    pub model: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct Validation {
+    /// Checks the synthesized itinerary against opening hours and travel times, and has the
+    /// synthesis model repair it once if there are conflicts.
+    pub enabled: bool,
+    /// Minutes a trip between two stops may take longer than scheduled before it's a conflict.
+    pub transit_tolerance: i64,
+    /// A template given the list of `conflicts`.
+    pub repair_prompt: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExchangeRateConfig {
+    /// The JSON rate table, which is refreshed outside of the bot. Relative paths are resolved
+    /// against the config directory. A dated snapshot bundled with the bot is used while the
+    /// file doesn't exist.
+    pub path: String,
+    /// Rate tables older than this many days are still used, but a warning is logged.
+    pub max_age_days: i64,
+}
+
+/// Prices in US dollars per million tokens. Reasoning tokens are billed as completion tokens.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default)]
+pub struct ModelPrice {
+    pub prompt: f64,
+    pub completion: f64,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
-pub struct Language {
+pub struct LanguageConfig {
+    /// The name of the language in English, e.g. `Traditional Chinese`.
+    pub name: String,
+    /// The language of geocoding and directions results, e.g. `zh-TW`.
+    pub geocoding: String,
+    /// The ISO 4217 code of the currency costs are estimated in, unless the user asks for
+    /// another one.
+    #[serde(default = "default_currency")]
+    pub currency: String,
+    #[serde(default)]
+    pub ui: UiStrings,
+    #[serde(flatten)]
+    pub prompts: LanguagePrompts,
+}
+
+/// The bot's own messages, such as the progress embed of a plan. Each one is a template. Strings
+/// that aren't configured default to the bundled translation of the language, or to English.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(default)]
+pub struct UiStrings {
+    pub daily_budget_reached: String,
+    /// Given `seconds`.
+    pub user_cooldown: String,
+    /// Given `seconds`.
+    pub guild_cooldown: String,
+    /// Given `position`.
+    pub queue_position: String,
+    pub execution_plan_title: String,
+    /// Given `analysis` and `task_count`.
+    pub plan_summary: String,
+    pub cancel_button: String,
+    /// Given `task` and `agent`.
+    pub executing_task: String,
+    /// Given `task`.
+    pub task_completed: String,
+    pub plan_budget_reached: String,
+    pub synthesizing: String,
+    pub plan_cancelled: String,
+    /// Given `cost`, `tokens` and `calls`.
+    pub cost_summary: String,
+    /// Given `failed_calls`.
+    pub failed_calls: String,
+    /// Given `model` and `seconds`.
+    pub slowest_model: String,
+    /// Given `currency`.
+    pub budget_title: String,
+    /// Given `day`.
+    pub budget_day: String,
+    pub budget_food: String,
+    pub budget_transit: String,
+    pub budget_admission: String,
+    pub budget_lodging: String,
+    pub budget_total: String,
+    /// Given `day`.
+    pub itinerary_day: String,
+    /// Given `minutes`.
+    pub itinerary_public_transport: String,
+    /// Given `minutes`.
+    pub itinerary_drive_or_taxi: String,
+    pub approval_title: String,
+    /// Given `task`, `agent` and `optional`.
+    pub approval_task: String,
+    /// Given `dependencies`.
+    pub approval_dependencies: String,
+    pub approve_button: String,
+    pub edit_button: String,
+    pub plan_approved: String,
+    pub plan_rejected: String,
+    pub plan_revising: String,
+    pub revision_failed: String,
+    pub approval_timed_out: String,
+    pub approval_not_pending: String,
+    pub approval_not_requester: String,
+    pub revision_title: String,
+    pub revision_label: String,
+    pub revision_placeholder: String,
+    pub cancelling_plan: String,
+    /// Given `count`.
+    pub cancelling_plans: String,
+    pub cancel_not_requester: String,
+    pub no_plans_running: String,
+    pub plan_not_running: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AgentConfig {
+    /// Shown to users, e.g. `Food`.
+    pub name: String,
+    /// Tells the orchestrator which tasks the agent is meant for.
+    pub description: String,
+    /// The models to fan out to, by the name sent to the provider. All models if empty.
+    #[serde(default)]
+    pub panel: Vec<String>,
+    /// Aggregates the responses of the panel.
+    #[serde(default = "default_aggregation_model")]
+    pub aggregation_model: String,
+    /// The tools the aggregator may call, e.g. `get_transit_time`.
+    #[serde(default)]
+    pub tools: Vec<String>,
+    /// Agents whose tasks have to finish first, so that their results are in the context of this
+    /// agent's tasks. Tasks that fail or are skipped don't hold it up; it goes on with the results
+    /// there are.
+    #[serde(default)]
+    pub depends_on: Vec<Agent>,
+    #[serde(default)]
+    pub output: AgentOutput,
+    /// The system and user prompts keyed by language tag. Every configured language needs them.
+    #[serde(default)]
+    pub prompts: BTreeMap<agent::Language, PromptPair>,
+}
+
+/// What the aggregator of an agent answers with.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AgentOutput {
+    #[default]
+    Text,
+    /// A `CostEstimate`, which is added to the final result as a budget section instead of
+    /// being synthesized.
+    CostEstimate,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct LanguagePrompts {
     pub orchestrator: Prompt,
     pub naming: Prompt,
-    pub food: PromptPair,
-    pub history: PromptPair,
-    pub modern: PromptPair,
-    pub nature: PromptPair,
-    pub transport: PromptPair,
     pub agent: Prompt,
     pub synthesis: Prompt,
+    /// The tool turn instructions of agents with tools.
     pub transport_agent: Prompt,
     pub transport_agent_maximum_try: Prompt,
+    #[serde(flatten, skip_serializing)]
+    legacy_agents: LegacyAgentPrompts,
+}
+
+/// The agent prompts of older configs, which were fixed per language. They're moved into
+/// `agents` on load.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct LegacyAgentPrompts {
+    food: Option<PromptPair>,
+    history: Option<PromptPair>,
+    modern: Option<PromptPair>,
+    nature: Option<PromptPair>,
+    transport: Option<PromptPair>,
+}
+
+/// The per-language tables of older configs, which are moved into `languages` on load.
+#[derive(Deserialize, Debug, Clone, Default)]
+struct LegacyLanguages {
+    english: Option<LanguagePrompts>,
+    chinese: Option<LanguagePrompts>,
+    japanese: Option<LanguagePrompts>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
@@ -41,47 +371,680 @@ pub struct PromptPair {
 
 impl Configuration {
     pub fn new() -> Self {
-        Configuration {
-            server_bind_point: "0.0.0.0:80".into(),
+        let mut config = Configuration {
             server_address: "http://localhost:80/".into(),
-            log_level: "DEBUG".into(),
             language_triage_prompt: "".into(),
-            english: Default::default(),
-            chinese: Default::default(),
-            japanese: Default::default(),
-        }
-    }
+            require_plan_approval: false,
+            plan_approval_timeout: default_plan_approval_timeout(),
+            admission: Default::default(),
+            prices: HashMap::new(),
+            show_cost_in_embed: false,
+            budget: Default::default(),
+            cache: Default::default(),
+            ranking: Default::default(),
+            context_window: Default::default(),
+            language_detection: Default::default(),
+            exchange_rates: Default::default(),
+            validation: Default::default(),
+            languages: [
+                ("en", "English", "en", "USD"),
+                ("ja", "Japanese", "ja", "JPY"),
+                ("zh-TW", "Traditional Chinese", "zh-TW", "TWD"),
+            ]
+            .into_iter()
+            .map(|(tag, name, geocoding, currency)| {
+                (
+                    agent::Language::new(tag),
+                    LanguageConfig {
+                        name: name.into(),
+                        geocoding: geocoding.into(),
+                        currency: currency.into(),
+                        ui: UiStrings::for_language(&agent::Language::new(tag)),
+                        prompts: Default::default(),
+                    },
+                )
+            })
+            .collect(),
+            default_language: Default::default(),
+            agents: Default::default(),
+            legacy_languages: Default::default(),
+            templates: Default::default(),
+        };
 
-    pub fn load_from_config_file() -> anyhow::Result<Self> {
-        let config_directory = Self::config_directory()?;
+        config.agents = default_agents()
+            .into_iter()
+            .map(|(agent, mut agent_config)| {
+                agent_config.prompts = config
+                    .languages
+                    .keys()
+                    .map(|language| {
+                        let prompts = agent_config.prompts.get(language).cloned();
+                        (language.clone(), prompts.unwrap_or_default())
+                    })
+                    .collect();
+                (agent, agent_config)
+            })
+            .collect();
 
-        if !config_directory.exists() {
-            std::fs::create_dir_all(&config_directory)?;
+        config
+    }
+
+    pub fn load_from_config_file(config_path: &Path) -> anyhow::Result<Self> {
+        if let Some(config_directory) = config_path.parent()
+            && !config_directory.exists()
+        {
+            std::fs::create_dir_all(config_directory)?;
         }
 
-        let config_path = Self::config_path()?;
         if !config_path.exists() {
             let new_config = Configuration::new();
             let serialized = toml::to_string_pretty(&new_config)?;
             std::fs::write(config_path, serialized)?;
-            Ok(new_config)
-        } else {
-            let raw_config = std::fs::read_to_string(config_path)?;
-            let deserialized: Configuration = toml::from_str(&raw_config)?;
-            Ok(deserialized)
+
+            let error_msg = format!(
+                "No config was found, so a default one was written to {}. Fill in its prompts and start again.",
+                config_path.display()
+            );
+            tracing::error!("{}", &error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        }
+
+        Self::load_from_path(config_path)
+    }
+
+    /// Loads and validates a config. Prompts are compiled into templates on the way.
+    pub fn load_from_path(path: &Path) -> anyhow::Result<Self> {
+        let raw_config = std::fs::read_to_string(path)?;
+        let mut deserialized: Configuration = toml::from_str(&raw_config)?;
+        deserialized.migrate_legacy_languages();
+        deserialized.migrate_legacy_agents();
+        deserialized.localize_ui_strings();
+        deserialized.validate()?;
+        deserialized.templates = Arc::new(PromptTemplates::compile(&deserialized)?);
+        Ok(deserialized)
+    }
+
+    fn migrate_legacy_languages(&mut self) {
+        let legacy_languages = std::mem::take(&mut self.legacy_languages);
+
+        let migrated = [
+            ("en", "English", "USD", legacy_languages.english),
+            ("ja", "Japanese", "JPY", legacy_languages.japanese),
+            (
+                "zh-TW",
+                "Traditional Chinese",
+                "TWD",
+                legacy_languages.chinese,
+            ),
+        ];
+
+        for (tag, name, currency, prompts) in migrated.into_iter() {
+            if let Some(prompts) = prompts {
+                self.languages
+                    .entry(agent::Language::new(tag))
+                    .or_insert_with(|| LanguageConfig {
+                        name: name.into(),
+                        geocoding: tag.into(),
+                        currency: currency.into(),
+                        ui: Default::default(),
+                        prompts,
+                    });
+            }
+        }
+    }
+
+    fn localize_ui_strings(&mut self) {
+        for (language, language_config) in self.languages.iter_mut() {
+            language_config.ui = std::mem::take(&mut language_config.ui).localize(language);
+        }
+    }
+
+    /// Moves the agent prompts of older configs into `agents`. Agents that aren't declared yet
+    /// are added with their former description, panel and tools, and so are the agents with
+    /// bundled prompts, as long as they have prompts for every configured language.
+    fn migrate_legacy_agents(&mut self) {
+        let mut default_agents = default_agents();
+
+        for (language, language_config) in self.languages.iter_mut() {
+            let legacy_agents = std::mem::take(&mut language_config.prompts.legacy_agents);
+
+            let migrated = [
+                ("food", legacy_agents.food),
+                ("history", legacy_agents.history),
+                ("modern", legacy_agents.modern),
+                ("nature", legacy_agents.nature),
+                ("transport", legacy_agents.transport),
+            ];
+
+            for (key, prompts) in migrated.into_iter() {
+                let Some(prompts) = prompts else {
+                    continue;
+                };
+
+                let agent = Agent::new(key);
+                let Some(agent_config) = self.agents.get_mut(&agent).or_else(|| {
+                    default_agents
+                        .iter_mut()
+                        .find(|(default_agent, _)| *default_agent == agent)
+                        .map(|(_, agent_config)| agent_config)
+                }) else {
+                    continue;
+                };
+
+                agent_config
+                    .prompts
+                    .entry(language.clone())
+                    .or_insert(prompts);
+            }
+        }
+
+        // In the order of `default_agents`, so that dependencies are added before their dependents.
+        for (agent, mut agent_config) in default_agents.into_iter() {
+            if self.agents.contains_key(&agent) {
+                continue;
+            }
+
+            agent_config
+                .prompts
+                .retain(|language, _| self.languages.contains_key(language));
+            if agent_config.prompts.is_empty()
+                || !self
+                    .languages
+                    .keys()
+                    .all(|language| agent_config.prompts.contains_key(language))
+            {
+                continue;
+            }
+
+            agent_config
+                .depends_on
+                .retain(|dependency| self.agents.contains_key(dependency));
+            self.agents.insert(agent, agent_config);
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+
+        if !self.languages.contains_key(&self.default_language) {
+            problems.push(format!(
+                "The default language `{}` isn't one of the configured languages.",
+                self.default_language
+            ));
+        }
+
+        for (language, language_config) in self.languages.iter() {
+            if language_config.name.trim().is_empty() {
+                problems.push(format!("`languages.{language}.name` can't be empty."));
+            }
+
+            if language_config
+                .geocoding
+                .parse::<::google_maps::Language>()
+                .is_err()
+            {
+                problems.push(format!(
+                    "`languages.{language}.geocoding` isn't a language Google Maps supports: `{}`.",
+                    language_config.geocoding
+                ));
+            }
+
+            let currency = &language_config.currency;
+            if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+                problems.push(format!(
+                    "`languages.{language}.currency` has to be an ISO 4217 code such as `USD`, not `{currency}`."
+                ));
+            }
+        }
+
+        if self.agents.is_empty() {
+            problems.push("At least one agent has to be configured in `agents`.".to_string());
+        }
+
+        for (agent, agent_config) in self.agents.iter() {
+            if agent.key().is_empty()
+                || !agent
+                    .key()
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+            {
+                problems.push(format!(
+                    "The agent `{agent}` has to be named with lowercase letters, digits and underscores."
+                ));
+            }
+
+            if agent_config.name.trim().is_empty() {
+                problems.push(format!("`agents.{agent}.name` can't be empty."));
+            }
+
+            if agent_config.description.trim().is_empty() {
+                problems.push(format!("`agents.{agent}.description` can't be empty."));
+            }
+
+            if agent_config.aggregation_model.trim().is_empty() {
+                problems.push(format!(
+                    "`agents.{agent}.aggregation_model` can't be empty."
+                ));
+            }
+
+            for model_name in agent_config.panel.iter() {
+                if LanguageModel::from_name(model_name).is_none() {
+                    problems.push(format!(
+                        "`agents.{agent}.panel` has the unknown model `{model_name}`."
+                    ));
+                }
+            }
+
+            for language in agent_config.prompts.keys() {
+                if !self.languages.contains_key(language) {
+                    problems.push(format!(
+                        "`agents.{agent}.prompts.{language}` is for a language that isn't configured."
+                    ));
+                }
+            }
+
+            for dependency in agent_config.depends_on.iter() {
+                if dependency == agent || !self.agents.contains_key(dependency) {
+                    problems.push(format!(
+                        "`agents.{agent}.depends_on` has `{dependency}`, which isn't another configured agent."
+                    ));
+                }
+            }
+
+            for tool in agent_config.tools.iter() {
+                if !TOOL_NAMES.contains(&tool.as_str()) {
+                    problems.push(format!(
+                        "`agents.{agent}.tools` has the unknown tool `{tool}`. Available tools: {}.",
+                        TOOL_NAMES.join(", ")
+                    ));
+                }
+            }
+        }
+
+        if self.ranking.top_k == 0 {
+            problems.push("`ranking.top_k` has to be at least 1.".to_string());
+        }
+
+        if !(0.0..=1.0).contains(&self.budget.degrade_ratio) {
+            problems.push("`budget.degrade_ratio` has to be between 0 and 1.".to_string());
+        }
+
+        let limits = [
+            ("budget.max_plan_cost", self.budget.max_plan_cost),
+            (
+                "budget.max_user_daily_cost",
+                self.budget.max_user_daily_cost,
+            ),
+        ];
+
+        for (name, limit) in limits.into_iter() {
+            if limit.is_some_and(|limit| limit <= 0.0) {
+                problems.push(format!("`{name}` has to be positive if it's set."));
+            }
+        }
+
+        if self.context_window.summarize && self.context_window.summary_model.trim().is_empty() {
+            problems.push(
+                "`context_window.summary_model` can't be empty while summarizing is on."
+                    .to_string(),
+            );
+        }
+
+        if !(0.0..=1.0).contains(&self.language_detection.min_confidence) {
+            problems
+                .push("`language_detection.min_confidence` has to be between 0 and 1.".to_string());
+        }
+
+        if self.validation.transit_tolerance < 0 {
+            problems.push("`validation.transit_tolerance` can't be negative.".to_string());
+        }
+
+        if self.exchange_rates.path.trim().is_empty() {
+            problems.push("`exchange_rates.path` can't be empty.".to_string());
+        }
+
+        if self.exchange_rates.max_age_days < 0 {
+            problems.push("`exchange_rates.max_age_days` can't be negative.".to_string());
+        }
+
+        if self.language_detection.model.trim().is_empty() {
+            problems.push("`language_detection.model` can't be empty.".to_string());
+        }
+
+        for (model_name, price) in self.prices.iter() {
+            if price.prompt < 0.0 || price.completion < 0.0 {
+                problems.push(format!("The prices of `{model_name}` can't be negative."));
+            }
+        }
+
+        if !problems.is_empty() {
+            let error_msg = format!("The configuration is invalid:\n- {}", problems.join("\n- "));
+            tracing::error!("{}", &error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        }
+
+        Ok(())
+    }
+
+    /// The configured language closest to the given one, or the default language.
+    pub fn resolve_language(&self, language: &agent::Language) -> agent::Language {
+        language
+            .closest(self.languages.keys())
+            .unwrap_or(&self.default_language)
+            .clone()
+    }
+
+    pub fn language(&self, language: &agent::Language) -> &LanguageConfig {
+        self.languages
+            .get(&self.resolve_language(language))
+            .or_else(|| self.languages.values().next())
+            .expect("At least one language has to be configured.")
+    }
+
+    pub fn agent(&self, agent: &Agent) -> anyhow::Result<&AgentConfig> {
+        match self.agents.get(agent) {
+            Some(agent_config) => Ok(agent_config),
+            None => {
+                let error_msg = format!("The agent `{agent}` isn't configured.");
+                tracing::error!("{}", &error_msg);
+                Err(anyhow::anyhow!("{}", error_msg))
+            }
+        }
+    }
+}
+
+impl LanguageConfig {
+    pub fn geocoding_language(&self) -> ::google_maps::Language {
+        self.geocoding
+            .parse()
+            .unwrap_or(::google_maps::Language::EnglishUs)
+    }
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn default_plan_approval_timeout() -> u64 {
+    60 * 15
+}
+
+fn default_currency() -> String {
+    "USD".into()
+}
+
+fn default_aggregation_model() -> String {
+    GEMINI_25_PRO.into()
+}
+
+/// The agents of a new config, with the bundled prompts of the agents that have them.
+fn default_agents() -> Vec<(Agent, AgentConfig)> {
+    let agents = [
+        (
+            "food",
+            "Food",
+            "Restaurants, cafés, street food and local dishes worth trying.",
+            vec![],
+            vec![],
+            AgentOutput::Text,
+        ),
+        (
+            "history",
+            "History",
+            "Historic sites, temples, museums and the stories behind them.",
+            vec![GET_CALENDAR_INFO],
+            vec![],
+            AgentOutput::Text,
+        ),
+        (
+            "modern",
+            "Modern",
+            "Contemporary architecture, shopping districts, pop culture and entertainment.",
+            vec![GET_CALENDAR_INFO],
+            vec![],
+            AgentOutput::Text,
+        ),
+        (
+            "nature",
+            "Nature",
+            "Parks, gardens, hikes, scenic views and other outdoor activities.",
+            vec![GET_CLIMATE_NORMALS],
+            vec![],
+            AgentOutput::Text,
+        ),
+        (
+            "transport",
+            "Transport",
+            "Routes between the places of the itinerary and how long it takes to travel them.",
+            vec![GET_TRANSIT_TIME],
+            vec![],
+            AgentOutput::Text,
+        ),
+        (
+            "lodging",
+            "Lodging",
+            "Neighbourhoods and types of accommodation to stay in, close to the places the other agents recommend.",
+            vec![SCORE_CENTRALITY, GET_TRANSIT_TIME],
+            vec!["food", "history", "modern", "nature"],
+            AgentOutput::Text,
+        ),
+        // Waits for every other agent to price what they recommend, but still runs on whatever
+        // results there are if some of them fail.
+        (
+            "budget",
+            "Budget",
+            "What the trip will cost per day for food, transit, admission and lodging.",
+            vec![CONVERT_CURRENCY],
+            vec![
+                "food",
+                "history",
+                "modern",
+                "nature",
+                "transport",
+                "lodging",
+            ],
+            AgentOutput::CostEstimate,
+        ),
+    ];
+
+    agents
+        .into_iter()
+        .map(|(key, name, description, tools, depends_on, output)| {
+            let agent = Agent::new(key);
+            let prompts = BUNDLED_AGENT_PROMPTS
+                .get(&agent)
+                .cloned()
+                .unwrap_or_default();
+
+            (
+                agent,
+                AgentConfig {
+                    name: name.into(),
+                    description: description.into(),
+                    panel: vec![],
+                    aggregation_model: default_aggregation_model(),
+                    tools: tools.into_iter().map(ToString::to_string).collect(),
+                    depends_on: depends_on.into_iter().map(Agent::new).collect(),
+                    output,
+                    prompts,
+                },
+            )
+        })
+        .collect()
+}
+
+impl Default for Admission {
+    fn default() -> Self {
+        Admission {
+            max_concurrent_plans: 2,
+            user_cooldown: 60 * 5,
+            guild_cooldown: 30,
+            provider_concurrency: HashMap::new(),
+            default_provider_concurrency: 16,
+        }
+    }
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            max_plan_cost: None,
+            max_user_daily_cost: None,
+            degrade_ratio: 0.6,
+            economy_panel_size: 4,
         }
     }
+}
 
-    pub fn config_directory() -> anyhow::Result<PathBuf> {
-        let config_directory_path = std::env::var("CONFIG_DIRECTORY")?;
-        let config_directory = std::path::Path::new(&config_directory_path);
+impl Default for Cache {
+    fn default() -> Self {
+        Cache {
+            mode: CacheMode::Off,
+            directory: "llm_cache".into(),
+            ttl: 60 * 60 * 24 * 7,
+            max_size_mb: 512,
+        }
+    }
+}
 
-        Ok(config_directory.to_path_buf())
+impl Default for Ranking {
+    fn default() -> Self {
+        Ranking {
+            top_k: 5,
+            min_candidate_length: 300,
+            judge_model: None,
+            judge_prompt: "Below are several responses to the same travel planning task. Score each of them from 0 to 10 for accuracy, relevance to the task and usefulness to a traveler. Give refusals, off-topic and incomplete responses low scores.\n\n## Task\n{{ task }}\n\n## Candidates\n{{ candidates }}".into(),
+        }
     }
+}
 
-    pub fn config_path() -> anyhow::Result<PathBuf> {
-        let config_directory = Self::config_directory()?;
-        let config_file_name = std::env::var("CONFIG_FILE_NAME")?;
-        Ok(config_directory.join(&config_file_name))
+impl Default for UiStrings {
+    fn default() -> Self {
+        UiStrings {
+            daily_budget_reached:
+                "💸 You've reached today's planning budget. Please try again tomorrow.".into(),
+            user_cooldown: "⏳ Please wait {{ seconds }} seconds before planning another trip."
+                .into(),
+            guild_cooldown: "⏳ Another trip was just planned in this server. Please try again in {{ seconds }} seconds.".into(),
+            queue_position:
+                "⏳ All agents are busy right now. You are #{{ position }} in the queue.".into(),
+            execution_plan_title: "Execution Plan".into(),
+            plan_summary: "- Analysis: {{ analysis }}\n- Number of tasks: {{ task_count }}\n\n🚀 Executing tasks...".into(),
+            cancel_button: "Cancel".into(),
+            executing_task: "Executing {{ task }} with {{ agent }} Agent...".into(),
+            task_completed: "✅ {{ task }} completed.".into(),
+            plan_budget_reached: "💸 The budget for this plan has been reached. Continuing with the completed tasks.".into(),
+            synthesizing: "🔄 Synthesizing final result...".into(),
+            plan_cancelled: "🛑 Plan cancelled.".into(),
+            cost_summary: "💰 ${{ cost }} · {{ tokens }} tokens · {{ calls }} calls".into(),
+            failed_calls: " ({{ failed_calls }} failed)".into(),
+            slowest_model: "\n🐢 Slowest: {{ model }} ({{ seconds }}s on average)".into(),
+            budget_title: "💴 Estimated Budget ({{ currency }})".into(),
+            budget_day: "Day {{ day }}".into(),
+            budget_food: "Food".into(),
+            budget_transit: "Transit".into(),
+            budget_admission: "Admission".into(),
+            budget_lodging: "Lodging".into(),
+            budget_total: "Total".into(),
+            itinerary_day: "Day {{ day }}".into(),
+            itinerary_public_transport: "🚆 {{ minutes }} min by public transport".into(),
+            itinerary_drive_or_taxi: "🚕 {{ minutes }} min by car or taxi".into(),
+            approval_title: "Execution Plan Review".into(),
+            approval_task:
+                "{{ task }} · {{ agent }} Agent{% if optional %} · Optional{% endif %}".into(),
+            approval_dependencies: "Depends on: {{ dependencies }}".into(),
+            approve_button: "Approve".into(),
+            edit_button: "Edit".into(),
+            plan_approved: "✅ Plan approved.".into(),
+            plan_rejected: "❌ Plan cancelled.".into(),
+            plan_revising: "✏️ Revising the plan...".into(),
+            revision_failed:
+                "⚠️ The plan couldn't be revised. Please review the previous plan again.".into(),
+            approval_timed_out: "⌛ The plan was not approved in time and has been cancelled."
+                .into(),
+            approval_not_pending: "This plan is no longer waiting for approval.".into(),
+            approval_not_requester: "Only the user who requested this plan can review it.".into(),
+            revision_title: "Edit Plan".into(),
+            revision_label: "What should be changed?".into(),
+            revision_placeholder: "e.g. Skip the museums and add a day trip to Nara.".into(),
+            cancelling_plan: "🛑 Cancelling your plan...".into(),
+            cancelling_plans: "🛑 Cancelling {{ count }} plans...".into(),
+            cancel_not_requester: "Only the user who requested this plan can cancel it.".into(),
+            no_plans_running: "You don't have any plans in progress.".into(),
+            plan_not_running: "This plan is no longer running.".into(),
+        }
+    }
+}
+
+impl UiStrings {
+    /// The bundled translation of a language, or English if there is none.
+    pub fn for_language(language: &agent::Language) -> Self {
+        UiStrings::default().localize(language)
+    }
+
+    /// Replaces the strings that were left at their English default with the bundled translation
+    /// of the language.
+    fn localize(self, language: &agent::Language) -> Self {
+        let Some(translation) = BUNDLED_UI_STRINGS.get(language) else {
+            return self;
+        };
+
+        let (Ok(serde_json::Value::Object(mut strings)), Ok(serde_json::Value::Object(english))) = (
+            serde_json::to_value(&self),
+            serde_json::to_value(UiStrings::default()),
+        ) else {
+            return self;
+        };
+
+        for (key, value) in strings.iter_mut() {
+            if english.get(key) == Some(value)
+                && let Some(translated) = translation.get(key)
+            {
+                *value = translated.clone();
+            }
+        }
+
+        serde_json::from_value(serde_json::Value::Object(strings)).unwrap_or(self)
+    }
+}
+
+impl Default for LanguageDetection {
+    fn default() -> Self {
+        LanguageDetection {
+            min_confidence: 0.4,
+            model: GPT_41.into(),
+        }
+    }
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation {
+            enabled: true,
+            transit_tolerance: 10,
+            repair_prompt: "The itinerary you wrote can't work out as scheduled:\n\n{{ conflicts }}\n\nRevise it so that every place is visited while it's open and there's enough time to travel between the stops, e.g. by reordering stops, moving them to other days or replacing them. Keep everything else as it is and reply in the same language.".into(),
+        }
+    }
+}
+
+impl Default for ExchangeRateConfig {
+    fn default() -> Self {
+        ExchangeRateConfig {
+            path: "exchange_rates.json".into(),
+            max_age_days: 7,
+        }
+    }
+}
+
+impl Default for ContextWindow {
+    fn default() -> Self {
+        ContextWindow {
+            output_reserve: 8192,
+            summarize: true,
+            summary_model: GEMINI_25_FLASH.into(),
+            summary_prompt: "Condense the following travel research to at most {{ tokens }} tokens. Keep every concrete fact such as names, addresses, opening hours, prices and travel times, and drop everything else. Reply in the language of the text.\n\n{{ content }}".into(),
+        }
     }
 }
@@ -0,0 +1,296 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tracing::Level;
+
+const DEFAULT_CONFIG_FILE_NAME: &str = "config.toml";
+const DEFAULT_SERVER_BIND_POINT: &str = "0.0.0.0";
+const DEFAULT_PORT: u16 = 80;
+const DEFAULT_LOG_LEVEL: &str = "DEBUG";
+
+/// A value that must not end up in logs. It's shown as `[redacted]` when printed or serialized.
+#[derive(Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+/// Settings as given by one source. Any of them may be missing, and they are merged with
+/// `merge` before they're resolved into `Settings`.
+#[derive(Args, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SettingsLayer {
+    /// The directory containing the config file and the service account file.
+    #[arg(long, env = "CONFIG_DIRECTORY")]
+    pub config_directory: Option<PathBuf>,
+    #[arg(long, env = "CONFIG_FILE_NAME")]
+    pub config_file_name: Option<String>,
+    #[arg(long, env = "SERVER_BIND_POINT")]
+    pub server_bind_point: Option<String>,
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+    /// One of TRACE, DEBUG, INFO, WARN and ERROR.
+    #[arg(long, env = "LOG_LEVEL")]
+    pub log_level: Option<String>,
+    /// The Google service account file, relative to the config directory.
+    #[arg(long, env = "SA_FILE_NAME")]
+    pub sa_file_name: Option<String>,
+    /// The Google Cloud project of the Firestore database.
+    #[arg(long, env = "PROJECT_ID")]
+    pub project_id: Option<String>,
+    #[arg(long, env = "APPLICATION_ID")]
+    pub application_id: Option<u64>,
+    /// Verifies that interactions come from Discord.
+    #[arg(long, env = "APPLICATION_PUBLIC_KEY")]
+    pub application_public_key: Option<String>,
+    #[arg(long, env = "BOT_TOKEN", hide_env_values = true)]
+    pub bot_token: Option<Secret>,
+    #[arg(long, env = "GOOGLE_API_KEY", hide_env_values = true)]
+    pub google_api_key: Option<Secret>,
+    #[command(flatten)]
+    pub api_keys: ApiKeys,
+}
+
+/// The keys of the LLM providers. Providers without a key can't be called, but aren't required
+/// to start the bot.
+#[derive(Args, Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiKeys {
+    #[arg(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+    pub openai_api_key: Option<Secret>,
+    #[arg(long, env = "OPEN_ROUTER_API_KEY", hide_env_values = true)]
+    pub open_router_api_key: Option<Secret>,
+    #[arg(long, env = "VOLC_ENGINE_API_KEY", hide_env_values = true)]
+    pub volc_engine_api_key: Option<Secret>,
+    #[arg(long, env = "MOONSHOT_API_KEY", hide_env_values = true)]
+    pub moonshot_api_key: Option<Secret>,
+    #[arg(long, env = "STEP_FUN_API_KEY", hide_env_values = true)]
+    pub step_fun_api_key: Option<Secret>,
+    #[arg(long, env = "ZHIPU_API_KEY", hide_env_values = true)]
+    pub zhipu_api_key: Option<Secret>,
+    #[arg(long, env = "DEEP_SEEK_API_KEY", hide_env_values = true)]
+    pub deep_seek_api_key: Option<Secret>,
+}
+
+/// The runtime settings of the bot. Each value is taken from the first source that has it: the
+/// command line, the environment, the `[settings]` table of the config file, and the default.
+/// Unlike the rest of the config, settings are only read at startup.
+#[derive(Serialize, Debug, Clone)]
+pub struct Settings {
+    pub config_directory: PathBuf,
+    pub config_file_name: String,
+    pub server_bind_point: String,
+    pub port: u16,
+    pub log_level: String,
+    pub sa_file_name: String,
+    pub project_id: String,
+    pub application_id: u64,
+    pub application_public_key: String,
+    pub bot_token: Secret,
+    pub google_api_key: Secret,
+    pub api_keys: ApiKeys,
+}
+
+#[derive(Deserialize, Default)]
+struct ConfigFileSettings {
+    #[serde(default)]
+    settings: SettingsLayer,
+}
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+impl SettingsLayer {
+    /// Reads the `[settings]` table of a config file. A missing file has no settings.
+    pub fn from_config_file(path: &Path) -> anyhow::Result<Self> {
+        if !path.exists() {
+            return Ok(SettingsLayer::default());
+        }
+
+        let raw_config = std::fs::read_to_string(path)?;
+        match toml::from_str::<ConfigFileSettings>(&raw_config) {
+            Ok(config) => Ok(config.settings),
+            Err(e) => {
+                let error_msg = format!("Failed to read the settings of {}: {e}", path.display());
+                tracing::error!("{}", &error_msg);
+                Err(anyhow::anyhow!("{}", error_msg))
+            }
+        }
+    }
+
+    /// Fills in the values missing from this layer with those of `fallback`.
+    pub fn merge(self, fallback: SettingsLayer) -> Self {
+        SettingsLayer {
+            config_directory: self.config_directory.or(fallback.config_directory),
+            config_file_name: self.config_file_name.or(fallback.config_file_name),
+            server_bind_point: self.server_bind_point.or(fallback.server_bind_point),
+            port: self.port.or(fallback.port),
+            log_level: self.log_level.or(fallback.log_level),
+            sa_file_name: self.sa_file_name.or(fallback.sa_file_name),
+            project_id: self.project_id.or(fallback.project_id),
+            application_id: self.application_id.or(fallback.application_id),
+            application_public_key: self
+                .application_public_key
+                .or(fallback.application_public_key),
+            bot_token: self.bot_token.or(fallback.bot_token),
+            google_api_key: self.google_api_key.or(fallback.google_api_key),
+            api_keys: self.api_keys.merge(fallback.api_keys),
+        }
+    }
+}
+
+impl ApiKeys {
+    pub fn merge(self, fallback: ApiKeys) -> Self {
+        ApiKeys {
+            openai_api_key: self.openai_api_key.or(fallback.openai_api_key),
+            open_router_api_key: self.open_router_api_key.or(fallback.open_router_api_key),
+            volc_engine_api_key: self.volc_engine_api_key.or(fallback.volc_engine_api_key),
+            moonshot_api_key: self.moonshot_api_key.or(fallback.moonshot_api_key),
+            step_fun_api_key: self.step_fun_api_key.or(fallback.step_fun_api_key),
+            zhipu_api_key: self.zhipu_api_key.or(fallback.zhipu_api_key),
+            deep_seek_api_key: self.deep_seek_api_key.or(fallback.deep_seek_api_key),
+        }
+    }
+
+    /// The names of the providers without a key.
+    pub fn missing(&self) -> Vec<&'static str> {
+        [
+            ("OpenAI", &self.openai_api_key),
+            ("OpenRouter", &self.open_router_api_key),
+            ("VolcEngine", &self.volc_engine_api_key),
+            ("Moonshot", &self.moonshot_api_key),
+            ("StepFun", &self.step_fun_api_key),
+            ("Zhipu", &self.zhipu_api_key),
+            ("DeepSeek", &self.deep_seek_api_key),
+        ]
+        .into_iter()
+        .filter(|(_, key)| key.as_ref().is_none_or(|key| key.expose().is_empty()))
+        .map(|(provider, _)| provider)
+        .collect()
+    }
+}
+
+impl Settings {
+    /// Merges the command line and the environment with the config file, and checks that every
+    /// required setting is present. All missing settings are reported at once.
+    pub fn load(overrides: SettingsLayer) -> anyhow::Result<Self> {
+        let Some(config_directory) = overrides.config_directory.clone() else {
+            let error_msg = "The config directory is missing. Set it with --config-directory or CONFIG_DIRECTORY.";
+            tracing::error!("{}", error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        };
+
+        let config_file_name = overrides
+            .config_file_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CONFIG_FILE_NAME.to_string());
+
+        let from_file = SettingsLayer::from_config_file(&config_directory.join(&config_file_name))?;
+        let layer = overrides.merge(from_file);
+
+        let mut problems = vec![];
+        let mut require = |value: Option<String>, name: &str, env: &str| {
+            let value = value.filter(|v| !v.is_empty());
+            if value.is_none() {
+                problems.push(format!(
+                    "`{name}` is missing. Set it with --{}, {env} or in the [settings] table of the config file.",
+                    name.replace('_', "-")
+                ));
+            }
+            value.unwrap_or_default()
+        };
+
+        let sa_file_name = require(layer.sa_file_name, "sa_file_name", "SA_FILE_NAME");
+        let project_id = require(layer.project_id, "project_id", "PROJECT_ID");
+        let application_id = require(
+            layer.application_id.map(|id| id.to_string()),
+            "application_id",
+            "APPLICATION_ID",
+        );
+        let application_public_key = require(
+            layer.application_public_key,
+            "application_public_key",
+            "APPLICATION_PUBLIC_KEY",
+        );
+        let bot_token = require(layer.bot_token.map(|s| s.0), "bot_token", "BOT_TOKEN");
+        let google_api_key = require(
+            layer.google_api_key.map(|s| s.0),
+            "google_api_key",
+            "GOOGLE_API_KEY",
+        );
+
+        let log_level = layer
+            .log_level
+            .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string())
+            .to_uppercase();
+        if log_level.parse::<Level>().is_err() {
+            problems.push(format!(
+                "`log_level` has to be one of TRACE, DEBUG, INFO, WARN and ERROR, not `{log_level}`."
+            ));
+        }
+
+        if !application_public_key.is_empty() && hex::decode(&application_public_key).is_err() {
+            problems.push("`application_public_key` has to be hexadecimal.".to_string());
+        }
+
+        if !problems.is_empty() {
+            let error_msg = format!("The settings are invalid:\n- {}", problems.join("\n- "));
+            tracing::error!("{}", &error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        }
+
+        Ok(Settings {
+            config_directory,
+            config_file_name,
+            server_bind_point: layer
+                .server_bind_point
+                .unwrap_or_else(|| DEFAULT_SERVER_BIND_POINT.to_string()),
+            port: layer.port.unwrap_or(DEFAULT_PORT),
+            log_level,
+            sa_file_name,
+            project_id,
+            application_id: application_id.parse()?,
+            application_public_key,
+            bot_token: Secret(bot_token),
+            google_api_key: Secret(google_api_key),
+            api_keys: layer.api_keys,
+        })
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.config_directory.join(&self.config_file_name)
+    }
+
+    pub fn service_account_path(&self) -> PathBuf {
+        self.config_directory.join(&self.sa_file_name)
+    }
+
+    pub fn max_log_level(&self) -> Level {
+        self.log_level.parse().unwrap_or(Level::DEBUG)
+    }
+
+    /// The settings as TOML, with secrets redacted.
+    pub fn to_redacted_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
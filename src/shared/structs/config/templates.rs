@@ -0,0 +1,506 @@
+use std::fmt::Write;
+
+use minijinja::{
+    AutoEscape, Environment, Error, ErrorKind, Output, State, UndefinedBehavior, Value,
+};
+use serde::Serialize;
+
+use crate::shared::structs::agent::{Agent, Language};
+use crate::shared::structs::config::Configuration;
+
+const NO_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &[],
+    required: &[],
+};
+const TASK_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["instruction", "context", "agent"],
+    required: &[&["instruction"], &["context"], &["agent"]],
+};
+const AGENT_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["results", "candidates", "agent_transport"],
+    required: &[&["results", "candidates"], &["agent_transport"]],
+};
+const TRANSPORT_AGENT_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["retry_count", "is_last_retry", "maximum_retry_reached"],
+    required: &[],
+};
+const SYNTHESIS_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["results", "contexts"],
+    required: &[&["results", "contexts"]],
+};
+const JUDGE_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["task", "candidates"],
+    required: &[&["task"], &["candidates"]],
+};
+const SUMMARY_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["tokens", "content"],
+    required: &[&["content"]],
+};
+const REPAIR_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["conflicts"],
+    required: &[&["conflicts"]],
+};
+const SECONDS_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["seconds"],
+    required: &[&["seconds"]],
+};
+const QUEUE_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["position"],
+    required: &[&["position"]],
+};
+const PLAN_SUMMARY_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["analysis", "task_count"],
+    required: &[&["analysis"]],
+};
+const EXECUTING_TASK_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["task", "agent"],
+    required: &[&["task"]],
+};
+const TASK_COMPLETED_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["task"],
+    required: &[&["task"]],
+};
+const COST_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["cost", "tokens", "calls"],
+    required: &[&["cost"]],
+};
+const FAILED_CALLS_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["failed_calls"],
+    required: &[&["failed_calls"]],
+};
+const SLOWEST_MODEL_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["model", "seconds"],
+    required: &[&["model"]],
+};
+const CURRENCY_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["currency"],
+    required: &[],
+};
+const DAY_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["day"],
+    required: &[&["day"]],
+};
+const MINUTES_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["minutes"],
+    required: &[&["minutes"]],
+};
+const APPROVAL_TASK_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["task", "agent", "optional"],
+    required: &[&["task"], &["agent"]],
+};
+const DEPENDENCIES_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["dependencies"],
+    required: &[&["dependencies"]],
+};
+const COUNT_VARIABLES: TemplateVariables = TemplateVariables {
+    available: &["count"],
+    required: &[&["count"]],
+};
+
+/// Escapes the values interpolated into UI strings, which are shown as Discord markdown.
+const MARKDOWN_ESCAPE: AutoEscape = AutoEscape::Custom("markdown");
+
+/// The characters Discord reads as markdown, which are shown as is once escaped with a backslash.
+const MARKDOWN_CHARACTERS: [char; 10] = ['\\', '*', '_', '~', '`', '|', '>', '#', '[', ']'];
+
+/// The `$NAME` placeholders of older configs and the template variables they stand for.
+const LEGACY_PLACEHOLDERS: [(&str, &str); 11] = [
+    ("$INSTRUCTION", "{{ instruction }}"),
+    ("$CONTEXT", "{{ context }}"),
+    ("$AGENT_TRANSPORT", "{{ agent_transport }}"),
+    ("$AGENT", "{{ agent }}"),
+    ("$RESULTS", "{{ results }}"),
+    ("$RETRY_COUNT", "{{ retry_count }}"),
+    ("$MAXIMUM_RETRY_REACHED", "{{ maximum_retry_reached }}"),
+    ("$TASK", "{{ task }}"),
+    ("$CANDIDATES", "{{ candidates }}"),
+    ("$TOKENS", "{{ tokens }}"),
+    ("$CONTENT", "{{ content }}"),
+];
+
+/// Every prompt of the configuration compiled as a template. Values are inserted in a single
+/// pass, so user text that looks like a placeholder is left alone. Values in UI strings are
+/// escaped as markdown unless marked `safe`, while prompts pass them to the model as they are.
+#[derive(Debug, Default)]
+pub struct PromptTemplates {
+    environment: Environment<'static>,
+    languages: Vec<Language>,
+    default_language: Language,
+}
+
+struct TemplateVariables {
+    /// The variables the template is rendered with.
+    available: &'static [&'static str],
+    /// Each group needs at least one of its variables in the template, since the prompt would
+    /// ignore what it's given otherwise.
+    required: &'static [&'static [&'static str]],
+}
+
+impl PromptTemplates {
+    /// Compiles the prompts, and checks that none of them is empty and that each of them uses
+    /// the variables it needs and no others. All problems are reported at once.
+    pub fn compile(config: &Configuration) -> anyhow::Result<Self> {
+        let mut environment = Environment::new();
+        environment.set_undefined_behavior(UndefinedBehavior::Strict);
+        environment.set_auto_escape_callback(|name| {
+            if name.contains(".ui.") {
+                MARKDOWN_ESCAPE
+            } else {
+                AutoEscape::None
+            }
+        });
+        environment.set_formatter(format_value);
+        environment.set_keep_trailing_newline(true);
+
+        let mut problems = vec![];
+
+        for (name, source, variables) in collect_prompts(config) {
+            if source.trim().is_empty() {
+                problems.push(format!("Prompt template `{name}` is empty."));
+                continue;
+            }
+
+            let source = convert_legacy_placeholders(&source);
+
+            if let Err(e) = environment.add_template_owned(name.clone(), source) {
+                problems.push(format!("Prompt template `{name}` is invalid: {e}"));
+                continue;
+            }
+
+            let used_variables = environment.get_template(&name)?.undeclared_variables(false);
+
+            let mut undefined = used_variables
+                .iter()
+                .filter(|variable| !variables.available.contains(&variable.as_str()))
+                .collect::<Vec<_>>();
+            undefined.sort();
+
+            for variable in undefined.into_iter() {
+                problems.push(format!(
+                    "Prompt template `{name}` uses the undefined variable `{variable}`. Available variables: {}.",
+                    if variables.available.is_empty() {
+                        "none".to_string()
+                    } else {
+                        variables.available.join(", ")
+                    }
+                ));
+            }
+
+            for group in variables.required.iter() {
+                if !group
+                    .iter()
+                    .any(|variable| used_variables.contains(*variable))
+                {
+                    problems.push(format!(
+                        "Prompt template `{name}` is missing the variable `{}`.",
+                        group.join("` or `")
+                    ));
+                }
+            }
+        }
+
+        if !problems.is_empty() {
+            let error_msg = format!(
+                "The prompt templates are invalid:\n- {}",
+                problems.join("\n- ")
+            );
+            tracing::error!("{}", &error_msg);
+            return Err(anyhow::anyhow!("{}", error_msg));
+        }
+
+        Ok(PromptTemplates {
+            environment,
+            languages: config.languages.keys().cloned().collect(),
+            default_language: config.default_language.clone(),
+        })
+    }
+
+    pub fn render<S: Serialize>(&self, name: &str, context: S) -> anyhow::Result<String> {
+        let rendered = self
+            .environment
+            .get_template(name)
+            .and_then(|template| template.render(context));
+
+        match rendered {
+            Ok(rendered) => Ok(rendered),
+            Err(e) => {
+                let error_msg = format!("Failed to render prompt template `{name}`: {e}");
+                tracing::error!("{}", &error_msg);
+                Err(anyhow::anyhow!("{}", error_msg))
+            }
+        }
+    }
+
+    /// The name of a language's prompt, e.g. `ja.synthesis` or `en.agents.food.user_prompt`.
+    /// Languages that aren't configured use the closest configured one, or the default language.
+    pub fn name(&self, language: &Language, key: &str) -> String {
+        let language = language
+            .closest(&self.languages)
+            .unwrap_or(&self.default_language);
+
+        template_name(language, key)
+    }
+
+    pub fn agent_name(&self, language: &Language, agent: &Agent, key: &str) -> String {
+        self.name(language, &agent_key(agent, key))
+    }
+
+    /// Renders one of a language's UI strings, e.g. `synthesizing`.
+    pub fn ui<S: Serialize>(
+        &self,
+        language: &Language,
+        key: &str,
+        context: S,
+    ) -> anyhow::Result<String> {
+        self.render(&self.name(language, &format!("ui.{key}")), context)
+    }
+}
+
+fn template_name(language: &Language, key: &str) -> String {
+    format!("{language}.{key}")
+}
+
+fn agent_key(agent: &Agent, key: &str) -> String {
+    format!("agents.{agent}.{key}")
+}
+
+fn collect_prompts(config: &Configuration) -> Vec<(String, String, TemplateVariables)> {
+    let mut prompts = vec![
+        (
+            "language_triage_prompt".to_string(),
+            config.language_triage_prompt.clone(),
+            NO_VARIABLES,
+        ),
+        (
+            "ranking.judge_prompt".to_string(),
+            config.ranking.judge_prompt.clone(),
+            JUDGE_VARIABLES,
+        ),
+        (
+            "context_window.summary_prompt".to_string(),
+            config.context_window.summary_prompt.clone(),
+            SUMMARY_VARIABLES,
+        ),
+        (
+            "validation.repair_prompt".to_string(),
+            config.validation.repair_prompt.clone(),
+            REPAIR_VARIABLES,
+        ),
+    ];
+
+    for (language, language_config) in config.languages.iter() {
+        let language_prompts = &language_config.prompts;
+
+        let shared_prompts = [
+            ("orchestrator", &language_prompts.orchestrator, NO_VARIABLES),
+            ("naming", &language_prompts.naming, NO_VARIABLES),
+            ("agent", &language_prompts.agent, AGENT_VARIABLES),
+            (
+                "synthesis",
+                &language_prompts.synthesis,
+                SYNTHESIS_VARIABLES,
+            ),
+            (
+                "transport_agent",
+                &language_prompts.transport_agent,
+                TRANSPORT_AGENT_VARIABLES,
+            ),
+            (
+                "transport_agent_maximum_try",
+                &language_prompts.transport_agent_maximum_try,
+                NO_VARIABLES,
+            ),
+        ];
+
+        for (key, prompt, variables) in shared_prompts.into_iter() {
+            prompts.push((
+                template_name(language, key),
+                prompt.prompt.clone(),
+                variables,
+            ));
+        }
+
+        let ui = &language_config.ui;
+        let ui_strings = [
+            (
+                "daily_budget_reached",
+                &ui.daily_budget_reached,
+                NO_VARIABLES,
+            ),
+            ("user_cooldown", &ui.user_cooldown, SECONDS_VARIABLES),
+            ("guild_cooldown", &ui.guild_cooldown, SECONDS_VARIABLES),
+            ("queue_position", &ui.queue_position, QUEUE_VARIABLES),
+            (
+                "execution_plan_title",
+                &ui.execution_plan_title,
+                NO_VARIABLES,
+            ),
+            ("plan_summary", &ui.plan_summary, PLAN_SUMMARY_VARIABLES),
+            ("cancel_button", &ui.cancel_button, NO_VARIABLES),
+            (
+                "executing_task",
+                &ui.executing_task,
+                EXECUTING_TASK_VARIABLES,
+            ),
+            (
+                "task_completed",
+                &ui.task_completed,
+                TASK_COMPLETED_VARIABLES,
+            ),
+            ("plan_budget_reached", &ui.plan_budget_reached, NO_VARIABLES),
+            ("synthesizing", &ui.synthesizing, NO_VARIABLES),
+            ("plan_cancelled", &ui.plan_cancelled, NO_VARIABLES),
+            ("cost_summary", &ui.cost_summary, COST_VARIABLES),
+            ("failed_calls", &ui.failed_calls, FAILED_CALLS_VARIABLES),
+            ("slowest_model", &ui.slowest_model, SLOWEST_MODEL_VARIABLES),
+            ("budget_title", &ui.budget_title, CURRENCY_VARIABLES),
+            ("budget_day", &ui.budget_day, DAY_VARIABLES),
+            ("budget_food", &ui.budget_food, NO_VARIABLES),
+            ("budget_transit", &ui.budget_transit, NO_VARIABLES),
+            ("budget_admission", &ui.budget_admission, NO_VARIABLES),
+            ("budget_lodging", &ui.budget_lodging, NO_VARIABLES),
+            ("budget_total", &ui.budget_total, NO_VARIABLES),
+            ("itinerary_day", &ui.itinerary_day, DAY_VARIABLES),
+            (
+                "itinerary_public_transport",
+                &ui.itinerary_public_transport,
+                MINUTES_VARIABLES,
+            ),
+            (
+                "itinerary_drive_or_taxi",
+                &ui.itinerary_drive_or_taxi,
+                MINUTES_VARIABLES,
+            ),
+            ("approval_title", &ui.approval_title, NO_VARIABLES),
+            ("approval_task", &ui.approval_task, APPROVAL_TASK_VARIABLES),
+            (
+                "approval_dependencies",
+                &ui.approval_dependencies,
+                DEPENDENCIES_VARIABLES,
+            ),
+            ("approve_button", &ui.approve_button, NO_VARIABLES),
+            ("edit_button", &ui.edit_button, NO_VARIABLES),
+            ("plan_approved", &ui.plan_approved, NO_VARIABLES),
+            ("plan_rejected", &ui.plan_rejected, NO_VARIABLES),
+            ("plan_revising", &ui.plan_revising, NO_VARIABLES),
+            ("revision_failed", &ui.revision_failed, NO_VARIABLES),
+            ("approval_timed_out", &ui.approval_timed_out, NO_VARIABLES),
+            (
+                "approval_not_pending",
+                &ui.approval_not_pending,
+                NO_VARIABLES,
+            ),
+            (
+                "approval_not_requester",
+                &ui.approval_not_requester,
+                NO_VARIABLES,
+            ),
+            ("revision_title", &ui.revision_title, NO_VARIABLES),
+            ("revision_label", &ui.revision_label, NO_VARIABLES),
+            (
+                "revision_placeholder",
+                &ui.revision_placeholder,
+                NO_VARIABLES,
+            ),
+            ("cancelling_plan", &ui.cancelling_plan, NO_VARIABLES),
+            ("cancelling_plans", &ui.cancelling_plans, COUNT_VARIABLES),
+            (
+                "cancel_not_requester",
+                &ui.cancel_not_requester,
+                NO_VARIABLES,
+            ),
+            ("no_plans_running", &ui.no_plans_running, NO_VARIABLES),
+            ("plan_not_running", &ui.plan_not_running, NO_VARIABLES),
+        ];
+
+        for (key, string, variables) in ui_strings.into_iter() {
+            prompts.push((
+                template_name(language, &format!("ui.{key}")),
+                string.clone(),
+                variables,
+            ));
+        }
+
+        for (agent, agent_config) in config.agents.iter() {
+            let agent_prompts = agent_config
+                .prompts
+                .get(language)
+                .cloned()
+                .unwrap_or_default();
+
+            prompts.push((
+                template_name(language, &agent_key(agent, "system_prompt")),
+                agent_prompts.system_prompt,
+                NO_VARIABLES,
+            ));
+            prompts.push((
+                template_name(language, &agent_key(agent, "user_prompt")),
+                agent_prompts.user_prompt,
+                TASK_VARIABLES,
+            ));
+        }
+    }
+
+    prompts
+}
+
+/// Rewrites the `$NAME` placeholders of older configs into template variables. Other text that
+/// looks like a placeholder, such as `$USD`, is kept as it is.
+fn convert_legacy_placeholders(source: &str) -> String {
+    let mut converted = String::with_capacity(source.len());
+    let mut rest = source;
+
+    while let Some(index) = rest.find('$') {
+        converted.push_str(&rest[..index]);
+
+        let length = rest[index + 1..]
+            .find(|c: char| !(c.is_ascii_uppercase() || c == '_'))
+            .unwrap_or(rest.len() - index - 1);
+        let placeholder = &rest[index..index + 1 + length];
+
+        match LEGACY_PLACEHOLDERS
+            .iter()
+            .find(|(legacy, _)| *legacy == placeholder)
+        {
+            Some((_, variable)) => converted.push_str(variable),
+            None => converted.push_str(placeholder),
+        }
+
+        rest = &rest[index + 1 + length..];
+    }
+
+    converted.push_str(rest);
+    converted
+}
+
+fn format_value(
+    output: &mut Output<'_>,
+    state: &State<'_, '_>,
+    value: &Value,
+) -> Result<(), Error> {
+    if state.auto_escape() != MARKDOWN_ESCAPE {
+        return minijinja::escape_formatter(output, state, value);
+    }
+
+    let formatted = match value.as_str() {
+        Some(string) if !value.is_safe() => escape_markdown(string),
+        _ => value.to_string(),
+    };
+
+    output
+        .write_str(&formatted)
+        .map_err(|e| Error::new(ErrorKind::WriteFailure, "Failed to write a value.").with_source(e))
+}
+
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if MARKDOWN_CHARACTERS.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
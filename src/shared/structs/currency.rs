@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+/// Exchange rates of a day, refreshed outside of the bot, e.g.
+/// `{ "date": "2026-10-17", "base": "USD", "rates": { "JPY": 150.1, "TWD": 30.6 } }`.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct RateTable {
+    pub date: NaiveDate,
+    /// The ISO 4217 code of the currency the rates are quoted against.
+    pub base: String,
+    /// How much of each currency one unit of the base currency buys.
+    pub rates: HashMap<String, f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConversionRequest {
+    pub conversions: Vec<Conversion>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Conversion {
+    pub amount: f64,
+    pub from: String,
+    /// The traveller's currency is used if absent.
+    pub to: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ConvertedAmount {
+    /// The original amount, formatted for the traveller's language, e.g. `¥12,000`.
+    pub from: String,
+    /// The converted amount, formatted for the traveller's language, e.g. `NT$2,450`.
+    pub to: Option<String>,
+    pub amount: Option<f64>,
+    /// The date of the rates the amount was converted with.
+    pub rate_date: Option<NaiveDate>,
+    /// Why the amount couldn't be converted.
+    pub error: Option<String>,
+}
+
+impl RateTable {
+    /// Converts between any two currencies of the table through the base currency.
+    pub fn convert(&self, amount: f64, from: &str, to: &str) -> Option<f64> {
+        Some(amount / self.rate(from)? * self.rate(to)?)
+    }
+
+    fn rate(&self, currency: &str) -> Option<f64> {
+        if currency == self.base {
+            return Some(1.0);
+        }
+
+        self.rates
+            .get(currency)
+            .copied()
+            .filter(|rate| rate.is_finite() && *rate > 0.0)
+    }
+}
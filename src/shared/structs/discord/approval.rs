@@ -0,0 +1,15 @@
+use serenity::all::UserId;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone)]
+pub enum ApprovalDecision {
+    Approve,
+    Cancel,
+    Revise(String),
+}
+
+#[derive(Debug)]
+pub struct PendingApproval {
+    pub user_id: UserId,
+    pub sender: oneshot::Sender<ApprovalDecision>,
+}
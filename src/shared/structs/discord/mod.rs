@@ -1 +1,3 @@
+pub mod approval;
 pub mod interaction;
+pub mod running_plan;
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use serenity::all::{ChannelId, UserId};
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct RunningPlan {
+    pub plan_id: Uuid,
+    pub user_id: UserId,
+    pub cancellation_token: CancellationToken,
+}
+
+/// Removes a plan from the running plans registry when the plan finishes, however it finishes.
+pub struct RunningPlanGuard {
+    pub thread_id: ChannelId,
+    pub running_plans: Arc<DashMap<ChannelId, RunningPlan>>,
+}
+
+impl Drop for RunningPlanGuard {
+    fn drop(&mut self) {
+        self.running_plans.remove(&self.thread_id);
+    }
+}
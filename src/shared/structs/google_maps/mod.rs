@@ -1,5 +1,8 @@
+use google_maps::LatLng;
 use serde::{Deserialize, Serialize};
 
+use crate::shared::structs::agent::Language;
+
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct TransferPlan {
     pub routes: Vec<Route>,
@@ -18,6 +21,8 @@ pub struct RouteWithDuration {
     pub to: String,
     pub by: TransferMethod,
     pub duration: String,
+    /// The fare of the whole route as given by the routing API, e.g. `¥210`.
+    pub fare: Option<String>,
     pub alternative: AlternativeTravelDuration,
 }
 
@@ -25,6 +30,7 @@ pub struct RouteWithDuration {
 pub struct AlternativeTravelDuration {
     pub by: TransferMethod,
     pub duration: Option<String>,
+    pub fare: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
@@ -33,3 +39,100 @@ pub enum TransferMethod {
     DriveOrTaxi,
     PublicTransport,
 }
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CentralityRequest {
+    pub areas: Vec<String>,
+    pub places: Vec<String>,
+    pub by: TransferMethod,
+}
+
+/// How well an area to stay in is connected to the places of the itinerary.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct AreaCentrality {
+    pub area: String,
+    /// The average travel time to the reachable places in minutes.
+    pub average_minutes: Option<f64>,
+    pub longest_minutes: Option<i64>,
+    pub farthest_place: Option<String>,
+    /// Places without a route from the area.
+    pub unreachable: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct PlaceSuggestion {
+    pub name: String,
+    pub place_id: String,
+    pub location: LatLng,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CachedPlaces {
+    pub query: String,
+    pub language: Language,
+    pub suggestions: Vec<PlaceSuggestion>,
+    /// Entries cached before suggestions expired count as expired.
+    #[serde(default)]
+    pub expires_at: i64,
+}
+
+/// The regular opening hours of a place as given by the Places API.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct OpeningHours {
+    #[serde(default)]
+    pub periods: Vec<OpeningPeriod>,
+    /// E.g. `Monday: 9:00 AM – 5:00 PM`.
+    #[serde(default)]
+    pub weekday_descriptions: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct OpeningPeriod {
+    pub open: OpeningTime,
+    /// Places that are always open have no closing time.
+    pub close: Option<OpeningTime>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy)]
+pub struct OpeningTime {
+    /// From 0 for Sunday to 6 for Saturday.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+}
+
+impl OpeningHours {
+    /// Whether the place is open for the whole visit, given as minutes since midnight on a day
+    /// from 0 for Sunday to 6 for Saturday.
+    pub fn is_open(&self, day: u32, (from, to): (u32, u32)) -> bool {
+        const MINUTES_PER_WEEK: u32 = 7 * 24 * 60;
+
+        let start = day * 24 * 60 + from;
+        let end = day * 24 * 60 + to;
+
+        self.periods.iter().any(|period| {
+            let Some(close) = period.close else {
+                return true;
+            };
+
+            let open = period.open.minutes_of_week();
+            let mut close = close.minutes_of_week();
+            if close <= open {
+                close += MINUTES_PER_WEEK;
+            }
+
+            // Periods that run past the end of the week also cover visits early in the week.
+            [start, start + MINUTES_PER_WEEK]
+                .into_iter()
+                .zip([end, end + MINUTES_PER_WEEK])
+                .any(|(start, end)| open <= start && end <= close)
+        })
+    }
+}
+
+impl OpeningTime {
+    fn minutes_of_week(&self) -> u32 {
+        (self.day * 24 + self.hour) * 60 + self.minute
+    }
+}
@@ -1,15 +1,34 @@
-use std::sync::Arc;
+use std::{collections::HashMap, path::Path, sync::Arc};
 
+use arc_swap::ArcSwap;
 use async_openai::config::OpenAIConfig;
 use dashmap::DashMap;
-use serenity::all::Http;
+use serenity::all::{ChannelId, Http};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use uuid::Uuid;
 
-use crate::shared::structs::{agent::Agent, config::Configuration};
+use crate::shared::structs::{
+    admission::AdmissionController,
+    agent::Provider,
+    config::{
+        Admission, Cache, Configuration,
+        settings::{ApiKeys, Secret, Settings},
+    },
+    discord::{approval::PendingApproval, running_plan::RunningPlan},
+};
+use crate::shared::utility::cache::ResponseCache;
+use crate::shared::utility::currency::ExchangeRates;
 
+pub mod admission;
 pub mod agent;
+pub mod budget;
+pub mod calendar;
+pub mod climate;
 pub mod config;
+pub mod currency;
 pub mod discord;
 pub mod google_maps;
+pub mod validation;
 
 const OPEN_ROUTER_BASE_URL: &str = "https://openrouter.ai/api/v1";
 const VOLC_ENGINE_BASE_URL: &str = "https://ark.cn-beijing.volces.com/api/v3";
@@ -20,76 +39,137 @@ const DEEP_SEEK_BASE_URL: &str = "https://api.deepseek.com";
 
 #[derive(Debug, Clone)]
 pub struct AppState {
-    pub config: Configuration,
+    /// Swapped out whenever the config file changes. Use `config()` to read it.
+    pub config: Arc<ArcSwap<Configuration>>,
+    pub settings: Arc<Settings>,
     pub llm_clients: Arc<LLMClients>,
     pub http_client: reqwest::Client,
     pub http: Arc<Http>,
     pub firestore_db: firestore::FirestoreDb,
     pub google_maps_client: Arc<::google_maps::Client>,
+    pub pending_approvals: Arc<DashMap<Uuid, PendingApproval>>,
+    pub running_plans: Arc<DashMap<ChannelId, RunningPlan>>,
+    pub admission: Arc<AdmissionController>,
+    pub exchange_rates: Arc<ExchangeRates>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LLMClients {
-    pub open_router_clients: DashMap<Agent, async_openai::Client<OpenAIConfig>>,
+    pub open_router_client: async_openai::Client<OpenAIConfig>,
     pub openai_client: async_openai::Client<OpenAIConfig>,
     pub volc_engine_client: async_openai::Client<OpenAIConfig>,
     pub moonshot_client: async_openai::Client<OpenAIConfig>,
     pub step_fun_client: async_openai::Client<OpenAIConfig>,
     pub zhipu_client: async_openai::Client<OpenAIConfig>,
     pub deepseek_client: async_openai::Client<OpenAIConfig>,
+    pub provider_limits: HashMap<Provider, Arc<Semaphore>>,
+    pub cache: Option<Arc<ResponseCache>>,
+}
+
+impl AppState {
+    /// The current config. Holding on to it keeps a consistent view even if it's reloaded.
+    pub fn config(&self) -> Arc<Configuration> {
+        self.config.load_full()
+    }
 }
 
 impl LLMClients {
-    pub fn new() -> Self {
-        let openai_config =
-            OpenAIConfig::new().with_api_key(std::env::var("OPENAI_API_KEY").unwrap_or_default());
+    /// Relative cache directories are resolved against `config_directory`.
+    pub fn new(config: &Configuration, api_keys: &ApiKeys, config_directory: &Path) -> Self {
+        let openai_config = OpenAIConfig::new().with_api_key(expose(&api_keys.openai_api_key));
         let openai_client = async_openai::Client::with_config(openai_config);
 
-        let open_router_clients = DashMap::new();
-        let agents = [
-            Agent::Food,
-            Agent::History,
-            Agent::Modern,
-            Agent::Nature,
-            Agent::Transport,
-        ];
-
-        for agent in agents.into_iter() {
-            open_router_clients.insert(
-                agent,
-                Self::initialize_compatible_client(
-                    OPEN_ROUTER_BASE_URL,
-                    std::env::var("OPEN_ROUTER_API_KEY").unwrap_or_default(),
-                ),
-            );
-        }
-
         LLMClients {
-            open_router_clients,
+            open_router_client: Self::initialize_compatible_client(
+                OPEN_ROUTER_BASE_URL,
+                expose(&api_keys.open_router_api_key),
+            ),
             openai_client,
             volc_engine_client: Self::initialize_compatible_client(
                 VOLC_ENGINE_BASE_URL,
-                std::env::var("VOLC_ENGINE_API_KEY").unwrap_or_default(),
+                expose(&api_keys.volc_engine_api_key),
             ),
             moonshot_client: Self::initialize_compatible_client(
                 MOONSHOT_BASE_URL,
-                std::env::var("MOONSHOT_API_KEY").unwrap_or_default(),
+                expose(&api_keys.moonshot_api_key),
             ),
             step_fun_client: Self::initialize_compatible_client(
                 STEP_FUN_BASE_URL,
-                std::env::var("STEP_FUN_API_KEY").unwrap_or_default(),
+                expose(&api_keys.step_fun_api_key),
             ),
             zhipu_client: Self::initialize_compatible_client(
                 ZHIPU_BASE_URL,
-                std::env::var("ZHIPU_API_KEY").unwrap_or_default(),
+                expose(&api_keys.zhipu_api_key),
             ),
             deepseek_client: Self::initialize_compatible_client(
                 DEEP_SEEK_BASE_URL,
-                std::env::var("DEEP_SEEK_API_KEY").unwrap_or_default(),
+                expose(&api_keys.deep_seek_api_key),
             ),
+            provider_limits: Self::initialize_provider_limits(&config.admission),
+            cache: Self::initialize_cache(&config.cache, config_directory),
+        }
+    }
+
+    pub fn client(&self, provider: Provider) -> async_openai::Client<OpenAIConfig> {
+        match provider {
+            Provider::OpenAI => self.openai_client.clone(),
+            Provider::OpenRouter => self.open_router_client.clone(),
+            Provider::VolcEngine => self.volc_engine_client.clone(),
+            Provider::Moonshot => self.moonshot_client.clone(),
+            Provider::StepFun => self.step_fun_client.clone(),
+            Provider::Zhipu => self.zhipu_client.clone(),
+            Provider::DeepSeek => self.deepseek_client.clone(),
+        }
+    }
+
+    /// Waits until the provider has capacity for another request. The slot is released when the
+    /// permit is dropped.
+    pub async fn acquire(&self, provider: Provider) -> anyhow::Result<OwnedSemaphorePermit> {
+        let semaphore = self
+            .provider_limits
+            .get(&provider)
+            .cloned()
+            .ok_or(anyhow::anyhow!(
+                "No concurrency limit configured for {provider}."
+            ))?;
+
+        Ok(semaphore.acquire_owned().await?)
+    }
+
+    fn initialize_cache(cache: &Cache, config_directory: &Path) -> Option<Arc<ResponseCache>> {
+        match ResponseCache::open(cache, config_directory) {
+            Ok(cache) => cache.map(Arc::new),
+            Err(e) => {
+                tracing::error!("Failed to open the LLM response cache, so it's disabled: {e:?}");
+                None
+            }
         }
     }
 
+    fn initialize_provider_limits(admission: &Admission) -> HashMap<Provider, Arc<Semaphore>> {
+        [
+            Provider::OpenAI,
+            Provider::OpenRouter,
+            Provider::VolcEngine,
+            Provider::Moonshot,
+            Provider::StepFun,
+            Provider::Zhipu,
+            Provider::DeepSeek,
+        ]
+        .into_iter()
+        .map(|provider| {
+            let permits = admission
+                .provider_concurrency
+                .get(&provider.to_string())
+                .copied()
+                .unwrap_or(admission.default_provider_concurrency)
+                .max(1);
+
+            (provider, Arc::new(Semaphore::new(permits)))
+        })
+        .collect()
+    }
+
     fn initialize_compatible_client(
         base_url: &str,
         api_key: String,
@@ -101,3 +181,10 @@ impl LLMClients {
         async_openai::Client::with_config(config)
     }
 }
+
+fn expose(api_key: &Option<Secret>) -> String {
+    api_key
+        .as_ref()
+        .map(|key| key.expose().to_string())
+        .unwrap_or_default()
+}
@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// A part of the itinerary that can't work out as scheduled.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ScheduleConflict {
+    pub day: u32,
+    pub place: String,
+    pub kind: ConflictKind,
+    pub description: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictKind {
+    /// The place isn't open during the visit.
+    Closed,
+    /// Getting from the previous stop takes longer than the time between the stops.
+    NotEnoughTransitTime,
+}
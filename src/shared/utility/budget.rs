@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use firestore::FirestoreConsistencySelector;
+use serenity::all::UserId;
+use uuid::Uuid;
+
+use crate::shared::{
+    USAGE_COLLECTION_NAME,
+    structs::{
+        AppState,
+        agent::record::GenerationDump,
+        budget::{DailyUsage, PlanBudget},
+    },
+};
+
+/// Creates the budget of a plan, which is the smaller of the per-plan limit and what's left of
+/// the user's daily limit. Whatever the plan has spent already is charged right away.
+pub async fn create_plan_budget(
+    user_id: UserId,
+    dumps: &[GenerationDump],
+    app_state: &AppState,
+) -> anyhow::Result<Arc<PlanBudget>> {
+    let config = app_state.config();
+
+    let daily_remaining = match config.budget.max_user_daily_cost {
+        Some(max_daily_cost) => {
+            let usage = load_daily_usage(user_id, &app_state.firestore_db).await?;
+            Some((max_daily_cost - usage.map_or(0.0, |u| u.cost)).max(0.0))
+        }
+        None => None,
+    };
+
+    let limit = match (config.budget.max_plan_cost, daily_remaining) {
+        (Some(plan_limit), Some(daily_limit)) => Some(plan_limit.min(daily_limit)),
+        (plan_limit, daily_limit) => plan_limit.or(daily_limit),
+    };
+
+    let budget = PlanBudget::new(limit, &config.budget, config.prices.clone());
+    for dump in dumps.iter() {
+        budget.charge(dump);
+    }
+
+    Ok(Arc::new(budget))
+}
+
+/// Whether the user has used up their daily budget.
+pub async fn is_daily_budget_exhausted(
+    user_id: UserId,
+    app_state: &AppState,
+) -> anyhow::Result<bool> {
+    let Some(max_daily_cost) = app_state.config().budget.max_user_daily_cost else {
+        return Ok(false);
+    };
+
+    let usage = load_daily_usage(user_id, &app_state.firestore_db).await?;
+    Ok(usage.is_some_and(|u| u.cost >= max_daily_cost))
+}
+
+/// Adds the cost of a finished, cancelled or failed plan to the user's spending of the day. The
+/// usage is read and written in a transaction, and each plan is only charged once.
+pub async fn record_daily_usage(
+    user_id: UserId,
+    plan_id: Uuid,
+    cost: f64,
+    firestore_db: &firestore::FirestoreDb,
+) -> anyhow::Result<()> {
+    let result = add_daily_usage(user_id, plan_id, cost, firestore_db).await;
+
+    if let Err(e) = result {
+        let error_msg = format!("Failed to record the daily usage of user {user_id}: {e:?}");
+        tracing::error!("{}", &error_msg);
+        return Err(anyhow::anyhow!("{}", error_msg));
+    }
+
+    Ok(())
+}
+
+async fn add_daily_usage(
+    user_id: UserId,
+    plan_id: Uuid,
+    cost: f64,
+    firestore_db: &firestore::FirestoreDb,
+) -> anyhow::Result<()> {
+    let mut transaction = firestore_db.begin_transaction().await?;
+    let transaction_db = firestore_db.clone_with_consistency_selector(
+        FirestoreConsistencySelector::Transaction(transaction.transaction_id().clone()),
+    );
+
+    let mut usage = load_daily_usage(user_id, &transaction_db)
+        .await?
+        .unwrap_or(DailyUsage {
+            user_id,
+            date: today(),
+            cost: 0.0,
+            plans: 0,
+            plan_ids: vec![],
+        });
+
+    if usage.plan_ids.contains(&plan_id) {
+        transaction.rollback().await?;
+        return Ok(());
+    }
+
+    usage.cost += cost;
+    usage.plans += 1;
+    usage.plan_ids.push(plan_id);
+
+    firestore_db
+        .fluent()
+        .update()
+        .in_col(USAGE_COLLECTION_NAME)
+        .document_id(usage_document_id(user_id))
+        .object(&usage)
+        .add_to_transaction(&mut transaction)?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+async fn load_daily_usage(
+    user_id: UserId,
+    firestore_db: &firestore::FirestoreDb,
+) -> anyhow::Result<Option<DailyUsage>> {
+    let usage = firestore_db
+        .fluent()
+        .select()
+        .by_id_in(USAGE_COLLECTION_NAME)
+        .obj::<DailyUsage>()
+        .one(usage_document_id(user_id))
+        .await?;
+
+    Ok(usage)
+}
+
+fn usage_document_id(user_id: UserId) -> String {
+    format!("{user_id}:{}", today())
+}
+
+fn today() -> String {
+    chrono::Utc::now().format("%Y-%m-%d").to_string()
+}
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+
+use crate::shared::structs::config::{Cache, CacheMode};
+
+/// Evictions free up space until the cache is this much of its maximum size.
+const EVICTION_TARGET_RATIO: f64 = 0.9;
+
+/// Stores chat completion responses on disk, keyed by a hash of the request.
+#[derive(Debug)]
+pub struct ResponseCache {
+    mode: CacheMode,
+    directory: PathBuf,
+    ttl: i64,
+    max_size: u64,
+    /// The current size of the cache in bytes. The lock also serializes evictions.
+    size: Mutex<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheEntry {
+    created_at: i64,
+    response: CreateChatCompletionResponse,
+}
+
+impl ResponseCache {
+    /// Opens the cache directory, or returns `None` if the cache is turned off.
+    pub fn open(cache: &Cache, config_directory: &Path) -> anyhow::Result<Option<Self>> {
+        if cache.mode == CacheMode::Off {
+            return Ok(None);
+        }
+
+        let directory = PathBuf::from(&cache.directory);
+        let directory = if directory.is_relative() {
+            config_directory.join(directory)
+        } else {
+            directory
+        };
+
+        std::fs::create_dir_all(&directory)?;
+
+        let mut size = 0;
+        for entry in std::fs::read_dir(&directory)? {
+            size += entry?.metadata()?.len();
+        }
+
+        tracing::info!(
+            "Opened the LLM response cache at {} in {:?} mode ({} bytes).",
+            directory.display(),
+            cache.mode,
+            size
+        );
+
+        Ok(Some(ResponseCache {
+            mode: cache.mode,
+            directory,
+            ttl: cache.ttl as i64,
+            max_size: cache.max_size_mb * 1024 * 1024,
+            size: Mutex::new(size),
+        }))
+    }
+
+    /// Hashes everything that affects the response, i.e. the model, the messages, the sampling
+    /// parameters and the tools.
+    pub fn key(request: &CreateChatCompletionRequest) -> anyhow::Result<String> {
+        let serialized = serde_json::to_vec(request)?;
+        Ok(hex::encode(Sha256::digest(&serialized)))
+    }
+
+    pub fn is_replay(&self) -> bool {
+        self.mode == CacheMode::Replay
+    }
+
+    pub async fn get(&self, key: &str) -> Option<CreateChatCompletionResponse> {
+        let raw_entry = tokio::fs::read(self.entry_path(key)).await.ok()?;
+
+        let entry = match serde_json::from_slice::<CacheEntry>(&raw_entry) {
+            Ok(entry) => entry,
+            Err(e) => {
+                tracing::warn!("Failed to read cached response {key}: {e:?}");
+                return None;
+            }
+        };
+
+        let expired = chrono::Utc::now().timestamp() - entry.created_at > self.ttl;
+        if expired && !self.is_replay() {
+            return None;
+        }
+
+        Some(entry.response)
+    }
+
+    pub async fn put(
+        &self,
+        key: &str,
+        response: &CreateChatCompletionResponse,
+    ) -> anyhow::Result<()> {
+        // Replays must stay deterministic, so the cache is never written to.
+        if self.is_replay() {
+            return Ok(());
+        }
+
+        let entry = CacheEntry {
+            created_at: chrono::Utc::now().timestamp(),
+            response: response.clone(),
+        };
+
+        let serialized = serde_json::to_vec(&entry)?;
+        let path = self.entry_path(key);
+        let previous_size = tokio::fs::metadata(&path).await.map_or(0, |m| m.len());
+
+        tokio::fs::write(&path, &serialized).await?;
+
+        let mut size = self.size.lock().await;
+        *size = (*size + serialized.len() as u64).saturating_sub(previous_size);
+
+        if *size > self.max_size {
+            *size = self.evict().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the oldest responses until the cache is below its target size, and returns the
+    /// new size.
+    async fn evict(&self) -> anyhow::Result<u64> {
+        let mut entries = vec![];
+        let mut read_dir = tokio::fs::read_dir(&self.directory).await?;
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            entries.push((entry.path(), metadata.modified()?, metadata.len()));
+        }
+
+        entries.sort_by_key(|(_, modified, _)| *modified);
+
+        let mut size = entries.iter().map(|(_, _, len)| *len).sum::<u64>();
+        let target_size = (self.max_size as f64 * EVICTION_TARGET_RATIO) as u64;
+        let mut evicted = 0;
+
+        for (path, _, len) in entries.into_iter() {
+            if size <= target_size {
+                break;
+            }
+
+            tokio::fs::remove_file(&path).await?;
+            size -= len;
+            evicted += 1;
+        }
+
+        tracing::info!("Evicted {evicted} responses from the LLM response cache.");
+
+        Ok(size)
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.json"))
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate};
+use once_cell::sync::Lazy;
+
+use crate::shared::structs::calendar::{CalendarDay, CalendarInfo, CountryCalendar};
+
+/// Keyed by ISO 3166-1 alpha-2 country code, e.g. `JP`.
+static COUNTRY_CALENDARS: Lazy<HashMap<String, CountryCalendar>> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../../data/holidays.json"))
+        .expect("Failed to parse the bundled holidays.")
+});
+
+/// The holidays, day of the week and usual closures of each date in the country, or an error if
+/// the country isn't in the dataset.
+pub fn get_calendar_info(
+    location: String,
+    country_code: Option<String>,
+    dates: &[String],
+) -> CalendarInfo {
+    let Some(calendar) = country_code
+        .as_ref()
+        .and_then(|code| COUNTRY_CALENDARS.get(code))
+    else {
+        return CalendarInfo {
+            location,
+            country: country_code,
+            days: vec![],
+            error: Some("There's no holiday calendar for the country of this location.".into()),
+        };
+    };
+
+    let days = dates
+        .iter()
+        .map(|date| calendar_day(calendar, date))
+        .collect();
+
+    CalendarInfo {
+        location,
+        country: Some(calendar.name.clone()),
+        days,
+        error: None,
+    }
+}
+
+fn calendar_day(calendar: &CountryCalendar, date: &str) -> CalendarDay {
+    let parsed = match NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d") {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            return CalendarDay {
+                date: date.to_string(),
+                day_of_week: None,
+                holidays: vec![],
+                closures: vec![],
+                error: Some("The date has to be in the YYYY-MM-DD format.".into()),
+            };
+        }
+    };
+
+    let day_of_week = parsed.format("%A").to_string();
+    let closures = calendar
+        .weekly_closures
+        .iter()
+        .filter(|closure| closure.weekday.eq_ignore_ascii_case(&day_of_week))
+        .map(|closure| closure.note.clone())
+        .collect();
+
+    // The day of the week and closures are still useful for years without holidays.
+    let year = parsed.year();
+    if !calendar.years.contains(&year) {
+        return CalendarDay {
+            date: parsed.to_string(),
+            day_of_week: Some(day_of_week),
+            holidays: vec![],
+            closures,
+            error: Some(format!("The holidays of {year} aren't known.")),
+        };
+    }
+
+    let holidays = calendar
+        .holidays
+        .iter()
+        .filter(|holiday| holiday.date == parsed)
+        .map(|holiday| holiday.name.clone())
+        .collect();
+
+    CalendarDay {
+        date: parsed.to_string(),
+        day_of_week: Some(day_of_week),
+        holidays,
+        closures,
+        error: None,
+    }
+}
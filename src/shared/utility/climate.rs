@@ -0,0 +1,114 @@
+use google_maps::LatLng;
+use once_cell::sync::Lazy;
+
+use crate::shared::MAX_CLIMATE_STATION_DISTANCE;
+use crate::shared::structs::climate::{ClimateNormals, ClimateStation, MonthlyNormals};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+/// The days of the year in the middle of each month.
+const MID_MONTH_DAYS: [f64; 12] = [
+    15.0, 46.0, 74.0, 105.0, 135.0, 166.0, 196.0, 227.0, 258.0, 288.0, 319.0, 349.0,
+];
+
+static CLIMATE_STATIONS: Lazy<Vec<ClimateStation>> = Lazy::new(|| {
+    serde_json::from_str(include_str!("../../../data/climate_normals.json"))
+        .expect("Failed to parse the bundled climate normals.")
+});
+
+/// The normals of the destination closest to `location` for the given months, or an error if
+/// the place couldn't be located or no destination of the dataset is within
+/// `MAX_CLIMATE_STATION_DISTANCE` kilometres.
+pub fn get_climate_normals(
+    place: String,
+    location: Option<LatLng>,
+    months: &[u32],
+) -> ClimateNormals {
+    let coordinates = location.map(|location| {
+        (
+            location.lat.to_string().parse::<f64>(),
+            location.lng.to_string().parse::<f64>(),
+        )
+    });
+
+    let (lat, lng) = match coordinates {
+        Some((Ok(lat), Ok(lng))) => (lat, lng),
+        _ => {
+            return ClimateNormals {
+                place,
+                station: None,
+                distance_km: None,
+                months: vec![],
+                error: Some("The place couldn't be located.".into()),
+            };
+        }
+    };
+
+    let closest = CLIMATE_STATIONS
+        .iter()
+        .map(|station| (station, distance_km((lat, lng), (station.lat, station.lng))))
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .filter(|(_, distance)| *distance <= MAX_CLIMATE_STATION_DISTANCE);
+
+    let Some((station, distance)) = closest else {
+        return ClimateNormals {
+            place,
+            station: None,
+            distance_km: None,
+            months: vec![],
+            error: Some(format!(
+                "There are no climate normals within {MAX_CLIMATE_STATION_DISTANCE} km of this place."
+            )),
+        };
+    };
+
+    let months = months
+        .iter()
+        .filter(|month| (1..=12).contains(*month))
+        .map(|&month| {
+            let index = month as usize - 1;
+            MonthlyNormals {
+                month,
+                mean_high_c: station.mean_high_c[index],
+                mean_low_c: station.mean_low_c[index],
+                rain_days: station.rain_days[index],
+                daylight_hours: daylight_hours(lat, MID_MONTH_DAYS[index]),
+            }
+        })
+        .collect();
+
+    ClimateNormals {
+        place,
+        station: Some(station.name.clone()),
+        distance_km: Some(distance.round()),
+        months,
+        error: None,
+    }
+}
+
+/// The great-circle distance between two coordinates.
+fn distance_km((lat_1, lng_1): (f64, f64), (lat_2, lng_2): (f64, f64)) -> f64 {
+    let (lat_1, lat_2) = (lat_1.to_radians(), lat_2.to_radians());
+    let delta_lat = lat_2 - lat_1;
+    let delta_lng = (lng_2 - lng_1).to_radians();
+
+    let a = (delta_lat / 2.0).sin().powi(2)
+        + lat_1.cos() * lat_2.cos() * (delta_lng / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// The time between sunrise and sunset at a latitude, rounded to a tenth of an hour. The sun's
+/// declination is approximated, which is accurate to a few minutes.
+fn daylight_hours(lat: f64, day_of_year: f64) -> f64 {
+    let declination =
+        (23.44_f64.to_radians()) * (std::f64::consts::TAU * (284.0 + day_of_year) / 365.0).sin();
+    let lat = lat.to_radians();
+    // The sun is considered up while its upper edge is above the horizon, including refraction.
+    let altitude = (-0.833_f64).to_radians();
+
+    let cos_hour_angle =
+        (altitude.sin() - lat.sin() * declination.sin()) / (lat.cos() * declination.cos());
+    let hour_angle = cos_hour_angle.clamp(-1.0, 1.0).acos().to_degrees();
+
+    (2.0 * hour_angle / 15.0 * 10.0).round() / 10.0
+}
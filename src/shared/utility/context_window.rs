@@ -0,0 +1,230 @@
+use std::sync::Arc;
+
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequestArgs,
+};
+use minijinja::context;
+use tokio::task::JoinSet;
+
+use crate::shared::TEMPERATURE_LOW;
+use crate::shared::structs::{
+    LLMClients,
+    agent::{Context, LanguageModel, Provider, record::GenerationStage},
+    config::{ContextWindow, templates::PromptTemplates},
+};
+use crate::shared::utility::llm::{GenerationLog, create_chat_completion};
+
+/// Assumed for models without a known context window, e.g. ad-hoc ones in the evaluation harness.
+const DEFAULT_CONTEXT_WINDOW: usize = 128_000;
+/// Pieces with a smaller share than this are trimmed, since summaries that short lose too much.
+const MIN_SUMMARY_TOKENS: usize = 256;
+const TRUNCATION_MARKER: &str = "\n…(truncated)";
+
+/// Fits the variable parts of a prompt, such as candidate outputs and dependency contexts, into
+/// a model's context window.
+#[derive(Debug, Clone)]
+pub struct PromptFitter {
+    config: ContextWindow,
+    templates: Arc<PromptTemplates>,
+    llm_clients: Arc<LLMClients>,
+    generation_log: GenerationLog,
+}
+
+/// A rough token count. Latin script averages about four characters per token, while CJK
+/// characters are about one token each.
+pub fn estimate_tokens(text: &str) -> usize {
+    let (ascii, other) = text.chars().fold((0usize, 0usize), |(ascii, other), c| {
+        if c.is_ascii() {
+            (ascii + 1, other)
+        } else {
+            (ascii, other + 1)
+        }
+    });
+
+    ascii.div_ceil(4) + other
+}
+
+pub fn context_window_of(model_name: &str) -> usize {
+    LanguageModel::from_name(model_name)
+        .map_or(DEFAULT_CONTEXT_WINDOW, |model| model.context_window())
+}
+
+/// The smallest context window of the models a subtask is fanned out to.
+pub fn smallest_panel_context_window(panel: &[(LanguageModel, String)]) -> usize {
+    panel
+        .iter()
+        .map(|(model, _)| model.context_window())
+        .min()
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+impl PromptFitter {
+    pub fn new(
+        config: ContextWindow,
+        templates: Arc<PromptTemplates>,
+        llm_clients: Arc<LLMClients>,
+        generation_log: GenerationLog,
+    ) -> Self {
+        PromptFitter {
+            config,
+            templates,
+            llm_clients,
+            generation_log,
+        }
+    }
+
+    /// The tokens left for the payload once the fixed parts of the prompt and the response are
+    /// accounted for.
+    pub fn available_tokens(&self, context_window: usize, fixed_parts: &[&str]) -> usize {
+        let fixed_tokens = fixed_parts
+            .iter()
+            .map(|part| estimate_tokens(part))
+            .sum::<usize>();
+
+        context_window.saturating_sub(fixed_tokens + self.config.output_reserve)
+    }
+
+    /// Shrinks the pieces so that together they fit into `available_tokens`. Every piece gets a
+    /// fair share of the space, and only pieces larger than their share are summarized or
+    /// trimmed.
+    pub async fn fit(&self, pieces: Vec<String>, available_tokens: usize) -> Vec<String> {
+        let sizes = pieces
+            .iter()
+            .map(|piece| estimate_tokens(piece))
+            .collect::<Vec<_>>();
+
+        let total_tokens = sizes.iter().sum::<usize>();
+        if total_tokens <= available_tokens {
+            return pieces;
+        }
+
+        tracing::info!(
+            "Fitting {} pieces of {total_tokens} tokens into {available_tokens} tokens.",
+            pieces.len()
+        );
+
+        let shares = allocate(&sizes, available_tokens);
+        let mut join_set = JoinSet::new();
+
+        for (index, (piece, share)) in pieces.into_iter().zip(shares).enumerate() {
+            let fitter = self.clone();
+            join_set.spawn(async move { (index, fitter.shrink(piece, share).await) });
+        }
+
+        let mut fitted = join_set.join_all().await;
+        fitted.sort_by_key(|(index, _)| *index);
+        fitted.into_iter().map(|(_, piece)| piece).collect()
+    }
+
+    pub async fn fit_contexts(
+        &self,
+        contexts: Vec<Context>,
+        available_tokens: usize,
+    ) -> Vec<Context> {
+        let contents = contexts.iter().map(|c| c.content.clone()).collect();
+        let fitted = self.fit(contents, available_tokens).await;
+
+        contexts
+            .into_iter()
+            .zip(fitted)
+            .map(|(context, content)| Context { content, ..context })
+            .collect()
+    }
+
+    async fn shrink(&self, piece: String, share: usize) -> String {
+        if estimate_tokens(&piece) <= share {
+            return piece;
+        }
+
+        if self.config.summarize && share >= MIN_SUMMARY_TOKENS {
+            match self.summarize(&piece, share).await {
+                Ok(summary) if estimate_tokens(&summary) <= share => return summary,
+                Ok(summary) => return trim_to_tokens(&summary, share),
+                Err(e) => {
+                    tracing::warn!("Failed to summarize a payload, trimming it instead: {e:?}")
+                }
+            }
+        }
+
+        trim_to_tokens(&piece, share)
+    }
+
+    async fn summarize(&self, piece: &str, share: usize) -> anyhow::Result<String> {
+        let summary_prompt = self.templates.render(
+            "context_window.summary_prompt",
+            context! {
+                tokens => share,
+                content => piece,
+            },
+        )?;
+
+        let request = CreateChatCompletionRequestArgs::default()
+            .model(&self.config.summary_model)
+            .temperature(TEMPERATURE_LOW)
+            .messages(vec![ChatCompletionRequestMessage::User(
+                ChatCompletionRequestUserMessageArgs::default()
+                    .content(summary_prompt)
+                    .build()?,
+            )])
+            .build()?;
+
+        let provider = Provider::for_model(&self.config.summary_model);
+
+        let response = create_chat_completion(
+            &self.llm_clients.client(provider),
+            provider,
+            request,
+            GenerationStage::Summarization,
+            &self.llm_clients,
+            &self.generation_log,
+        )
+        .await?;
+
+        response
+            .choices
+            .first()
+            .and_then(|c| c.message.content.clone())
+            .filter(|summary| !summary.trim().is_empty())
+            .ok_or(anyhow::anyhow!("The summary model returned no content."))
+    }
+}
+
+/// Splits the available tokens so that small pieces keep their full size, and the rest is
+/// shared evenly among the larger ones.
+fn allocate(sizes: &[usize], available_tokens: usize) -> Vec<usize> {
+    let mut order = (0..sizes.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| sizes[index]);
+
+    let mut shares = vec![0; sizes.len()];
+    let mut remaining = available_tokens;
+
+    for (position, index) in order.into_iter().enumerate() {
+        let share = (remaining / (sizes.len() - position)).min(sizes[index]);
+        shares[index] = share;
+        remaining -= share;
+    }
+
+    shares
+}
+
+fn trim_to_tokens(text: &str, tokens: usize) -> String {
+    let budget = tokens.saturating_sub(estimate_tokens(TRUNCATION_MARKER));
+    let (mut ascii, mut other, mut end) = (0usize, 0usize, 0);
+
+    for (index, c) in text.char_indices() {
+        if c.is_ascii() {
+            ascii += 1;
+        } else {
+            other += 1;
+        }
+
+        if ascii.div_ceil(4) + other > budget {
+            break;
+        }
+
+        end = index + c.len_utf8();
+    }
+
+    format!("{}{TRUNCATION_MARKER}", &text[..end])
+}
@@ -0,0 +1,264 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+
+use crate::shared::structs::agent::Language;
+use crate::shared::structs::config::ExchangeRateConfig;
+use crate::shared::structs::currency::{Conversion, ConvertedAmount, RateTable};
+
+/// Currencies whose amounts are usually shown without decimals.
+const ZERO_DECIMAL_CURRENCIES: [&str; 10] = [
+    "CLP", "ISK", "JPY", "KRW", "PYG", "TWD", "UGX", "VND", "XAF", "XOF",
+];
+
+/// Symbols that can't be mistaken for another currency. Other currencies are shown by code.
+const CURRENCY_SYMBOLS: [(&str, &str); 12] = [
+    ("USD", "US$"),
+    ("JPY", "¥"),
+    ("EUR", "€"),
+    ("GBP", "£"),
+    ("TWD", "NT$"),
+    ("CNY", "CN¥"),
+    ("KRW", "₩"),
+    ("HKD", "HK$"),
+    ("AUD", "A$"),
+    ("CAD", "CA$"),
+    ("SGD", "S$"),
+    ("THB", "฿"),
+];
+
+/// A dated snapshot used until a rate table is put in place, so that conversions work out of
+/// the box.
+static BUNDLED_RATES: Lazy<Arc<RateTable>> = Lazy::new(|| {
+    Arc::new(
+        serde_json::from_str(include_str!("../../../data/exchange_rates.json"))
+            .expect("Failed to parse the bundled exchange rates."),
+    )
+});
+
+/// The rate table in the local file, which is loaded again whenever the file changes. The
+/// bundled table is used while there's no file.
+#[derive(Debug)]
+pub struct ExchangeRates {
+    path: PathBuf,
+    max_age_days: i64,
+    /// The modification time of the file when it was loaded, or `None` for the bundled table.
+    table: Mutex<Option<(Option<SystemTime>, Arc<RateTable>)>>,
+}
+
+/// How a language writes amounts of money.
+struct NumberFormat {
+    group_separator: &'static str,
+    decimal_separator: &'static str,
+    /// Whether the symbol comes after the amount, e.g. `12,50 €`.
+    symbol_after: bool,
+}
+
+impl ExchangeRates {
+    /// Relative paths are resolved against `config_directory`. The file is read on first use.
+    pub fn new(config: &ExchangeRateConfig, config_directory: &Path) -> Self {
+        let path = PathBuf::from(&config.path);
+        let path = if path.is_relative() {
+            config_directory.join(path)
+        } else {
+            path
+        };
+
+        ExchangeRates {
+            path,
+            max_age_days: config.max_age_days,
+            table: Mutex::new(None),
+        }
+    }
+
+    /// The current rate table. Stale tables are still used, but logged.
+    pub async fn table(&self) -> anyhow::Result<Arc<RateTable>> {
+        let modified = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => Some(metadata.modified()?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut table = self.table.lock().await;
+
+        if let Some((loaded_at, loaded)) = table.as_ref()
+            && *loaded_at == modified
+        {
+            return Ok(loaded.clone());
+        }
+
+        let (loaded, source) = match modified {
+            Some(_) => {
+                let raw_table = tokio::fs::read_to_string(&self.path).await?;
+                let loaded = Arc::new(serde_json::from_str::<RateTable>(&raw_table)?);
+                (loaded, self.path.display().to_string())
+            }
+            None => {
+                tracing::warn!(
+                    "There's no rate table at {}, so the bundled exchange rates are used.",
+                    self.path.display()
+                );
+                (BUNDLED_RATES.clone(), "the bundled table".to_string())
+            }
+        };
+
+        let age = (chrono::Utc::now().date_naive() - loaded.date).num_days();
+        if age > self.max_age_days {
+            tracing::warn!("The exchange rates in {source} are {age} days old.");
+        }
+
+        tracing::info!(
+            "Loaded the exchange rates of {} from {source}.",
+            loaded.date
+        );
+
+        *table = Some((modified, loaded.clone()));
+        Ok(loaded)
+    }
+
+    /// Converts each amount to its target currency, or else to `default_currency`. Amounts that
+    /// can't be converted, including all of them if the rate table can't be read, carry the
+    /// reason instead.
+    pub async fn convert(
+        &self,
+        conversions: &[Conversion],
+        default_currency: &str,
+        language: &Language,
+    ) -> Vec<ConvertedAmount> {
+        let table = match self.table().await {
+            Ok(table) => table,
+            Err(e) => {
+                tracing::error!(
+                    "Failed to load the exchange rates from {}: {e:?}",
+                    self.path.display()
+                );
+
+                return conversions
+                    .iter()
+                    .map(|conversion| ConvertedAmount {
+                        from: format_money(
+                            conversion.amount,
+                            &conversion.from.trim().to_uppercase(),
+                            language,
+                        ),
+                        to: None,
+                        amount: None,
+                        rate_date: None,
+                        error: Some("The exchange rates aren't available right now.".into()),
+                    })
+                    .collect();
+            }
+        };
+
+        conversions
+            .iter()
+            .map(|conversion| {
+                let from = conversion.from.trim().to_uppercase();
+                let to = conversion
+                    .to
+                    .as_deref()
+                    .unwrap_or(default_currency)
+                    .trim()
+                    .to_uppercase();
+
+                let amount = table.convert(conversion.amount, &from, &to);
+                ConvertedAmount {
+                    from: format_money(conversion.amount, &from, language),
+                    to: amount.map(|amount| format_money(amount, &to, language)),
+                    amount: amount.map(|amount| round_to_minor_unit(amount, &to)),
+                    rate_date: amount.map(|_| table.date),
+                    error: amount.is_none().then(|| {
+                        format!(
+                            "There's no exchange rate between {from} and {to} as of {}.",
+                            table.date
+                        )
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Formats an amount with the currency's symbol or code, e.g. `¥12,000`, `US$8.50` or
+/// `12,50 €`.
+pub fn format_money(amount: f64, currency: &str, language: &Language) -> String {
+    let format = number_format(language);
+    let number = format_amount(amount, currency, language);
+    let symbol = CURRENCY_SYMBOLS
+        .iter()
+        .find(|(code, _)| *code == currency)
+        .map(|(_, symbol)| *symbol);
+
+    match symbol {
+        Some(symbol) if format.symbol_after => format!("{number} {symbol}"),
+        Some(symbol) => format!("{symbol}{number}"),
+        None => format!("{number} {currency}"),
+    }
+}
+
+/// Formats an amount without a symbol, with the decimals of the currency and the separators of
+/// the language, e.g. `12,000` for `JPY` or `8.50` for `USD`.
+pub fn format_amount(amount: f64, currency: &str, language: &Language) -> String {
+    let format = number_format(language);
+    let decimals = minor_unit_digits(currency);
+    let scale = 10_u64.pow(decimals);
+
+    let sign = if amount < 0.0 { "-" } else { "" };
+    let scaled = (amount.abs() * scale as f64).round() as u64;
+    let (whole, fraction) = (scaled / scale, scaled % scale);
+
+    let digits = whole.to_string();
+    let mut grouped = String::with_capacity(digits.len() + digits.len() / 3 * 3);
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index) % 3 == 0 {
+            grouped.push_str(format.group_separator);
+        }
+        grouped.push(digit);
+    }
+
+    if decimals == 0 {
+        format!("{sign}{grouped}")
+    } else {
+        format!(
+            "{sign}{grouped}{}{fraction:0width$}",
+            format.decimal_separator,
+            width = decimals as usize
+        )
+    }
+}
+
+fn round_to_minor_unit(amount: f64, currency: &str) -> f64 {
+    let scale = 10_f64.powi(minor_unit_digits(currency) as i32);
+    (amount * scale).round() / scale
+}
+
+fn minor_unit_digits(currency: &str) -> u32 {
+    if ZERO_DECIMAL_CURRENCIES.contains(&currency) {
+        0
+    } else {
+        2
+    }
+}
+
+fn number_format(language: &Language) -> NumberFormat {
+    match language.primary() {
+        "de" | "es" | "it" | "pt" | "nl" | "id" | "tr" | "da" | "vi" => NumberFormat {
+            group_separator: ".",
+            decimal_separator: ",",
+            symbol_after: true,
+        },
+        "fr" | "ru" | "pl" | "sv" | "cs" | "fi" | "nb" | "uk" => NumberFormat {
+            group_separator: "\u{202f}",
+            decimal_separator: ",",
+            symbol_after: true,
+        },
+        _ => NumberFormat {
+            group_separator: ",",
+            decimal_separator: ".",
+            symbol_after: false,
+        },
+    }
+}
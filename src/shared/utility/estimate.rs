@@ -0,0 +1,123 @@
+use async_openai::types::{ResponseFormat, ResponseFormatJsonSchema};
+use minijinja::context;
+use serde_json::json;
+
+use crate::shared::structs::agent::Language;
+use crate::shared::structs::agent::estimate::{CostEstimate, DayCost};
+use crate::shared::structs::config::templates::PromptTemplates;
+use crate::shared::utility::currency::format_amount;
+
+/// Makes the aggregator answer with a `CostEstimate`. `currency` is used unless the user asked
+/// for another one.
+pub fn cost_estimate_format(currency: &str) -> ResponseFormat {
+    ResponseFormat::JsonSchema {
+        json_schema: ResponseFormatJsonSchema {
+            description: Some("Estimate what the trip will cost per day and per category.".into()),
+            name: "estimate_costs".into(),
+            schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "currency": {
+                        "type": "string",
+                        "description": format!("The ISO 4217 code of the currency of all amounts. Use {currency} unless the user asked for another currency.")
+                    },
+                    "days": {
+                        "type": "array",
+                        "description": "The estimated costs of each day of the trip for one traveller. Use the fares in the context where available.",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "day": {
+                                    "type": "integer",
+                                    "description": "The day of the trip, starting at 1."
+                                },
+                                "food": {
+                                    "type": "number",
+                                    "description": "Meals, snacks and drinks."
+                                },
+                                "transit": {
+                                    "type": "number",
+                                    "description": "Trains, buses, taxis and other transport."
+                                },
+                                "admission": {
+                                    "type": "number",
+                                    "description": "Tickets and entrance fees."
+                                },
+                                "lodging": {
+                                    "type": "number",
+                                    "description": "The night's accommodation, or 0 on the last day."
+                                }
+                            },
+                            "required": ["day", "food", "transit", "admission", "lodging"],
+                            "additionalProperties": false
+                        }
+                    },
+                    "notes": {
+                        "type": "string",
+                        "description": "The assumptions behind the estimate, such as the class of hotel and the number of paid sights per day."
+                    }
+                },
+                "required": ["currency", "days", "notes"],
+                "additionalProperties": false
+            })),
+            strict: Some(true),
+        },
+    }
+}
+
+/// Renders an estimate as a markdown section with a line per day and a line of totals. Discord
+/// doesn't render tables, so the categories follow each day's total.
+pub fn render_cost_estimate(
+    estimate: &CostEstimate,
+    templates: &PromptTemplates,
+    language: &Language,
+) -> anyhow::Result<String> {
+    let currency = estimate.currency.as_str();
+    let food = templates.ui(language, "budget_food", context! {})?;
+    let transit = templates.ui(language, "budget_transit", context! {})?;
+    let admission = templates.ui(language, "budget_admission", context! {})?;
+    let lodging = templates.ui(language, "budget_lodging", context! {})?;
+
+    let breakdown = |day: &DayCost| {
+        format!(
+            "{food} {} · {transit} {} · {admission} {} · {lodging} {}",
+            format_amount(day.food, currency, language),
+            format_amount(day.transit, currency, language),
+            format_amount(day.admission, currency, language),
+            format_amount(day.lodging, currency, language)
+        )
+    };
+
+    let mut lines = vec![format!(
+        "## {}",
+        templates.ui(
+            language,
+            "budget_title",
+            context! { currency => estimate.currency },
+        )?
+    )];
+
+    for day in estimate.days.iter() {
+        lines.push(format!(
+            "- **{}**: {} ({})",
+            templates.ui(language, "budget_day", context! { day => day.day })?,
+            format_amount(day.total(), currency, language),
+            breakdown(day)
+        ));
+    }
+
+    let totals = estimate.totals();
+    lines.push(format!(
+        "- **{}**: **{}** ({})",
+        templates.ui(language, "budget_total", context! {})?,
+        format_amount(totals.total(), currency, language),
+        breakdown(&totals)
+    ));
+
+    if !estimate.notes.trim().is_empty() {
+        lines.push(String::new());
+        lines.push(format!("*{}*", estimate.notes.trim()));
+    }
+
+    Ok(lines.join("\n"))
+}
@@ -3,93 +3,146 @@ use std::sync::Arc;
 use dashmap::DashMap;
 use google_maps::{
     LatLng,
-    prelude::{DepartureTime, Local, TravelMode},
+    prelude::{DepartureTime, Local, PlaceType, TravelMode},
 };
+use serde::Deserialize;
+use serde_json::json;
 
-use crate::shared::structs::{
-    agent::Language,
-    google_maps::{AlternativeTravelDuration, Route, TransferMethod},
+use crate::shared::{
+    AUTOCOMPLETE_TIMEOUT, MAX_AUTOCOMPLETE_CHOICES, PLACE_CACHE_TTL, PLACE_COLLECTION_NAME,
+    PLACES_TEXT_SEARCH_ENDPOINT,
+    structs::{
+        agent::Language,
+        google_maps::{
+            AlternativeTravelDuration, CachedPlaces, OpeningHours, PlaceSuggestion, Route,
+            TransferMethod,
+        },
+    },
 };
 
+#[derive(Deserialize, Debug, Default)]
+struct PlacesResponse {
+    #[serde(default)]
+    places: Vec<PlaceWithOpeningHours>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PlaceWithOpeningHours {
+    regular_opening_hours: Option<OpeningHours>,
+}
+
 pub async fn get_latitude_and_longitude(
     route: &Route,
-    language: Language,
+    response_language: ::google_maps::Language,
     lat_lngs: Arc<DashMap<String, LatLng>>,
     client: Arc<::google_maps::Client>,
 ) -> anyhow::Result<(LatLng, LatLng)> {
-    let response_language = match language {
-        Language::Chinese => ::google_maps::Language::ChineseTaiwan,
-        Language::Japanese => ::google_maps::Language::Japanese,
-        _ => ::google_maps::Language::EnglishUs,
-    };
+    let mut locations = vec![];
+    for place in [&route.from, &route.to] {
+        let location = get_location(place, response_language, &lat_lngs, &client)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("{place} couldn't be found on the map."))?;
+        locations.push(location);
+    }
 
-    let from_location = if let Some(lat_lng) = lat_lngs.get(&route.from) {
-        *lat_lng
-    } else {
-        let from_response = client
-            .geocoding()
-            .with_language(response_language)
-            .with_address(&route.from)
-            .execute()
-            .await?;
-
-        let location = from_response
-            .results
-            .first()
-            .map(|g| g.geometry.location)
-            .unwrap_or_default();
-
-        lat_lngs.insert(route.from.clone(), location);
-        location
-    };
+    Ok((locations[0], locations[1]))
+}
 
-    let to_location = if let Some(lat_lng) = lat_lngs.get(&route.to) {
-        *lat_lng
-    } else {
-        let to_response = client
-            .geocoding()
-            .with_language(response_language)
-            .with_address(&route.to)
-            .execute()
-            .await?;
-
-        let location = to_response
-            .results
-            .first()
-            .map(|g| g.geometry.location)
-            .unwrap_or_default();
-
-        lat_lngs.insert(route.to.clone(), location);
-        location
-    };
+/// Geocodes a place, reusing the locations that were already looked up. Returns `None` if the
+/// place can't be found.
+pub async fn get_location(
+    place: &str,
+    response_language: ::google_maps::Language,
+    lat_lngs: &DashMap<String, LatLng>,
+    client: &::google_maps::Client,
+) -> anyhow::Result<Option<LatLng>> {
+    if let Some(lat_lng) = lat_lngs.get(place) {
+        return Ok(Some(*lat_lng));
+    }
+
+    let response = client
+        .geocoding()
+        .with_language(response_language)
+        .with_address(place)
+        .execute()
+        .await?;
+
+    let location = response.results.first().map(|g| g.geometry.location);
+
+    if let Some(location) = location {
+        lat_lngs.insert(place.to_string(), location);
+    }
+
+    Ok(location)
+}
+
+/// The ISO 3166-1 alpha-2 code of the country a place is in, e.g. `JP`, or `None` if it can't be
+/// geocoded.
+pub async fn get_country_code(
+    place: &str,
+    response_language: ::google_maps::Language,
+    client: &::google_maps::Client,
+) -> anyhow::Result<Option<String>> {
+    let response = client
+        .geocoding()
+        .with_language(response_language)
+        .with_address(place)
+        .execute()
+        .await?;
 
-    Ok((from_location, to_location))
+    Ok(response.results.first().and_then(|g| {
+        g.address_components
+            .iter()
+            .find(|component| component.types.contains(&PlaceType::Country))
+            .map(|component| component.short_name.to_uppercase())
+    }))
 }
 
+/// The regular opening hours of the best match for a place name from the Places API, or `None`
+/// if the place isn't found or has no opening hours.
+pub async fn get_opening_hours(
+    place: &str,
+    language_code: &str,
+    http_client: &reqwest::Client,
+    api_key: &str,
+) -> anyhow::Result<Option<OpeningHours>> {
+    let response = http_client
+        .post(PLACES_TEXT_SEARCH_ENDPOINT)
+        .header("X-Goog-Api-Key", api_key)
+        .header("X-Goog-FieldMask", "places.regularOpeningHours")
+        .json(&json!({
+            "textQuery": place,
+            "languageCode": language_code,
+            "pageSize": 1,
+        }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<PlacesResponse>()
+        .await?;
+
+    Ok(response
+        .places
+        .into_iter()
+        .next()
+        .and_then(|p| p.regular_opening_hours)
+        .filter(|hours| !hours.periods.is_empty()))
+}
+
+/// Returns the duration and the fare of the route, and the alternative. Fares are only known for
+/// some transit routes.
 pub async fn get_travel_time(
     (from, to, transfer_method): (LatLng, LatLng, TransferMethod),
-    language: Language,
+    response_language: ::google_maps::Language,
     client: Arc<::google_maps::Client>,
-) -> anyhow::Result<(String, AlternativeTravelDuration)> {
-    let response_language = match language {
-        Language::Chinese => ::google_maps::Language::ChineseTaiwan,
-        Language::Japanese => ::google_maps::Language::Japanese,
-        _ => ::google_maps::Language::EnglishUs,
-    };
-
+) -> anyhow::Result<(String, Option<String>, AlternativeTravelDuration)> {
     let (travel_mode, alternative_travel_mode) = match transfer_method {
         TransferMethod::DriveOrTaxi => (TravelMode::Driving, TravelMode::Transit),
         _ => (TravelMode::Transit, TravelMode::Driving),
     };
 
-    let date = Local::now().date_naive();
-    // To get approximate travel time from a place to another, we're setting time to 12:00:00 here.
-    let departure_time = DepartureTime::At(
-        date.and_time(
-            chrono::NaiveTime::from_hms_opt(12, 0, 0)
-                .ok_or(anyhow::anyhow!("Failed to construct a NaiveTime"))?,
-        ),
-    );
+    let departure_time = noon_today()?;
 
     let direction_response = client
         .directions(from, to)
@@ -117,9 +170,11 @@ pub async fn get_travel_time(
     match (direction_response, alternative_direction_response) {
         (Ok(res_1), Ok(res_2)) => Ok((
             extract_duration_text(&res_1.routes),
+            extract_fare_text(&res_1.routes),
             AlternativeTravelDuration {
                 by: alternative_transfer_method,
                 duration: Some(extract_duration_text(&res_2.routes)),
+                fare: extract_fare_text(&res_2.routes),
             },
         )),
         (Ok(res_1), Err(e)) => {
@@ -127,9 +182,11 @@ pub async fn get_travel_time(
             tracing::warn!("{error_msg}");
             Ok((
                 extract_duration_text(&res_1.routes),
+                extract_fare_text(&res_1.routes),
                 AlternativeTravelDuration {
                     by: alternative_transfer_method,
                     duration: None,
+                    fare: None,
                 },
             ))
         }
@@ -138,9 +195,11 @@ pub async fn get_travel_time(
             tracing::warn!("{error_msg}");
             Ok((
                 "No result".into(),
+                None,
                 AlternativeTravelDuration {
                     by: alternative_transfer_method,
                     duration: Some(extract_duration_text(&res_2.routes)),
+                    fare: extract_fare_text(&res_2.routes),
                 },
             ))
         }
@@ -150,15 +209,147 @@ pub async fn get_travel_time(
             tracing::warn!("{error_msg}");
             Ok((
                 "No result".into(),
+                None,
                 AlternativeTravelDuration {
                     by: alternative_transfer_method,
                     duration: None,
+                    fare: None,
                 },
             ))
         }
     }
 }
 
+/// The travel time between two places in minutes, or `None` if there's no route.
+pub async fn get_travel_minutes(
+    (from, to, transfer_method): (LatLng, LatLng, TransferMethod),
+    response_language: ::google_maps::Language,
+    client: &::google_maps::Client,
+) -> anyhow::Result<Option<i64>> {
+    let travel_mode = match transfer_method {
+        TransferMethod::DriveOrTaxi => TravelMode::Driving,
+        _ => TravelMode::Transit,
+    };
+
+    let response = client
+        .directions(from, to)
+        .with_language(response_language)
+        .with_alternatives(false)
+        .with_travel_mode(travel_mode)
+        .with_departure_time(noon_today()?)
+        .execute()
+        .await?;
+
+    Ok(response
+        .routes
+        .first()
+        .and_then(|r| r.legs.first())
+        .map(|l| l.duration.value.num_minutes()))
+}
+
+/// Suggests normalized place names for a partially typed destination.
+///
+/// Results are looked up in the persistent place cache first, and only geocoded (and then
+/// cached) when the query hasn't been seen before or its entry has expired. Lookups that take
+/// longer than `AUTOCOMPLETE_TIMEOUT` fall back to the expired entry, or to no suggestions.
+pub async fn suggest_places(
+    query: &str,
+    language: &Language,
+    response_language: ::google_maps::Language,
+    firestore_db: &firestore::FirestoreDb,
+    client: Arc<::google_maps::Client>,
+) -> anyhow::Result<Vec<PlaceSuggestion>> {
+    let deadline =
+        tokio::time::Instant::now() + std::time::Duration::from_millis(AUTOCOMPLETE_TIMEOUT);
+    let normalized_query = query.trim().to_lowercase();
+    let document_id = hex::encode(format!("{language}:{normalized_query}"));
+
+    let cached = tokio::time::timeout_at(
+        deadline,
+        firestore_db
+            .fluent()
+            .select()
+            .by_id_in(PLACE_COLLECTION_NAME)
+            .obj::<CachedPlaces>()
+            .one(&document_id),
+    )
+    .await;
+
+    let now = chrono::Utc::now().timestamp();
+    let expired = match cached {
+        Ok(Ok(Some(cached_places))) if cached_places.expires_at > now => {
+            return Ok(cached_places.suggestions);
+        }
+        Ok(Ok(cached_places)) => cached_places.map(|cached_places| cached_places.suggestions),
+        Ok(Err(e)) => {
+            let error_msg = format!("Failed to read place cache from Firestore: {e:?}");
+            tracing::warn!("{error_msg}");
+            None
+        }
+        Err(_) => {
+            tracing::warn!("Timed out reading the place cache for {normalized_query}.");
+            None
+        }
+    };
+
+    let response = tokio::time::timeout_at(
+        deadline,
+        client
+            .geocoding()
+            .with_language(response_language)
+            .with_address(&normalized_query)
+            .execute(),
+    )
+    .await;
+
+    let response = match response {
+        Ok(response) => response?,
+        Err(_) => {
+            tracing::warn!("Timed out geocoding place suggestions for {normalized_query}.");
+            return Ok(expired.unwrap_or_default());
+        }
+    };
+
+    let suggestions = response
+        .results
+        .into_iter()
+        .take(MAX_AUTOCOMPLETE_CHOICES)
+        .map(|g| PlaceSuggestion {
+            name: g.formatted_address,
+            place_id: g.place_id,
+            location: g.geometry.location,
+        })
+        .collect::<Vec<_>>();
+
+    let cached_places = CachedPlaces {
+        query: normalized_query,
+        language: language.clone(),
+        suggestions: suggestions.clone(),
+        expires_at: now + PLACE_CACHE_TTL,
+    };
+
+    // Written in the background, since the response is already late enough. Entries are
+    // replaced rather than inserted, so that expired ones are refreshed.
+    let firestore_db = firestore_db.clone();
+    tokio::spawn(async move {
+        let result = firestore_db
+            .fluent()
+            .update()
+            .in_col(PLACE_COLLECTION_NAME)
+            .document_id(&document_id)
+            .object(&cached_places)
+            .execute::<CachedPlaces>()
+            .await;
+
+        if let Err(e) = result {
+            let error_msg = format!("Failed to write place cache to Firestore: {e:?}");
+            tracing::warn!("{error_msg}");
+        }
+    });
+
+    Ok(suggestions)
+}
+
 fn extract_duration_text(routes: &[::google_maps::directions::response::route::Route]) -> String {
     routes
         .first()
@@ -166,3 +357,24 @@ fn extract_duration_text(routes: &[::google_maps::directions::response::route::R
         .map(|l| l.duration.text.clone())
         .unwrap_or_default()
 }
+
+fn extract_fare_text(
+    routes: &[::google_maps::directions::response::route::Route],
+) -> Option<String> {
+    routes
+        .first()
+        .and_then(|r| r.fare.as_ref())
+        .map(|f| f.text.clone())
+}
+
+// To get approximate travel time from a place to another, we're setting time to 12:00:00 here.
+fn noon_today() -> anyhow::Result<DepartureTime> {
+    let date = Local::now().date_naive();
+
+    Ok(DepartureTime::At(
+        date.and_time(
+            chrono::NaiveTime::from_hms_opt(12, 0, 0)
+                .ok_or(anyhow::anyhow!("Failed to construct a NaiveTime"))?,
+        ),
+    ))
+}
@@ -0,0 +1,106 @@
+use dashmap::DashMap;
+use minijinja::context;
+
+use crate::shared::MAX_ITINERARY_STOPS;
+use crate::shared::structs::agent::Language;
+use crate::shared::structs::agent::itinerary::{Itinerary, ItineraryStop, TransitLeg};
+use crate::shared::structs::config::templates::PromptTemplates;
+use crate::shared::structs::google_maps::TransferMethod;
+use crate::shared::utility::google_maps::get_location;
+
+/// Geocodes the stops of the itinerary. Stops that can't be found are left without a location.
+pub async fn locate_stops(
+    itinerary: &mut Itinerary,
+    response_language: ::google_maps::Language,
+    client: &::google_maps::Client,
+) {
+    let lat_lngs = DashMap::new();
+
+    for stop in itinerary
+        .days
+        .iter_mut()
+        .flat_map(|day| day.stops.iter_mut())
+        .take(MAX_ITINERARY_STOPS)
+    {
+        if stop.location.is_some() {
+            continue;
+        }
+
+        match get_location(&stop.place, response_language, &lat_lngs, client).await {
+            Ok(Some(location)) => stop.location = Some(location),
+            Ok(None) => tracing::warn!("{} couldn't be found on the map.", stop.place),
+            Err(e) => tracing::warn!("Failed to locate {}: {e:?}", stop.place),
+        }
+    }
+}
+
+/// Renders the itinerary as Discord markdown, with a section per day after the narrative.
+pub fn render_itinerary(
+    itinerary: &Itinerary,
+    templates: &PromptTemplates,
+    language: &Language,
+) -> anyhow::Result<String> {
+    let mut sections = vec![];
+    if !itinerary.narrative.trim().is_empty() {
+        sections.push(itinerary.narrative.trim().to_string());
+    }
+
+    for day in itinerary.days.iter() {
+        let mut heading = templates.ui(language, "itinerary_day", context! { day => day.day })?;
+        if let Some(date) = day.date.as_deref() {
+            heading.push_str(&format!(" · {date}"));
+        }
+        if !day.title.trim().is_empty() {
+            heading.push_str(&format!(" · {}", day.title.trim()));
+        }
+
+        let mut lines = vec![format!("## {heading}")];
+        for stop in day.stops.iter() {
+            if let Some(transit) = stop.transit.as_ref() {
+                lines.push(render_transit(transit, templates, language)?);
+            }
+            lines.push(render_stop(stop));
+        }
+
+        sections.push(lines.join("\n"));
+    }
+
+    Ok(sections.join("\n\n"))
+}
+
+fn render_stop(stop: &ItineraryStop) -> String {
+    let time = match (stop.start.as_deref(), stop.end.as_deref()) {
+        (Some(start), Some(end)) => format!("**{start}–{end}** "),
+        (Some(start), None) => format!("**{start}** "),
+        _ => String::new(),
+    };
+
+    let mut line = format!("{time}{} **{}**", stop.category.emoji(), stop.place);
+    if !stop.notes.trim().is_empty() {
+        line.push_str(&format!("\n{}", stop.notes.trim()));
+    }
+
+    line
+}
+
+/// A subtext line, so that the stops stand out.
+fn render_transit(
+    transit: &TransitLeg,
+    templates: &PromptTemplates,
+    language: &Language,
+) -> anyhow::Result<String> {
+    let key = match transit.by {
+        TransferMethod::PublicTransport => "itinerary_public_transport",
+        TransferMethod::DriveOrTaxi => "itinerary_drive_or_taxi",
+    };
+
+    let mut line = format!(
+        "-# {}",
+        templates.ui(language, key, context! { minutes => transit.minutes })?
+    );
+    if !transit.notes.trim().is_empty() {
+        line.push_str(&format!(" · {}", transit.notes.trim()));
+    }
+
+    Ok(line)
+}
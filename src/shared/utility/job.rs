@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use firestore::FirestoreConsistencySelector;
+use once_cell::sync::Lazy;
+use tokio::sync::Mutex;
+use tokio_util::sync::{CancellationToken, DropGuard};
+use uuid::Uuid;
+
+use crate::shared::{
+    JOB_COLLECTION_NAME, JOB_LEASE_DURATION,
+    structs::agent::{
+        Context,
+        job::{JobStatus, PlanJob},
+        record::{GenerationDump, ToolOutput},
+    },
+};
+
+/// Identifies this instance as the owner of the jobs it is running.
+pub static INSTANCE_ID: Lazy<Uuid> = Lazy::new(Uuid::now_v7);
+
+/// The fields written when only the lease changes.
+const LEASE_FIELDS: [&str; 2] = ["owner", "lease_expires_at"];
+
+/// Shared access to a running job, persisting every change as a checkpoint.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    job: Arc<Mutex<PlanJob>>,
+    firestore_db: firestore::FirestoreDb,
+}
+
+impl JobHandle {
+    pub fn new(job: PlanJob, firestore_db: firestore::FirestoreDb) -> Self {
+        JobHandle {
+            job: Arc::new(Mutex::new(job)),
+            firestore_db,
+        }
+    }
+
+    pub async fn snapshot(&self) -> PlanJob {
+        self.job.lock().await.clone()
+    }
+
+    /// Applies the change to the job, renews its lease and saves it.
+    pub async fn update<F>(&self, f: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&mut PlanJob),
+    {
+        let mut job = self.job.lock().await;
+        f(&mut job);
+        job.owner = *INSTANCE_ID;
+        job.lease_expires_at = chrono::Utc::now().timestamp() + JOB_LEASE_DURATION;
+        save_job(&job, &self.firestore_db).await
+    }
+
+    /// Checkpoints a completed task so that it's skipped when the job is resumed.
+    pub async fn record_task(
+        &self,
+        context: Context,
+        mut dumps: Vec<GenerationDump>,
+        mut tool_outputs: Vec<ToolOutput>,
+    ) -> anyhow::Result<()> {
+        self.update(|job| {
+            job.contexts.push(context);
+            if let Some(ref mut plan_record) = job.plan_record {
+                plan_record.dumps.append(&mut dumps);
+                plan_record.tool_outputs.append(&mut tool_outputs);
+            }
+        })
+        .await
+    }
+
+    /// Renews the lease without saving the rest of the job, which grows with every task.
+    async fn renew_lease(&self) -> anyhow::Result<()> {
+        let mut job = self.job.lock().await;
+        job.owner = *INSTANCE_ID;
+        job.lease_expires_at = chrono::Utc::now().timestamp() + JOB_LEASE_DURATION;
+
+        self.firestore_db
+            .fluent()
+            .update()
+            .fields(LEASE_FIELDS)
+            .in_col(JOB_COLLECTION_NAME)
+            .document_id(job.id.to_string())
+            .object(&*job)
+            .execute::<PlanJob>()
+            .await?;
+
+        Ok(())
+    }
+
+    /// Keeps renewing the lease until the returned guard is dropped, so that other instances
+    /// don't pick the job up while this one is still working on it.
+    pub fn start_heartbeat(&self) -> DropGuard {
+        let cancellation_token = CancellationToken::new();
+        let handle = self.clone();
+        let token = cancellation_token.clone();
+
+        tokio::spawn(async move {
+            let interval = std::time::Duration::from_secs(JOB_LEASE_DURATION as u64 / 3);
+
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {
+                        if let Err(e) = handle.renew_lease().await {
+                            tracing::warn!("Failed to renew the job lease: {e:?}");
+                        }
+                    }
+                    _ = token.cancelled() => break,
+                }
+            }
+        });
+
+        cancellation_token.drop_guard()
+    }
+}
+
+pub async fn save_job(job: &PlanJob, firestore_db: &firestore::FirestoreDb) -> anyhow::Result<()> {
+    let result = firestore_db
+        .fluent()
+        .update()
+        .in_col(JOB_COLLECTION_NAME)
+        .document_id(job.id.to_string())
+        .object(job)
+        .execute::<PlanJob>()
+        .await;
+
+    if let Err(e) = result {
+        let error_msg = format!("Failed to save job {} in Firestore: {e:?}", job.id);
+        tracing::error!("{}", &error_msg);
+        return Err(anyhow::anyhow!("{}", error_msg));
+    }
+
+    Ok(())
+}
+
+/// Loads unfinished jobs whose lease has expired, i.e. nobody is working on them anymore.
+pub async fn load_resumable_jobs(
+    firestore_db: &firestore::FirestoreDb,
+) -> anyhow::Result<Vec<PlanJob>> {
+    let now = chrono::Utc::now().timestamp();
+    let mut resumable_jobs = vec![];
+
+    for status in JobStatus::UNFINISHED.into_iter() {
+        let jobs = firestore_db
+            .fluent()
+            .select()
+            .from(JOB_COLLECTION_NAME)
+            .filter(|q| q.for_all([q.field("status").eq(status.to_string())]))
+            .obj::<PlanJob>()
+            .query()
+            .await?;
+
+        resumable_jobs.extend(jobs.into_iter().filter(|job| job.lease_expires_at < now));
+    }
+
+    Ok(resumable_jobs)
+}
+
+/// Takes over the lease of a job, and returns whether this instance won it. The lease is checked
+/// and taken in a transaction, so only one of several competing instances can win. The job is
+/// replaced with its latest saved state.
+pub async fn claim_job(
+    job: &mut PlanJob,
+    firestore_db: &firestore::FirestoreDb,
+) -> anyhow::Result<bool> {
+    let mut transaction = firestore_db.begin_transaction().await?;
+    let transaction_db = firestore_db.clone_with_consistency_selector(
+        FirestoreConsistencySelector::Transaction(transaction.transaction_id().clone()),
+    );
+
+    let current = transaction_db
+        .fluent()
+        .select()
+        .by_id_in(JOB_COLLECTION_NAME)
+        .obj::<PlanJob>()
+        .one(job.id.to_string())
+        .await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let Some(mut current) = current.filter(|current| {
+        JobStatus::UNFINISHED.contains(&current.status) && current.lease_expires_at < now
+    }) else {
+        transaction.rollback().await?;
+        return Ok(false);
+    };
+
+    current.owner = *INSTANCE_ID;
+    current.lease_expires_at = now + JOB_LEASE_DURATION;
+
+    firestore_db
+        .fluent()
+        .update()
+        .fields(LEASE_FIELDS)
+        .in_col(JOB_COLLECTION_NAME)
+        .document_id(current.id.to_string())
+        .object(&current)
+        .add_to_transaction(&mut transaction)?;
+
+    // The commit fails if another instance claimed the job since it was read.
+    if let Err(e) = transaction.commit().await {
+        tracing::info!("Lost the claim on job {}: {e:?}", current.id);
+        return Ok(false);
+    }
+
+    *job = current;
+    Ok(true)
+}
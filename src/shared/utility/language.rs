@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+
+use whatlang::{Lang, Script};
+
+use crate::shared::structs::{
+    agent::Language,
+    config::{LanguageConfig, LanguageDetection},
+};
+
+/// Prompts in which the letters of a second script make up at least this share are considered
+/// mixed, e.g. English with Japanese place names.
+const MIXED_SCRIPT_SHARE: f64 = 0.2;
+
+/// The primary BCP-47 subtags of the languages the detector may recognize, by ISO 639-3 code.
+const PRIMARY_SUBTAGS: [(Lang, &str); 24] = [
+    (Lang::Eng, "en"),
+    (Lang::Cmn, "zh"),
+    (Lang::Jpn, "ja"),
+    (Lang::Kor, "ko"),
+    (Lang::Spa, "es"),
+    (Lang::Fra, "fr"),
+    (Lang::Deu, "de"),
+    (Lang::Ita, "it"),
+    (Lang::Por, "pt"),
+    (Lang::Rus, "ru"),
+    (Lang::Ukr, "uk"),
+    (Lang::Pol, "pl"),
+    (Lang::Nld, "nl"),
+    (Lang::Swe, "sv"),
+    (Lang::Tur, "tr"),
+    (Lang::Ara, "ar"),
+    (Lang::Heb, "he"),
+    (Lang::Hin, "hi"),
+    (Lang::Tha, "th"),
+    (Lang::Vie, "vi"),
+    (Lang::Ind, "id"),
+    (Lang::Tgl, "tl"),
+    (Lang::Ell, "el"),
+    (Lang::Ces, "cs"),
+];
+
+/// What the statistical detector made of a prompt.
+#[derive(Debug, Clone)]
+pub struct Detection {
+    /// The configured language of the prompt, if the detector is confident about it and it
+    /// matches exactly one configured language.
+    pub language: Option<Language>,
+    /// The ISO 639-3 code of the detected language, e.g. `jpn`.
+    pub detected: Option<&'static str>,
+    pub confidence: f64,
+}
+
+/// Detects the language of a prompt from its script and trigrams, without calling a model.
+/// Mixed or short prompts, languages that aren't configured, and languages configured in more
+/// than one variant, such as `zh-CN` and `zh-TW`, are left to the model.
+pub fn detect_language(
+    text: &str,
+    languages: &BTreeMap<Language, LanguageConfig>,
+    config: &LanguageDetection,
+) -> Detection {
+    let Some(info) = whatlang::detect(text) else {
+        return Detection {
+            language: None,
+            detected: None,
+            confidence: 0.0,
+        };
+    };
+
+    let is_confident = info.confidence() >= config.min_confidence && !is_mixed_script(text);
+
+    let candidates = PRIMARY_SUBTAGS
+        .iter()
+        .find(|(lang, _)| *lang == info.lang())
+        .map(|(_, primary)| {
+            languages
+                .keys()
+                .filter(|language| language.primary() == *primary)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let language = match candidates.as_slice() {
+        [language] if is_confident => Some((*language).clone()),
+        _ => None,
+    };
+
+    Detection {
+        language,
+        detected: Some(info.lang().code()),
+        confidence: info.confidence(),
+    }
+}
+
+/// Compares the scripts of the words of a prompt, so that accented Latin letters such as in
+/// "café" don't count as another script.
+fn is_mixed_script(text: &str) -> bool {
+    let mut letters = HashMap::new();
+
+    for word in text.split(|c: char| !c.is_alphabetic()) {
+        if let Some(script) = whatlang::detect_script(word) {
+            *letters.entry(script_family(script)).or_insert(0usize) += word.chars().count();
+        }
+    }
+
+    let total = letters.values().sum::<usize>();
+    let most_common = letters.values().max().copied().unwrap_or_default();
+
+    total > 0 && (total - most_common) as f64 / total as f64 >= MIXED_SCRIPT_SHARE
+}
+
+/// Kana and Han are written together in Japanese, so they count as a single script.
+fn script_family(script: Script) -> Script {
+    match script {
+        Script::Hiragana | Script::Katakana => Script::Mandarin,
+        script => script,
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use async_openai::config::OpenAIConfig;
+use async_openai::types::{CreateChatCompletionRequest, CreateChatCompletionResponse};
+use tokio::sync::Mutex;
+
+use crate::shared::structs::{
+    LLMClients,
+    agent::{
+        DEFAULT_SUBTASK_TIMEOUT, LanguageModel, Provider,
+        record::{CandidateScore, GenerationDump, GenerationStage, GenerationStatus, TokenUsage},
+    },
+    budget::PlanBudget,
+};
+use crate::shared::utility::cache::ResponseCache;
+
+/// Collects a dump for every LLM call made on behalf of a plan, and charges it to the plan's
+/// budget if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct GenerationLog {
+    dumps: Arc<Mutex<Vec<GenerationDump>>>,
+    budget: Option<Arc<PlanBudget>>,
+}
+
+impl GenerationLog {
+    pub fn new(budget: Option<Arc<PlanBudget>>) -> Self {
+        GenerationLog {
+            dumps: Arc::new(Mutex::new(Vec::new())),
+            budget,
+        }
+    }
+
+    pub async fn push(&self, dump: GenerationDump) {
+        if let Some(ref budget) = self.budget {
+            budget.charge(&dump);
+        }
+
+        self.dumps.lock().await.push(dump);
+    }
+
+    /// Attaches the score of a fan-out candidate to the dump of the model that wrote it.
+    pub async fn set_score(&self, model_name: &str, score: CandidateScore) {
+        let mut dumps = self.dumps.lock().await;
+
+        if let Some(dump) = dumps.iter_mut().find(|dump| {
+            dump.stage == Some(GenerationStage::FanOut) && dump.model_name == model_name
+        }) {
+            dump.score = Some(score);
+        }
+    }
+
+    pub async fn snapshot(&self) -> Vec<GenerationDump> {
+        self.dumps.lock().await.clone()
+    }
+}
+
+/// Sends a chat completion request within the provider's concurrency limit, and logs its
+/// usage, latency and outcome whether it succeeds or not. Responses are served from and stored
+/// in the response cache if it's enabled.
+pub async fn create_chat_completion(
+    client: &async_openai::Client<OpenAIConfig>,
+    provider: Provider,
+    request: CreateChatCompletionRequest,
+    stage: GenerationStage,
+    llm_clients: &LLMClients,
+    generation_log: &GenerationLog,
+) -> anyhow::Result<CreateChatCompletionResponse> {
+    let model_name = request.model.clone();
+
+    let mut dump = GenerationDump {
+        model: LanguageModel::from_name(&model_name).unwrap_or_default(),
+        is_final_result: stage == GenerationStage::Synthesis,
+        stage: Some(stage),
+        model_name: model_name.clone(),
+        provider: provider.to_string(),
+        ..Default::default()
+    };
+
+    let cache = match llm_clients.cache {
+        Some(ref cache) => Some((cache, ResponseCache::key(&request)?)),
+        None => None,
+    };
+
+    let cached_response = match cache {
+        Some((cache, ref key)) => cache.get(key).await,
+        None => None,
+    };
+
+    let mut started_at = std::time::Instant::now();
+
+    let outcome = if let Some(response) = cached_response {
+        dump.cached = true;
+        Ok(response)
+    } else if let Some((cache, _)) = cache
+        && cache.is_replay()
+    {
+        Err((
+            GenerationStatus::Failed,
+            format!("No cached response from {model_name} to replay."),
+        ))
+    } else {
+        let _permit = llm_clients.acquire(provider).await?;
+        started_at = std::time::Instant::now();
+
+        let response = tokio::time::timeout(
+            std::time::Duration::from_secs(DEFAULT_SUBTASK_TIMEOUT),
+            client.chat().create(request),
+        )
+        .await;
+
+        match response {
+            Ok(Ok(response)) => {
+                if let Some((cache, ref key)) = cache
+                    && let Err(e) = cache.put(key, &response).await
+                {
+                    tracing::warn!("Failed to cache the response from {model_name}: {e:?}");
+                }
+
+                Ok(response)
+            }
+            Ok(Err(e)) => Err((
+                GenerationStatus::Failed,
+                format!("Failed to get a response from {model_name}: {e:?}"),
+            )),
+            Err(_) => Err((
+                GenerationStatus::TimedOut,
+                format!("{model_name} timed out."),
+            )),
+        }
+    };
+
+    dump.latency_ms = started_at.elapsed().as_millis() as u64;
+
+    let result = match outcome {
+        Ok(response) => {
+            dump.content = response
+                .choices
+                .first()
+                .and_then(|choice| {
+                    choice.message.content.clone().or_else(|| {
+                        choice.message.tool_calls.as_ref().map(|calls| {
+                            calls
+                                .iter()
+                                .map(|call| call.function.arguments.clone())
+                                .collect::<Vec<_>>()
+                                .join("\n")
+                        })
+                    })
+                })
+                .unwrap_or_default();
+
+            if let Some(ref usage) = response.usage {
+                dump.usage = TokenUsage {
+                    prompt_tokens: usage.prompt_tokens,
+                    completion_tokens: usage.completion_tokens,
+                    reasoning_tokens: usage
+                        .completion_tokens_details
+                        .as_ref()
+                        .and_then(|details| details.reasoning_tokens)
+                        .unwrap_or_default(),
+                };
+            }
+
+            Ok(response)
+        }
+        Err((status, error_msg)) => {
+            dump.status = status;
+            dump.error = Some(error_msg.clone());
+            Err(anyhow::anyhow!("{}", error_msg))
+        }
+    };
+
+    tracing::debug!(
+        "{stage:?} call to {model_name} via {provider} finished in {} ms (cached: {}).",
+        dump.latency_ms,
+        dump.cached
+    );
+
+    generation_log.push(dump).await;
+
+    result
+}
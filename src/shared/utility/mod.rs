@@ -4,7 +4,22 @@ use async_openai::types::{
 };
 use serenity::all::ImageHash;
 
+pub mod budget;
+pub mod cache;
+pub mod calendar;
+pub mod climate;
+pub mod context_window;
+pub mod currency;
+pub mod estimate;
 pub mod google_maps;
+pub mod itinerary;
+pub mod job;
+pub mod language;
+pub mod llm;
+pub mod ranking;
+pub mod synthesis;
+pub mod tools;
+pub mod validation;
 
 pub fn build_one_shot_messages(
     system_prompt: &str,
@@ -28,3 +43,58 @@ pub fn create_avatar_url(id: u64, image_hash: ImageHash) -> String {
     let hash = image_hash.to_string();
     format!("https://cdn.discordapp.com/avatars/{id}/{hash}.webp?size=1024")
 }
+
+/// Splits text into messages of at most `limit` characters, between sections where possible and
+/// otherwise between lines, so that markdown isn't cut in half. Only lines longer than the limit
+/// are split in the middle.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut messages = vec![];
+    let mut current = String::new();
+
+    for section in text.split("\n\n") {
+        if section.chars().count() > limit {
+            for (index, line) in section.lines().enumerate() {
+                let separator = if index == 0 { "\n\n" } else { "\n" };
+                push_part(&mut messages, &mut current, line, separator, limit);
+            }
+        } else {
+            push_part(&mut messages, &mut current, section, "\n\n", limit);
+        }
+    }
+
+    if !current.trim().is_empty() {
+        messages.push(current);
+    }
+
+    messages
+}
+
+fn push_part(
+    messages: &mut Vec<String>,
+    current: &mut String,
+    part: &str,
+    separator: &str,
+    limit: usize,
+) {
+    let length = current.chars().count() + separator.chars().count() + part.chars().count();
+    if !current.is_empty() && length > limit {
+        messages.push(std::mem::take(current));
+    }
+
+    if part.chars().count() > limit {
+        let chars = part.chars().collect::<Vec<_>>();
+        let mut chunks = chars
+            .chunks(limit)
+            .map(|chunk| chunk.iter().collect::<String>());
+        if let Some(last) = chunks.next_back() {
+            messages.extend(chunks);
+            *current = last;
+        }
+        return;
+    }
+
+    if !current.is_empty() {
+        current.push_str(separator);
+    }
+    current.push_str(part);
+}
@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+
+use async_openai::types::{
+    CreateChatCompletionRequestArgs, CreateChatCompletionResponse, FinishReason, ResponseFormat,
+    ResponseFormatJsonSchema,
+};
+use minijinja::context;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::shared::TEMPERATURE_LOW;
+use crate::shared::structs::{
+    LLMClients,
+    agent::{
+        LanguageModel, Provider,
+        record::{CandidateScore, GenerationStage},
+    },
+    config::{Ranking, templates::PromptTemplates},
+};
+use crate::shared::utility::build_one_shot_messages;
+use crate::shared::utility::llm::{GenerationLog, create_chat_completion};
+
+/// Candidates are cut to this many characters before they are shown to the judge.
+const MAX_JUDGED_CANDIDATE_LENGTH: usize = 4000;
+
+/// Openings of responses in which the model declined the task.
+const REFUSAL_MARKERS: [&str; 7] = [
+    "i'm sorry",
+    "i am sorry",
+    "i cannot",
+    "i can't",
+    "as an ai",
+    "抱歉",
+    "申し訳",
+];
+
+/// A fan-out response waiting to be ranked.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub model: LanguageModel,
+    pub model_name: String,
+    pub content: String,
+    /// The model stopped because it ran out of tokens.
+    pub truncated: bool,
+    pub score: CandidateScore,
+}
+
+#[derive(Deserialize, Debug)]
+struct JudgeScores {
+    scores: Vec<JudgeScore>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JudgeScore {
+    candidate: usize,
+    score: f64,
+}
+
+impl Candidate {
+    pub fn new(
+        model: LanguageModel,
+        model_name: String,
+        response: CreateChatCompletionResponse,
+    ) -> Self {
+        let choice = response.choices.into_iter().next();
+
+        Candidate {
+            model,
+            model_name,
+            truncated: choice
+                .as_ref()
+                .is_some_and(|c| c.finish_reason == Some(FinishReason::Length)),
+            content: choice.and_then(|c| c.message.content).unwrap_or_default(),
+            score: CandidateScore::default(),
+        }
+    }
+
+    /// The score candidates are ordered by, from 0 to 1.
+    fn rank(&self) -> f64 {
+        self.score
+            .judge
+            .map_or(self.score.heuristic, |score| score / 10.0)
+    }
+}
+
+/// Scores the candidates with heuristics, and with the judge model if one is configured, then
+/// returns the best `top_k` of them. The scores of all candidates are recorded in the log.
+pub async fn rank_candidates(
+    mut candidates: Vec<Candidate>,
+    task_prompt: &str,
+    ranking: &Ranking,
+    templates: &PromptTemplates,
+    llm_clients: &LLMClients,
+    generation_log: &GenerationLog,
+) -> Vec<Candidate> {
+    let mut seen = HashSet::new();
+
+    for candidate in candidates.iter_mut() {
+        let duplicate = !seen.insert(candidate.content.trim().to_string());
+        candidate.score.heuristic = if duplicate {
+            0.0
+        } else {
+            heuristic_score(
+                &candidate.content,
+                candidate.truncated,
+                ranking.min_candidate_length,
+            )
+        };
+    }
+
+    let viable = candidates
+        .iter()
+        .filter(|c| c.score.heuristic > 0.0)
+        .count();
+
+    if let Some(ref judge_model) = ranking.judge_model
+        && viable > ranking.top_k
+    {
+        let result = judge_candidates(
+            &candidates,
+            task_prompt,
+            judge_model,
+            templates,
+            llm_clients,
+            generation_log,
+        )
+        .await;
+
+        match result {
+            Ok(scores) => {
+                for (index, candidate) in candidates.iter_mut().enumerate() {
+                    if candidate.score.heuristic > 0.0 {
+                        candidate.score.judge = scores.get(&index).copied();
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Falling back to heuristic ranking: {e:?}"),
+        }
+    }
+
+    candidates.sort_by(|a, b| b.rank().total_cmp(&a.rank()));
+
+    for (index, candidate) in candidates.iter_mut().enumerate() {
+        candidate.score.selected = index < ranking.top_k && candidate.score.heuristic > 0.0;
+        generation_log
+            .set_score(&candidate.model_name, candidate.score)
+            .await;
+    }
+
+    tracing::info!(
+        "Kept {} of {} candidates for aggregation.",
+        candidates.iter().filter(|c| c.score.selected).count(),
+        candidates.len()
+    );
+
+    candidates.retain(|c| c.score.selected);
+    candidates
+}
+
+/// Rejects empty responses and refusals with a score of 0, and favors responses that are long
+/// enough, structured and complete.
+fn heuristic_score(content: &str, truncated: bool, min_length: usize) -> f64 {
+    let content = content.trim();
+    if content.is_empty() {
+        return 0.0;
+    }
+
+    let opening = content.chars().take(50).collect::<String>().to_lowercase();
+    if REFUSAL_MARKERS
+        .iter()
+        .any(|marker| opening.starts_with(marker))
+    {
+        return 0.0;
+    }
+
+    let length = content.chars().count() as f64;
+    let mut score = (length / min_length.max(1) as f64).min(1.0) * 0.8;
+
+    let is_structured = content.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with('#') || line.starts_with("- ") || line.starts_with("* ")
+    });
+
+    if is_structured {
+        score += 0.2;
+    }
+
+    if truncated {
+        score *= 0.5;
+    }
+
+    score
+}
+
+/// Asks the judge model to score the viable candidates. Returns the scores by candidate index.
+async fn judge_candidates(
+    candidates: &[Candidate],
+    task_prompt: &str,
+    judge_model: &str,
+    templates: &PromptTemplates,
+    llm_clients: &LLMClients,
+    generation_log: &GenerationLog,
+) -> anyhow::Result<HashMap<usize, f64>> {
+    let numbered_candidates = candidates
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.score.heuristic > 0.0)
+        .map(|(index, c)| {
+            let content = c
+                .content
+                .chars()
+                .take(MAX_JUDGED_CANDIDATE_LENGTH)
+                .collect::<String>();
+            format!("### Candidate {index}\n{content}")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let judge_prompt = templates.render(
+        "ranking.judge_prompt",
+        context! {
+            task => task_prompt,
+            candidates => numbered_candidates,
+        },
+    )?;
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(judge_model)
+        .temperature(TEMPERATURE_LOW)
+        .messages(build_one_shot_messages(
+            "You are a strict and impartial judge of travel planning responses.",
+            &judge_prompt,
+        )?)
+        .response_format(ResponseFormat::JsonSchema {
+            json_schema: ResponseFormatJsonSchema {
+                description: Some("Score each candidate response.".into()),
+                name: "score_candidates".into(),
+                schema: Some(json!({
+                    "type": "object",
+                    "properties": {
+                        "scores": {
+                            "type": "array",
+                            "items": {
+                                "type": "object",
+                                "properties": {
+                                    "candidate": {
+                                        "type": "integer",
+                                        "description": "The number of the candidate."
+                                    },
+                                    "score": {
+                                        "type": "number",
+                                        "description": "The score of the candidate from 0 to 10."
+                                    }
+                                },
+                                "required": ["candidate", "score"],
+                                "additionalProperties": false
+                            }
+                        }
+                    },
+                    "required": ["scores"],
+                    "additionalProperties": false
+                })),
+                strict: Some(true),
+            },
+        })
+        .build()?;
+
+    let provider = Provider::for_model(judge_model);
+
+    let response = create_chat_completion(
+        &llm_clients.client(provider),
+        provider,
+        request,
+        GenerationStage::Ranking,
+        llm_clients,
+        generation_log,
+    )
+    .await?;
+
+    let content = response
+        .choices
+        .first()
+        .and_then(|c| c.message.content.clone())
+        .unwrap_or_default();
+
+    let scores = serde_json::from_str::<JudgeScores>(&content)?
+        .scores
+        .into_iter()
+        .map(|s| (s.candidate, s.score.clamp(0.0, 10.0)))
+        .collect();
+
+    Ok(scores)
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use async_openai::types::{
+    ChatCompletionRequestMessage, ChatCompletionRequestUserMessageArgs,
+    CreateChatCompletionRequest, CreateChatCompletionRequestArgs, ResponseFormat,
+    ResponseFormatJsonSchema,
+};
+use minijinja::context;
+use serde_json::json;
+
+use crate::shared::TEMPERATURE_LOW;
+use crate::shared::structs::agent::record::Message;
+use crate::shared::structs::agent::{Context, Language};
+use crate::shared::structs::config::templates::PromptTemplates;
+use crate::shared::utility::context_window::{PromptFitter, context_window_of};
+
+/// Fills the results of the subtasks into the synthesis prompt.
+pub fn render_synthesis_prompt(
+    templates: &PromptTemplates,
+    language: &Language,
+    results: Vec<Context>,
+) -> anyhow::Result<String> {
+    let results_by_task = results
+        .iter()
+        .map(|c| (c.task_id.clone(), c))
+        .collect::<HashMap<_, _>>();
+
+    templates.render(
+        &templates.name(language, "synthesis"),
+        context! {
+            results => serde_json::to_string_pretty(&results_by_task)?,
+            contexts => results,
+        },
+    )
+}
+
+/// Shrinks the results of the subtasks so that the synthesis prompt fits into the model's
+/// context window along with the conversation so far.
+pub async fn fit_synthesis_results(
+    results: Vec<Context>,
+    prompt: &str,
+    messages: &[Message],
+    model: &str,
+    fitter: &PromptFitter,
+) -> Vec<Context> {
+    let conversation = messages
+        .iter()
+        .filter_map(|m| m.content.extract_content().ok())
+        .collect::<Vec<_>>();
+
+    let fixed_parts = conversation
+        .iter()
+        .map(String::as_str)
+        .chain(std::iter::once(prompt))
+        .collect::<Vec<_>>();
+
+    let available_tokens = fitter.available_tokens(context_window_of(model), &fixed_parts);
+    fitter.fit_contexts(results, available_tokens).await
+}
+
+/// Builds the request that synthesizes the final response from the conversation so far, which
+/// the model answers with an `Itinerary`.
+pub fn build_synthesis_request(
+    model: &str,
+    mut messages: Vec<ChatCompletionRequestMessage>,
+    synthesis_prompt: &str,
+) -> anyhow::Result<CreateChatCompletionRequest> {
+    messages.push(ChatCompletionRequestMessage::User(
+        ChatCompletionRequestUserMessageArgs::default()
+            .content(synthesis_prompt)
+            .build()?,
+    ));
+
+    let request = CreateChatCompletionRequestArgs::default()
+        .model(model)
+        .temperature(TEMPERATURE_LOW)
+        .messages(messages)
+        .response_format(ResponseFormat::JsonSchema { json_schema: ResponseFormatJsonSchema {
+            description: Some("Synthesize the results of subtasks into the final response.".into()),
+            name: "synthesize_tasks".into(),
+            schema: Some(json!({
+                "type": "object",
+                "properties": {
+                    "narrative": {
+                        "type": "string",
+                        "description": "Everything of the response to the user's request that isn't a stop of the schedule, such as the introduction, tips and a summary, in markdown."
+                    },
+                    "days": {
+                        "type": "array",
+                        "description": "The schedule of each day of the trip in order, or an empty array if the request isn't for an itinerary.",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "day": {
+                                    "type": "integer",
+                                    "description": "The day of the trip, starting at 1."
+                                },
+                                "date": {
+                                    "type": ["string", "null"],
+                                    "description": "The date of the day in the YYYY-MM-DD format, or null if the user didn't give dates."
+                                },
+                                "title": {
+                                    "type": "string",
+                                    "description": "A short theme of the day, e.g. the area it's spent in."
+                                },
+                                "stops": {
+                                    "type": "array",
+                                    "items": {
+                                        "type": "object",
+                                        "properties": {
+                                            "start": {
+                                                "type": ["string", "null"],
+                                                "description": "When the stop starts in the HH:MM format, or null if it isn't scheduled to the time."
+                                            },
+                                            "end": {
+                                                "type": ["string", "null"],
+                                                "description": "When the stop ends in the HH:MM format, or null if it isn't scheduled to the time."
+                                            },
+                                            "place": {
+                                                "type": "string",
+                                                "description": "The name of the place with its city, so that it can be found on a map."
+                                            },
+                                            "category": {
+                                                "type": "string",
+                                                "enum": ["food", "culture", "nature", "shopping", "entertainment", "lodging", "other"]
+                                            },
+                                            "notes": {
+                                                "type": "string",
+                                                "description": "What to do, eat or see there and anything else worth knowing, in markdown."
+                                            },
+                                            "transit": {
+                                                "type": ["object", "null"],
+                                                "description": "How to get here from the previous stop of the day, or null for the first stop.",
+                                                "properties": {
+                                                    "by": {
+                                                        "type": "string",
+                                                        "enum": ["drive_or_taxi", "public_transport"]
+                                                    },
+                                                    "minutes": {
+                                                        "type": "integer",
+                                                        "description": "How long the trip takes."
+                                                    },
+                                                    "notes": {
+                                                        "type": "string",
+                                                        "description": "The lines, stations or fares of the trip, if known."
+                                                    }
+                                                },
+                                                "required": ["by", "minutes", "notes"],
+                                                "additionalProperties": false
+                                            }
+                                        },
+                                        "required": ["start", "end", "place", "category", "notes", "transit"],
+                                        "additionalProperties": false
+                                    }
+                                }
+                            },
+                            "required": ["day", "date", "title", "stops"],
+                            "additionalProperties": false
+                        }
+                    }
+                },
+                "required": ["narrative", "days"],
+                "additionalProperties": false
+            })),
+            strict: Some(true) } })
+        .build()?;
+
+    Ok(request)
+}
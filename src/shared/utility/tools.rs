@@ -0,0 +1,583 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use async_openai::types::{
+    ChatChoice, ChatCompletionMessageToolCall, ChatCompletionRequestMessage,
+    ChatCompletionRequestToolMessageArgs, ChatCompletionRequestToolMessageContent,
+    ChatCompletionTool, ChatCompletionToolArgs, ChatCompletionToolType,
+    CreateChatCompletionRequestArgs, FunctionObjectArgs, ResponseFormat,
+};
+use dashmap::DashMap;
+use serde_json::json;
+use tokio::task::JoinSet;
+
+use crate::shared::structs::LLMClients;
+use crate::shared::structs::agent::record::GenerationStage;
+use crate::shared::structs::agent::{Language, Provider};
+use crate::shared::structs::calendar::{CalendarInfo, CalendarRequest};
+use crate::shared::structs::climate::{ClimateNormals, ClimateRequest};
+use crate::shared::structs::currency::{ConversionRequest, ConvertedAmount};
+use crate::shared::structs::google_maps::{
+    AreaCentrality, CentralityRequest, RouteWithDuration, TransferPlan,
+};
+use crate::shared::utility::calendar::get_calendar_info;
+use crate::shared::utility::climate::get_climate_normals;
+use crate::shared::utility::currency::ExchangeRates;
+use crate::shared::utility::google_maps::{
+    get_country_code, get_latitude_and_longitude, get_location, get_travel_minutes, get_travel_time,
+};
+use crate::shared::utility::llm::{GenerationLog, create_chat_completion};
+use crate::shared::{
+    CENTRALITY_TIMEOUT, MAX_CALENDAR_DATES, MAX_CENTRALITY_AREAS, MAX_CENTRALITY_PLACES,
+    MAX_CENTRALITY_ROUTES, MAX_CLIMATE_PLACES, TEMPERATURE_MEDIUM,
+};
+
+pub const GET_TRANSIT_TIME: &str = "get_transit_time";
+pub const SCORE_CENTRALITY: &str = "score_centrality";
+pub const CONVERT_CURRENCY: &str = "convert_currency";
+pub const GET_CLIMATE_NORMALS: &str = "get_climate_normals";
+pub const GET_CALENDAR_INFO: &str = "get_calendar_info";
+
+/// The tools agents can be given in the config.
+pub const TOOL_NAMES: [&str; 5] = [
+    GET_TRANSIT_TIME,
+    SCORE_CENTRALITY,
+    CONVERT_CURRENCY,
+    GET_CLIMATE_NORMALS,
+    GET_CALENDAR_INFO,
+];
+
+/// What the tools need to answer a call.
+#[derive(Clone)]
+pub struct ToolContext {
+    /// The language of geocoding and directions results.
+    pub response_language: ::google_maps::Language,
+    pub google_maps_client: Arc<::google_maps::Client>,
+    /// The language amounts of money are formatted for.
+    pub language: Language,
+    /// The currency amounts are converted to unless the model asks for another one.
+    pub currency: String,
+    pub exchange_rates: Arc<ExchangeRates>,
+}
+
+/// The definition of a tool that's sent to the model.
+pub fn create_tool(name: &str) -> anyhow::Result<ChatCompletionTool> {
+    match name {
+        GET_TRANSIT_TIME => create_get_transit_time_tool(),
+        SCORE_CENTRALITY => create_score_centrality_tool(),
+        CONVERT_CURRENCY => create_convert_currency_tool(),
+        GET_CLIMATE_NORMALS => create_get_climate_normals_tool(),
+        GET_CALENDAR_INFO => create_get_calendar_info_tool(),
+        _ => {
+            let error_msg = format!("There's no tool named `{name}`.");
+            tracing::error!("{}", &error_msg);
+            Err(anyhow::anyhow!("{}", error_msg))
+        }
+    }
+}
+
+/// Runs a tool call and returns its result as JSON.
+pub async fn call_tool(
+    tool_call: &ChatCompletionMessageToolCall,
+    context: &ToolContext,
+) -> anyhow::Result<String> {
+    match tool_call.function.name.as_str() {
+        GET_TRANSIT_TIME => {
+            let results = get_transit_time(&tool_call.function.arguments, context).await?;
+            Ok(serde_json::to_string_pretty(&results)?)
+        }
+        SCORE_CENTRALITY => {
+            let results = score_centrality(&tool_call.function.arguments, context).await?;
+            Ok(serde_json::to_string_pretty(&results)?)
+        }
+        CONVERT_CURRENCY => {
+            let results = convert_currency(&tool_call.function.arguments, context).await?;
+            Ok(serde_json::to_string_pretty(&results)?)
+        }
+        GET_CLIMATE_NORMALS => {
+            let results = climate_normals(&tool_call.function.arguments, context).await?;
+            Ok(serde_json::to_string_pretty(&results)?)
+        }
+        GET_CALENDAR_INFO => {
+            let results = calendar_info(&tool_call.function.arguments, context).await?;
+            Ok(serde_json::to_string_pretty(&results)?)
+        }
+        name => {
+            let error_msg = format!("The model called the unknown tool `{name}`.");
+            tracing::error!("{}", &error_msg);
+            Err(anyhow::anyhow!("{}", error_msg))
+        }
+    }
+}
+
+/// Sends the result of a tool call back to the aggregation model, which either answers or calls
+/// another tool.
+pub async fn build_tool_turn_message(
+    message_histories: &mut Vec<ChatCompletionRequestMessage>,
+    tool_call_id: String,
+    results: String,
+    tools: &[ChatCompletionTool],
+    model: &str,
+    response_format: Option<ResponseFormat>,
+    llm_clients: Arc<LLMClients>,
+    generation_log: &GenerationLog,
+) -> anyhow::Result<ChatChoice> {
+    message_histories.push(ChatCompletionRequestMessage::Tool(
+        ChatCompletionRequestToolMessageArgs::default()
+            .content(ChatCompletionRequestToolMessageContent::Text(results))
+            .tool_call_id(tool_call_id)
+            .build()?,
+    ));
+
+    let mut request = CreateChatCompletionRequestArgs::default();
+    request
+        .model(model)
+        .temperature(TEMPERATURE_MEDIUM)
+        .messages(message_histories.clone());
+
+    if !tools.is_empty() {
+        request.tools(tools.to_vec());
+    }
+
+    if let Some(response_format) = response_format {
+        request.response_format(response_format);
+    }
+
+    let provider = Provider::for_model(model);
+    let client = llm_clients.client(provider);
+
+    let response = create_chat_completion(
+        &client,
+        provider,
+        request.build()?,
+        GenerationStage::ToolTurn,
+        &llm_clients,
+        generation_log,
+    )
+    .await?;
+
+    response.choices.first().cloned().ok_or(anyhow::anyhow!(
+        "Failed to generate the message of the tool turn."
+    ))
+}
+
+async fn get_transit_time(
+    arguments: &str,
+    context: &ToolContext,
+) -> anyhow::Result<Vec<RouteWithDuration>> {
+    let transfer_plan = serde_json::from_str::<TransferPlan>(arguments)?;
+
+    tracing::info!("Transfer Plan: {transfer_plan:?}");
+
+    let mut routes = Vec::with_capacity(transfer_plan.routes.len());
+
+    let lat_lngs = Arc::new(DashMap::new());
+
+    for route in transfer_plan.routes.iter() {
+        let (from, to) = get_latitude_and_longitude(
+            route,
+            context.response_language,
+            lat_lngs.clone(),
+            context.google_maps_client.clone(),
+        )
+        .await?;
+        routes.push((from, to, route.by));
+    }
+
+    let routes = routes
+        .into_iter()
+        .zip(transfer_plan.routes)
+        .collect::<Vec<_>>();
+
+    let mut results = Vec::with_capacity(routes.len());
+
+    for (values, route) in routes.into_iter() {
+        let (duration, fare, alternative) = get_travel_time(
+            values,
+            context.response_language,
+            context.google_maps_client.clone(),
+        )
+        .await?;
+        results.push(RouteWithDuration {
+            from: route.from,
+            to: route.to,
+            by: route.by,
+            duration,
+            fare,
+            alternative,
+        });
+    }
+
+    tracing::info!("Direction UI results: {results:?}");
+
+    Ok(results)
+}
+
+/// Scores how central each candidate area is by the travel times from it to the places of the
+/// itinerary. The most central areas come first.
+async fn score_centrality(
+    arguments: &str,
+    context: &ToolContext,
+) -> anyhow::Result<Vec<AreaCentrality>> {
+    let mut request = serde_json::from_str::<CentralityRequest>(arguments)?;
+    request.areas.truncate(MAX_CENTRALITY_AREAS);
+    request
+        .places
+        .truncate(MAX_CENTRALITY_PLACES.min(MAX_CENTRALITY_ROUTES / request.areas.len().max(1)));
+
+    tracing::info!("Centrality request: {request:?}");
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(CENTRALITY_TIMEOUT);
+    let client = &context.google_maps_client;
+
+    // Places that can't be geocoded are treated like places without a route.
+    let lat_lngs = DashMap::new();
+    let mut locations = HashMap::new();
+    for name in request.areas.iter().chain(request.places.iter()) {
+        if locations.contains_key(name) {
+            continue;
+        }
+
+        let location = tokio::time::timeout_at(
+            deadline,
+            get_location(name, context.response_language, &lat_lngs, client),
+        )
+        .await;
+        let location = match location {
+            Ok(Ok(location)) => location,
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to locate {name}: {e:?}");
+                None
+            }
+            Err(_) => None,
+        };
+
+        locations.insert(name.clone(), location);
+    }
+
+    let locations = Arc::new(locations);
+    let places = Arc::new(request.places);
+    let mut join_set = JoinSet::new();
+
+    for area in request.areas.into_iter() {
+        let context = context.clone();
+        let locations = locations.clone();
+        let places = places.clone();
+        let by = request.by;
+
+        join_set.spawn(async move {
+            let client = &context.google_maps_client;
+            let from = locations.get(&area).copied().flatten();
+
+            let mut durations = vec![];
+            let mut unreachable = vec![];
+
+            for place in places.iter() {
+                let (Some(from), Some(to)) = (from, locations.get(place).copied().flatten()) else {
+                    unreachable.push(place.clone());
+                    continue;
+                };
+
+                let minutes = tokio::time::timeout_at(
+                    deadline,
+                    get_travel_minutes((from, to, by), context.response_language, client),
+                )
+                .await;
+
+                match minutes {
+                    Ok(Ok(Some(minutes))) => durations.push((place.clone(), minutes)),
+                    Ok(Ok(None)) => unreachable.push(place.clone()),
+                    Ok(Err(e)) => {
+                        tracing::warn!("Failed to get travel time from {area} to {place}: {e:?}");
+                        unreachable.push(place.clone());
+                    }
+                    Err(_) => {
+                        tracing::warn!("Timed out getting travel time from {area} to {place}.");
+                        unreachable.push(place.clone());
+                    }
+                }
+            }
+
+            let farthest = durations
+                .iter()
+                .max_by_key(|(_, minutes)| *minutes)
+                .cloned();
+
+            AreaCentrality {
+                average_minutes: (!durations.is_empty()).then(|| {
+                    durations
+                        .iter()
+                        .map(|(_, minutes)| *minutes as f64)
+                        .sum::<f64>()
+                        / durations.len() as f64
+                }),
+                longest_minutes: farthest.as_ref().map(|(_, minutes)| *minutes),
+                farthest_place: farthest.map(|(place, _)| place),
+                area,
+                unreachable,
+            }
+        });
+    }
+
+    let mut results = join_set.join_all().await;
+
+    // Areas with fewer unreachable places come first, then the ones with shorter trips.
+    results.sort_by(|a, b| {
+        a.unreachable.len().cmp(&b.unreachable.len()).then(
+            a.average_minutes
+                .unwrap_or(f64::MAX)
+                .total_cmp(&b.average_minutes.unwrap_or(f64::MAX)),
+        )
+    });
+
+    tracing::info!("Centrality results: {results:?}");
+
+    Ok(results)
+}
+
+async fn convert_currency(
+    arguments: &str,
+    context: &ToolContext,
+) -> anyhow::Result<Vec<ConvertedAmount>> {
+    let request = serde_json::from_str::<ConversionRequest>(arguments)?;
+
+    tracing::info!("Conversion request: {request:?}");
+
+    let results = context
+        .exchange_rates
+        .convert(&request.conversions, &context.currency, &context.language)
+        .await;
+
+    tracing::info!("Conversion results: {results:?}");
+
+    Ok(results)
+}
+
+/// Looks up the climate normals of each place by its geocoded coordinates.
+async fn climate_normals(
+    arguments: &str,
+    context: &ToolContext,
+) -> anyhow::Result<Vec<ClimateNormals>> {
+    let mut request = serde_json::from_str::<ClimateRequest>(arguments)?;
+    request.places.truncate(MAX_CLIMATE_PLACES);
+
+    tracing::info!("Climate request: {request:?}");
+
+    let lat_lngs = DashMap::new();
+    let mut results = Vec::with_capacity(request.places.len());
+
+    for place in request.places.into_iter() {
+        let location = get_location(
+            &place,
+            context.response_language,
+            &lat_lngs,
+            &context.google_maps_client,
+        )
+        .await?;
+        results.push(get_climate_normals(place, location, &request.months));
+    }
+
+    tracing::info!("Climate results: {results:?}");
+
+    Ok(results)
+}
+
+/// Looks up the holidays and usual closures of the dates in the country of the location.
+async fn calendar_info(arguments: &str, context: &ToolContext) -> anyhow::Result<CalendarInfo> {
+    let mut request = serde_json::from_str::<CalendarRequest>(arguments)?;
+    request.dates.truncate(MAX_CALENDAR_DATES);
+
+    tracing::info!("Calendar request: {request:?}");
+
+    let country_code = get_country_code(
+        &request.location,
+        context.response_language,
+        &context.google_maps_client,
+    )
+    .await?;
+    let result = get_calendar_info(request.location, country_code, &request.dates);
+
+    tracing::info!("Calendar result: {result:?}");
+
+    Ok(result)
+}
+
+fn create_get_transit_time_tool() -> anyhow::Result<ChatCompletionTool> {
+    Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(FunctionObjectArgs::default()
+                    .name(GET_TRANSIT_TIME)
+                    .description("Get transit time needed to navigate from one place to another, and the fare where it's known.")
+                    .strict(true)
+                    .parameters(json!({
+                        "type": "object",
+                        "properties": {
+                            "routes": {
+                                "type": "array",
+                                "description": "A list of routes covered in the itinerary.",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "from": {
+                                            "type": "string",
+                                            "description": "The origin or start point of a route. Make sure that it's a valid and correct place name."
+                                        },
+                                        "to": {
+                                            "type": "string",
+                                            "description": "The destination, goal, or end point of a route. Make sure that it's a valid and correct place name."
+                                        },
+                                        "by": {
+                                            "type": "string",
+                                            "description": "The preferred type of transit to take.",
+                                            "enum": ["drive_or_taxi", "public_transport"]
+                                        }
+                                    },
+                                    "required": ["from", "to", "by"],
+                                    "additionalProperties": false
+                                }
+                            }
+                        },
+                        "required": ["routes"],
+                        "additionalProperties": false
+                    }))
+                    .build()?)
+                .build()?)
+}
+
+fn create_score_centrality_tool() -> anyhow::Result<ChatCompletionTool> {
+    Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(FunctionObjectArgs::default()
+                    .name(SCORE_CENTRALITY)
+                    .description("Score how central each candidate area to stay in is, by the travel times from it to the places of the itinerary. The most central areas come first.")
+                    .strict(true)
+                    .parameters(json!({
+                        "type": "object",
+                        "properties": {
+                            "areas": {
+                                "type": "array",
+                                "description": format!("Up to {MAX_CENTRALITY_AREAS} neighbourhoods or districts to compare. Make sure that they're valid and correct place names."),
+                                "items": {
+                                    "type": "string"
+                                }
+                            },
+                            "places": {
+                                "type": "array",
+                                "description": format!("Up to {MAX_CENTRALITY_PLACES} places the itinerary visits, such as restaurants, sights and parks."),
+                                "items": {
+                                    "type": "string"
+                                }
+                            },
+                            "by": {
+                                "type": "string",
+                                "description": "The preferred type of transit to take.",
+                                "enum": ["drive_or_taxi", "public_transport"]
+                            }
+                        },
+                        "required": ["areas", "places", "by"],
+                        "additionalProperties": false
+                    }))
+                    .build()?)
+                .build()?)
+}
+
+fn create_convert_currency_tool() -> anyhow::Result<ChatCompletionTool> {
+    Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(FunctionObjectArgs::default()
+                    .name(CONVERT_CURRENCY)
+                    .description("Convert amounts of money to the traveller's currency or another one with the latest exchange rates, and format them for the traveller.")
+                    .strict(true)
+                    .parameters(json!({
+                        "type": "object",
+                        "properties": {
+                            "conversions": {
+                                "type": "array",
+                                "description": "The amounts to convert, such as prices, fares and fees.",
+                                "items": {
+                                    "type": "object",
+                                    "properties": {
+                                        "amount": {
+                                            "type": "number",
+                                            "description": "The amount in the original currency."
+                                        },
+                                        "from": {
+                                            "type": "string",
+                                            "description": "The ISO 4217 code of the original currency, e.g. JPY."
+                                        },
+                                        "to": {
+                                            "type": ["string", "null"],
+                                            "description": "The ISO 4217 code of the currency to convert to. Use null for the traveller's currency."
+                                        }
+                                    },
+                                    "required": ["amount", "from", "to"],
+                                    "additionalProperties": false
+                                }
+                            }
+                        },
+                        "required": ["conversions"],
+                        "additionalProperties": false
+                    }))
+                    .build()?)
+                .build()?)
+}
+
+fn create_get_climate_normals_tool() -> anyhow::Result<ChatCompletionTool> {
+    Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(FunctionObjectArgs::default()
+                    .name(GET_CLIMATE_NORMALS)
+                    .description("Get the average high and low temperatures, rain days and daylight hours of places in the months of the trip. Recommend indoor alternatives for months with many rain days or extreme temperatures.")
+                    .strict(true)
+                    .parameters(json!({
+                        "type": "object",
+                        "properties": {
+                            "places": {
+                                "type": "array",
+                                "description": format!("Up to {MAX_CLIMATE_PLACES} cities or areas the itinerary visits. Make sure that they're valid and correct place names."),
+                                "items": {
+                                    "type": "string"
+                                }
+                            },
+                            "months": {
+                                "type": "array",
+                                "description": "The months of the trip from 1 for January to 12 for December.",
+                                "items": {
+                                    "type": "integer"
+                                }
+                            }
+                        },
+                        "required": ["places", "months"],
+                        "additionalProperties": false
+                    }))
+                    .build()?)
+                .build()?)
+}
+
+fn create_get_calendar_info_tool() -> anyhow::Result<ChatCompletionTool> {
+    Ok(ChatCompletionToolArgs::default()
+                .r#type(ChatCompletionToolType::Function)
+                .function(FunctionObjectArgs::default()
+                    .name(GET_CALENDAR_INFO)
+                    .description("Get the day of the week, public holidays and venues that are usually closed on each date of the trip at a location. Don't schedule visits to venues on days they're closed, and expect crowds on holidays.")
+                    .strict(true)
+                    .parameters(json!({
+                        "type": "object",
+                        "properties": {
+                            "dates": {
+                                "type": "array",
+                                "description": format!("Up to {MAX_CALENDAR_DATES} dates of the trip in the YYYY-MM-DD format."),
+                                "items": {
+                                    "type": "string"
+                                }
+                            },
+                            "location": {
+                                "type": "string",
+                                "description": "The city or country of the trip. Make sure that it's a valid and correct place name."
+                            }
+                        },
+                        "required": ["dates", "location"],
+                        "additionalProperties": false
+                    }))
+                    .build()?)
+                .build()?)
+}
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use chrono::{Datelike, NaiveDate, NaiveTime, Timelike};
+
+use crate::shared::MAX_ITINERARY_STOPS;
+use crate::shared::structs::AppState;
+use crate::shared::structs::agent::Language;
+use crate::shared::structs::agent::itinerary::{Itinerary, ItineraryStop};
+use crate::shared::structs::google_maps::OpeningHours;
+use crate::shared::structs::validation::{ConflictKind, ScheduleConflict};
+use crate::shared::utility::google_maps::{get_opening_hours, get_travel_minutes};
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// Checks the stops of a synthesized itinerary against the opening hours of the places and the
+/// travel times between them. Stops without times, dates or locations are only checked as far as
+/// they can be, so the stops should be located first. Without a date, a stop only conflicts with
+/// the opening hours if the place is closed at that time on every day of the week.
+pub async fn find_schedule_conflicts(
+    itinerary: &Itinerary,
+    language: &Language,
+    app_state: &AppState,
+) -> Vec<ScheduleConflict> {
+    let config = app_state.config();
+    let language_config = config.language(language);
+    let response_language = language_config.geocoding_language();
+    let mut opening_hours = HashMap::new();
+    let mut conflicts = vec![];
+    let mut checked_stops = 0;
+
+    for day in itinerary.days.iter() {
+        let weekday = day
+            .date
+            .as_deref()
+            .and_then(|date| NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok())
+            .map(|date| date.weekday().num_days_from_sunday());
+
+        for (index, stop) in day.stops.iter().enumerate() {
+            if checked_stops >= MAX_ITINERARY_STOPS {
+                tracing::warn!("Only the first {MAX_ITINERARY_STOPS} stops were validated.");
+                return conflicts;
+            }
+            checked_stops += 1;
+
+            if let Some((from, to)) = visit_minutes(stop) {
+                if !opening_hours.contains_key(&stop.place) {
+                    let hours = get_opening_hours(
+                        &stop.place,
+                        &language_config.geocoding,
+                        &app_state.http_client,
+                        app_state.settings.google_api_key.expose(),
+                    )
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::warn!("Failed to get the opening hours of {}: {e:?}", stop.place);
+                        None
+                    });
+                    opening_hours.insert(stop.place.clone(), hours);
+                }
+
+                if let Some(Some(hours)) = opening_hours.get(&stop.place)
+                    && !hours.periods.is_empty()
+                {
+                    let closed = match weekday {
+                        Some(weekday) => !hours.is_open(weekday, (from, to)),
+                        None => (0..7).all(|weekday| !hours.is_open(weekday, (from, to))),
+                    };
+
+                    if closed {
+                        conflicts.push(closed_conflict(day.day, stop, weekday, hours));
+                    }
+                }
+            }
+
+            let Some(previous) = index.checked_sub(1).map(|index| &day.stops[index]) else {
+                continue;
+            };
+
+            let (Some(departure), Some(arrival), Some(from), Some(to), Some(transit)) = (
+                previous.end.as_deref().and_then(parse_minutes),
+                stop.start.as_deref().and_then(parse_minutes),
+                previous.location,
+                stop.location,
+                stop.transit.as_ref(),
+            ) else {
+                continue;
+            };
+
+            if arrival < departure {
+                continue;
+            }
+
+            let minutes = match get_travel_minutes(
+                (from, to, transit.by),
+                response_language,
+                &app_state.google_maps_client,
+            )
+            .await
+            {
+                Ok(Some(minutes)) => minutes,
+                Ok(None) => continue,
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to get travel time from {} to {}: {e:?}",
+                        previous.place,
+                        stop.place
+                    );
+                    continue;
+                }
+            };
+
+            let scheduled = (arrival - departure) as i64;
+            if minutes > scheduled + config.validation.transit_tolerance {
+                conflicts.push(ScheduleConflict {
+                    day: day.day,
+                    place: stop.place.clone(),
+                    kind: ConflictKind::NotEnoughTransitTime,
+                    description: format!(
+                        "Getting from {} to {} takes about {minutes} minutes, but only {scheduled} minutes are scheduled.",
+                        previous.place, stop.place
+                    ),
+                });
+            }
+        }
+    }
+
+    conflicts
+}
+
+/// Lists the conflicts for the repair prompt.
+pub fn describe_conflicts(conflicts: &[ScheduleConflict]) -> String {
+    conflicts
+        .iter()
+        .map(|conflict| format!("- Day {}: {}", conflict.day, conflict.description))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn closed_conflict(
+    day: u32,
+    stop: &ItineraryStop,
+    weekday: Option<u32>,
+    hours: &OpeningHours,
+) -> ScheduleConflict {
+    let when = match weekday {
+        Some(weekday) => format!("on {}", WEEKDAYS[weekday as usize % 7]),
+        None => "on any day".to_string(),
+    };
+
+    let opening_hours = if hours.weekday_descriptions.is_empty() {
+        String::new()
+    } else {
+        format!(
+            " Its opening hours are: {}.",
+            hours.weekday_descriptions.join("; ")
+        )
+    };
+
+    ScheduleConflict {
+        day,
+        place: stop.place.clone(),
+        kind: ConflictKind::Closed,
+        description: format!(
+            "{} isn't open from {} to {} {when}.{opening_hours}",
+            stop.place,
+            stop.start.as_deref().unwrap_or_default(),
+            stop.end.as_deref().unwrap_or_default(),
+        ),
+    }
+}
+
+/// The start and end of a stop in minutes since midnight, if both are given and the stop
+/// doesn't run past midnight.
+fn visit_minutes(stop: &ItineraryStop) -> Option<(u32, u32)> {
+    let from = parse_minutes(stop.start.as_deref()?)?;
+    let to = parse_minutes(stop.end.as_deref()?)?;
+    (from <= to).then_some((from, to))
+}
+
+fn parse_minutes(time: &str) -> Option<u32> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .ok()
+        .map(|time| time.hour() * 60 + time.minute())
+}